@@ -0,0 +1,280 @@
+use anyhow::Result;
+use clap::{ArgEnum, Parser, Subcommand};
+use std::net::SocketAddr;
+
+const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:8787";
+
+pub struct Args {
+    pub command: Command,
+    pub format: OutputFormat,
+    /// Name of the crop layout to scan with
+    pub layout: Option<String>,
+    /// Show details such as OCR confidence and deal scores
+    pub verbose: bool,
+    /// Only report scans with an item that hasn't been seen this session
+    pub new_only: bool,
+    /// Use the cached item list, however old it is
+    pub no_refresh: bool,
+    /// Only use the public API endpoints, ignoring the login
+    pub no_login: bool,
+    /// Look up prices for this platform instead of the logged in one
+    pub platform: Option<String>,
+}
+
+pub enum Command {
+    /// Wait for the hotkey and scan the relic reward screen
+    Watch,
+    /// Scan every screenshot saved to the directory, or to `screenshot_dir`
+    /// from the config without one
+    WatchDir(Option<String>),
+    /// Scan the screenshots (from stdin for `-`) and exit
+    Scan(Vec<String>),
+    /// Look up the price of a single item by (fuzzy) name
+    Price(String),
+    /// Look up prices of items typed at a prompt, with name completion
+    PricePrompt,
+    /// Show the cached items closest to the text, as if OCR had read it
+    Match(String),
+    /// Scan whenever a `POST /scan` request comes in, instead of on a hotkey
+    Daemon { listen: SocketAddr },
+    /// Log in to warframe.market, replacing the stored token
+    Login,
+    /// Remove cached screenshots and force an item list refresh, plus the
+    /// extracted tessdata with `all`
+    ClearCache { all: bool },
+    /// Save a copy of the screenshot (or a new capture) with the crops outlined
+    Preview(Option<String>),
+    /// Find the reward row on the screenshot (or a new capture) and save a
+    /// layout for it, with `interactive` after letting the user adjust it
+    Calibrate {
+        path: Option<String>,
+        interactive: bool,
+    },
+    /// Write the scan history to a spreadsheet
+    Export(String),
+    /// Play the scan sound and a beep, to check that audio works
+    TestBeep,
+    /// List the displays `monitor` in the config can select
+    Monitors,
+    /// Bind the next key pressed to scanning, in the config file
+    SetHotkey,
+    /// Use the profile from now on, without `--profile`
+    SwitchProfile(String),
+    /// Print the color of a pixel in an image, and whether it counts as text
+    SamplePixel { path: String, x: u32, y: u32 },
+}
+
+#[derive(Clone, Copy, PartialEq, ArgEnum)]
+pub enum OutputFormat {
+    /// Colored, human readable output
+    Text,
+    /// A pretty-printed JSON object per scan
+    Json,
+    /// A single-line JSON object per scan, flushed immediately
+    #[clap(name = "jsonl")]
+    JsonLines,
+}
+
+impl OutputFormat {
+    pub fn is_machine_readable(&self) -> bool {
+        *self != OutputFormat::Text
+    }
+}
+
+/// A command-line tool to see what the best relic reward is, based on
+/// warframe.market platinum prices
+#[derive(Parser)]
+#[clap(version)]
+struct Cli {
+    /// How to print scan results
+    #[clap(long, arg_enum, default_value = "text", global = true)]
+    format: OutputFormat,
+    /// Name of the crop layout to scan with
+    #[clap(long, global = true)]
+    layout: Option<String>,
+    /// Show details such as OCR confidence and deal scores
+    #[clap(short, long, global = true)]
+    verbose: bool,
+    /// Only report scans with an item that hasn't been seen this session
+    #[clap(long, global = true)]
+    new_only: bool,
+    /// Use the cached item list, however old it is
+    #[clap(long, global = true)]
+    no_refresh: bool,
+    /// Only use the public API endpoints, ignoring the login
+    #[clap(long, global = true)]
+    no_login: bool,
+    /// Look up prices for this platform instead of the logged in one
+    #[clap(long, global = true)]
+    platform: Option<String>,
+    /// Read the settings from this file [env: WFM_CLI_CONFIG]
+    #[clap(long, global = true, value_name = "FILE")]
+    config: Option<String>,
+    /// Keep everything in this directory [env: WFM_CLI_DATA_DIR]
+    #[clap(long, global = true, value_name = "DIR")]
+    data_dir: Option<String>,
+    /// Use this profile from the config, `default` for the top level account [env: WFM_CLI_PROFILE]
+    #[clap(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+    /// What to do, `watch` if left out
+    #[clap(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Wait for the hotkey and scan the relic reward screen
+    Watch,
+    /// Scan every new screenshot in a directory, instead of on a hotkey
+    WatchDir {
+        /// Where the screenshots are saved, `screenshot_dir` from the config
+        /// if left out
+        dir: Option<String>,
+    },
+    /// Scan screenshots and exit
+    Scan {
+        /// Paths of the screenshots, or - to read one from stdin
+        #[clap(required = true)]
+        images: Vec<String>,
+    },
+    /// Look up the price of an item by (fuzzy) name, or of items typed at a
+    /// prompt without one
+    Price { name: Vec<String> },
+    /// Show the cached items closest to the text, as if OCR had read it
+    Match {
+        #[clap(required = true)]
+        text: Vec<String>,
+    },
+    /// Scan whenever a `POST /scan` request comes in, instead of on a hotkey
+    Daemon {
+        #[clap(long, default_value = DEFAULT_LISTEN_ADDR)]
+        listen: SocketAddr,
+    },
+    /// Log in to warframe.market, replacing the stored token
+    Login,
+    /// Change the config file
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Switch between the accounts in `profiles` of the config
+    Profile {
+        #[clap(subcommand)]
+        command: ProfileCommand,
+    },
+    /// Remove cached screenshots and force an item list refresh
+    ClearCache {
+        /// Remove the extracted tessdata as well
+        #[clap(long)]
+        all: bool,
+    },
+    /// Save a copy of the screenshot (or a new capture) with the crops outlined
+    Preview { image: Option<String> },
+    /// Find the reward row on the screenshot (or a new capture) and save a
+    /// layout for it
+    Calibrate {
+        /// Adjust the layout at a prompt before saving it
+        #[clap(short, long)]
+        interactive: bool,
+        image: Option<String>,
+    },
+    /// Write the scan history to a spreadsheet
+    Export { file: String },
+    /// Play the scan sound and a beep, to check that audio works
+    TestBeep,
+    /// List the displays `monitor` in the config can select
+    Monitors,
+    /// Print the color of a pixel in an image, and whether it counts as text
+    SamplePixel {
+        /// Coordinates of the pixel as x,y
+        #[clap(parse(try_from_str = parse_coords))]
+        coords: (u32, u32),
+        image: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Bind the next key pressed to scanning
+    SetHotkey,
+}
+
+#[derive(Subcommand)]
+enum ProfileCommand {
+    /// Use the profile until switching again, `default` for the top level account
+    Switch { name: String },
+}
+
+/// Sets the path flags in the environment, so it has to run before any
+/// other threads are started
+pub fn parse() -> Result<Args> {
+    let cli = Cli::parse();
+    if let Some(platform) = &cli.platform {
+        crate::config::check_platform(platform)?;
+    }
+    // every path is looked up through these, so the flags just stand in for them
+    if let Some(path) = &cli.config {
+        std::env::set_var(crate::util::CONFIG_FILE_ENV, path);
+    }
+    if let Some(dir) = &cli.data_dir {
+        std::env::set_var(crate::util::DATA_DIR_ENV, dir);
+    }
+    if let Some(profile) = &cli.profile {
+        std::env::set_var(crate::config::PROFILE_ENV, profile);
+    }
+
+    let command = match cli.command.unwrap_or(CliCommand::Watch) {
+        CliCommand::Watch => Command::Watch,
+        CliCommand::WatchDir { dir } => Command::WatchDir(dir),
+        CliCommand::Scan { images } => Command::Scan(images),
+        CliCommand::Price { name } if name.is_empty() => Command::PricePrompt,
+        CliCommand::Price { name } => Command::Price(name.join(" ")),
+        CliCommand::Match { text } => Command::Match(text.join(" ")),
+        CliCommand::Daemon { listen } => Command::Daemon { listen },
+        CliCommand::Login => Command::Login,
+        CliCommand::Config {
+            command: ConfigCommand::SetHotkey,
+        } => Command::SetHotkey,
+        CliCommand::Profile {
+            command: ProfileCommand::Switch { name },
+        } => Command::SwitchProfile(name),
+        CliCommand::ClearCache { all } => Command::ClearCache { all },
+        CliCommand::Preview { image } => Command::Preview(image),
+        CliCommand::Calibrate { interactive, image } => Command::Calibrate {
+            path: image,
+            interactive,
+        },
+        CliCommand::Export { file } => Command::Export(file),
+        CliCommand::TestBeep => Command::TestBeep,
+        CliCommand::Monitors => Command::Monitors,
+        CliCommand::SamplePixel {
+            coords: (x, y),
+            image,
+        } => Command::SamplePixel { path: image, x, y },
+    };
+
+    Ok(Args {
+        command,
+        format: cli.format,
+        layout: cli.layout,
+        verbose: cli.verbose,
+        new_only: cli.new_only,
+        no_refresh: cli.no_refresh,
+        no_login: cli.no_login,
+        platform: cli.platform,
+    })
+}
+
+fn parse_coords(s: &str) -> std::result::Result<(u32, u32), String> {
+    coords(s).ok_or_else(|| format!("expected coordinates as x,y, not '{}'", s))
+}
+
+fn coords(s: &str) -> Option<(u32, u32)> {
+    let mut parts = s.split(',');
+    let x = parts.next()?.trim().parse().ok()?;
+    let y = parts.next()?.trim().parse().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((x, y))
+}
@@ -0,0 +1,409 @@
+use crate::ocr::{Layout, OUTLINE_WIDTH};
+use anyhow::Result;
+use image::{DynamicImage, GenericImageView, GrayImage};
+
+/// Width and top-left corner of the divider bar below the reward slots on a
+/// 1080p screen, which the slot positions of the default layout are relative to
+const BAR_WIDTH: u32 = 968;
+const BAR_ORIGIN: [u32; 2] = [476, 476];
+/// Columns are averaged in groups of this many, the bar is wide enough to be
+/// found at a quarter of the horizontal resolution
+const DOWNSCALE: u32 = 4;
+/// The reference is matched at every step between these scales (relative to
+/// 1080p), then once more in finer steps around the best one
+const MIN_SCALE: f32 = 0.5;
+const MAX_SCALE: f32 = 2.0;
+const COARSE_STEP: f32 = 0.05;
+const FINE_STEP: f32 = 0.01;
+/// The bar is somewhere in the middle of the screen, searching only there
+/// avoids the similar line below the screen title
+const SEARCH_ROWS: (f32, f32) = (0.3, 0.7);
+/// Correlation below which the screenshot most likely isn't the reward screen
+const MIN_SCORE: f32 = 0.5;
+/// Pixels a nudge moves or resizes the crops by when no amount is given
+const DEFAULT_NUDGE: i32 = 5;
+
+/// Where the reward row was found
+pub struct Calibration {
+    pub layout: Layout,
+    /// UI scale the row was found at, relative to 1080p
+    pub scale: f32,
+    /// Normalized correlation of the best match, from -1 to 1
+    pub score: f32,
+}
+
+/// Locates the reward row by matching a reference of the divider bar below
+/// the slots against the screenshot at different scales, and derives the slot
+/// positions from where (and at what size) it was found.
+///
+/// The reference is a bright bar with darker margins, one row high so the
+/// thickness of the bar doesn't matter. Since it's drawn rather than cut from
+/// a screenshot, it scales without resampling artifacts.
+pub fn calibrate(img: &DynamicImage) -> Result<Calibration> {
+    let columns = downscale_columns(&img.to_luma8());
+    let sums = Integral::new(&columns, |x| x);
+    let squares = Integral::new(&columns, |x| x * x);
+
+    let coarse_steps = ((MAX_SCALE - MIN_SCALE) / COARSE_STEP).round() as i32;
+    let mut best = (0..=coarse_steps)
+        .map(|i| MIN_SCALE + COARSE_STEP * i as f32)
+        .filter_map(|scale| match_bar(&sums, &squares, scale))
+        .fold(None, better);
+
+    if let Some(coarse) = best {
+        let fine_steps = (COARSE_STEP / FINE_STEP).round() as i32;
+        best = (-fine_steps..=fine_steps)
+            .map(|i| coarse.scale + FINE_STEP * i as f32)
+            .filter_map(|scale| match_bar(&sums, &squares, scale))
+            .fold(best, better);
+    }
+
+    let found = match best {
+        Some(x) if x.score >= MIN_SCORE => x,
+        _ => anyhow::bail!(
+            "Couldn't find the reward row, make sure the screenshot shows the reward screen"
+        ),
+    };
+
+    let reference = Layout::default();
+    let scaled = |value: u32| (value as f32 * found.scale).round() as u32;
+    let offset = |value: u32, reference: u32, origin: u32| {
+        (origin as f32 + (value as f32 - reference as f32) * found.scale)
+            .round()
+            .max(0.0) as u32
+    };
+
+    Ok(Calibration {
+        layout: Layout {
+            crop_size: [
+                scaled(reference.crop_size[0]),
+                scaled(reference.crop_size[1]),
+            ],
+            crop_coords: reference
+                .crop_coords
+                .iter()
+                .map(|coords| {
+                    [
+                        offset(coords[0], BAR_ORIGIN[0], found.x),
+                        offset(coords[1], BAR_ORIGIN[1], found.y),
+                    ]
+                })
+                .collect(),
+            resolution: Some([img.width(), img.height()]),
+        },
+        scale: found.scale,
+        score: found.score,
+    })
+}
+
+/// A change to the crops typed while calibrating interactively
+#[derive(Debug, PartialEq)]
+pub enum Nudge {
+    /// Moves every crop right and down, or left and up when negative
+    Move(i32, i32),
+    /// Makes every crop wider and taller around its center
+    Resize(i32, i32),
+    /// Moves every crop further away from the middle of the row, by this much
+    /// per slot
+    Spread(i32),
+}
+
+impl Nudge {
+    /// Parses `<direction> [pixels]`, such as `left 10`. The directions are
+    /// up, down, left, right, wider, narrower, taller, shorter, spread and closer.
+    pub fn parse(line: &str) -> Option<Nudge> {
+        let mut words = line.split_whitespace();
+        let direction = words.next()?;
+        let amount = match words.next() {
+            Some(x) => x.parse::<i32>().ok()?,
+            None => DEFAULT_NUDGE,
+        };
+        if words.next().is_some() {
+            return None;
+        }
+
+        Some(match direction {
+            "up" => Nudge::Move(0, -amount),
+            "down" => Nudge::Move(0, amount),
+            "left" => Nudge::Move(-amount, 0),
+            "right" => Nudge::Move(amount, 0),
+            "wider" => Nudge::Resize(amount, 0),
+            "narrower" => Nudge::Resize(-amount, 0),
+            "taller" => Nudge::Resize(0, amount),
+            "shorter" => Nudge::Resize(0, -amount),
+            "spread" => Nudge::Spread(amount),
+            "closer" => Nudge::Spread(-amount),
+            _ => return None,
+        })
+    }
+
+    pub fn apply(&self, layout: &Layout) -> Layout {
+        let shift = |value: u32, by: f64| (value as f64 + by).round().max(0.0) as u32;
+        let mut layout = layout.clone();
+        let slots = layout.crop_coords.len();
+
+        match *self {
+            Nudge::Move(x, y) => {
+                for coords in layout.crop_coords.iter_mut() {
+                    *coords = [shift(coords[0], x as f64), shift(coords[1], y as f64)];
+                }
+            }
+            Nudge::Resize(width, height) => {
+                let old_size = layout.crop_size;
+                // no smaller than the outline, so the preview still shows the crop
+                layout.crop_size = [
+                    shift(old_size[0], width as f64).max(OUTLINE_WIDTH),
+                    shift(old_size[1], height as f64).max(OUTLINE_WIDTH),
+                ];
+                // keep the centers where they are
+                let grown_x = layout.crop_size[0] as f64 - old_size[0] as f64;
+                let grown_y = layout.crop_size[1] as f64 - old_size[1] as f64;
+                for coords in layout.crop_coords.iter_mut() {
+                    *coords = [shift(coords[0], -grown_x / 2.0), shift(coords[1], -grown_y / 2.0)];
+                }
+            }
+            Nudge::Spread(by) => {
+                for (idx, coords) in layout.crop_coords.iter_mut().enumerate() {
+                    let from_middle = idx as f64 - (slots as f64 - 1.0) / 2.0;
+                    coords[0] = shift(coords[0], from_middle * by as f64);
+                }
+            }
+        }
+
+        layout
+    }
+}
+
+#[derive(Clone, Copy)]
+struct BarMatch {
+    scale: f32,
+    score: f32,
+    /// Left end of the bar, in screenshot pixels
+    x: u32,
+    y: u32,
+}
+
+fn better(best: Option<BarMatch>, candidate: BarMatch) -> Option<BarMatch> {
+    match best {
+        Some(x) if x.score >= candidate.score => Some(x),
+        _ => Some(candidate),
+    }
+}
+
+/// The best position of the reference bar at `scale`, by normalized
+/// correlation. The reference only has two values, so the correlation only
+/// needs the sums over the window and over the bar, which the integral images
+/// give at constant cost.
+fn match_bar(sums: &Integral, squares: &Integral, scale: f32) -> Option<BarMatch> {
+    let bar_width = (BAR_WIDTH as f32 * scale / DOWNSCALE as f32).round() as usize;
+    let margin = (bar_width / 4).max(1);
+    let window = bar_width + 2 * margin;
+    if bar_width == 0 || window > sums.width {
+        return None;
+    }
+
+    // share of the window covered by the bar, and the variance of the reference
+    let n = window as f64;
+    let bar_share = bar_width as f64 / n;
+    let reference_var = bar_share - bar_share * bar_share;
+
+    let first_row = (sums.height as f32 * SEARCH_ROWS.0) as usize;
+    let last_row = (sums.height as f32 * SEARCH_ROWS.1) as usize;
+
+    let mut best: Option<BarMatch> = None;
+    for y in first_row..last_row.min(sums.height) {
+        for x in 0..=sums.width - window {
+            let mean = sums.row_sum(x, y, window) / n;
+            let var = squares.row_sum(x, y, window) / n - mean * mean;
+            if var <= f64::EPSILON {
+                continue;
+            }
+
+            let covariance = sums.row_sum(x + margin, y, bar_width) / n - bar_share * mean;
+            let score = (covariance / (reference_var * var).sqrt()) as f32;
+            if best.map_or(true, |x| score > x.score) {
+                best = Some(BarMatch {
+                    scale,
+                    score,
+                    x: ((x + margin) as u32) * DOWNSCALE,
+                    y: y as u32,
+                });
+            }
+        }
+    }
+
+    best
+}
+
+/// Averages every `DOWNSCALE` neighbouring columns, returning rows of values
+fn downscale_columns(img: &GrayImage) -> Vec<Vec<f64>> {
+    let width = img.width() / DOWNSCALE;
+    (0..img.height())
+        .map(|y| {
+            (0..width)
+                .map(|x| {
+                    (0..DOWNSCALE)
+                        .map(|i| img.get_pixel(x * DOWNSCALE + i, y)[0] as f64)
+                        .sum::<f64>()
+                        / DOWNSCALE as f64
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Running sums of every row, for summing any run of a row at constant cost
+struct Integral {
+    sums: Vec<Vec<f64>>,
+    width: usize,
+    height: usize,
+}
+
+impl Integral {
+    fn new(values: &[Vec<f64>], f: impl Fn(f64) -> f64) -> Integral {
+        let sums = values
+            .iter()
+            .map(|row| {
+                let mut sum = 0.0;
+                let mut sums = vec![0.0];
+                for &value in row {
+                    sum += f(value);
+                    sums.push(sum);
+                }
+                sums
+            })
+            .collect();
+
+        Integral {
+            sums,
+            width: values.first().map_or(0, |x| x.len()),
+            height: values.len(),
+        }
+    }
+
+    /// Sum of the `len` values of row `y` starting at `x`
+    fn row_sum(&self, x: usize, y: usize, len: usize) -> f64 {
+        self.sums[y][x + len] - self.sums[y][x]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Luma;
+
+    #[test]
+    fn test_nudge() {
+        assert_eq!(Nudge::parse("left 10"), Some(Nudge::Move(-10, 0)));
+        assert_eq!(Nudge::parse("up"), Some(Nudge::Move(0, -DEFAULT_NUDGE)));
+        assert_eq!(Nudge::parse("closer 2"), Some(Nudge::Spread(-2)));
+        assert_eq!(Nudge::parse("left ten"), None);
+        assert_eq!(Nudge::parse("sideways 3"), None);
+
+        let layout = Layout {
+            crop_size: [100, 20],
+            crop_coords: vec![[100, 50], [200, 50], [300, 50]],
+            resolution: None,
+        };
+        let moved = Nudge::Move(-150, 5).apply(&layout);
+        assert_eq!(moved.crop_coords, vec![[0, 55], [50, 55], [150, 55]]);
+
+        let resized = Nudge::Resize(10, -4).apply(&layout);
+        assert_eq!(resized.crop_size, [110, 16]);
+        assert_eq!(resized.crop_coords[0], [95, 52]);
+
+        let shrunk = Nudge::Resize(-200, -200).apply(&layout);
+        assert_eq!(shrunk.crop_size, [OUTLINE_WIDTH, OUTLINE_WIDTH]);
+
+        let spread = Nudge::Spread(10).apply(&layout);
+        assert_eq!(spread.crop_coords, vec![[90, 50], [200, 50], [310, 50]]);
+    }
+
+    fn background(width: u32, height: u32) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| {
+            Luma([(60 + (x * 7 + y * 13) % 31) as u8])
+        })
+    }
+
+    /// A noisy background with the title line, the reward names and the
+    /// divider bar where they are on the reward screen, at `scale` of 1080p
+    fn reward_screen(width: u32, height: u32, scale: f32) -> DynamicImage {
+        let at = |value: u32| (value as f32 * scale) as u32;
+        let mut img = background(width, height);
+
+        let mut fill = |left: u32, top: u32, right: u32, bottom: u32, value: u8, stripes: bool| {
+            for y in top..bottom {
+                for x in left..right {
+                    if !stripes || (x / 3) % 2 == 1 {
+                        img.put_pixel(x, y, Luma([value]));
+                    }
+                }
+            }
+        };
+        fill(0, at(100), at(930), at(104), 200, false);
+        for left in [497, 742, 984, 1210].iter() {
+            fill(at(*left), at(436), at(left + 200), at(456), 230, true);
+        }
+        let bar_thickness = at(4).max(1);
+        fill(
+            at(BAR_ORIGIN[0]),
+            at(BAR_ORIGIN[1]),
+            at(BAR_ORIGIN[0]) + at(BAR_WIDTH),
+            at(BAR_ORIGIN[1]) + bar_thickness,
+            140,
+            false,
+        );
+
+        DynamicImage::ImageLuma8(img)
+    }
+
+    fn assert_near(layout: &Layout, expected: &Layout) {
+        for (coords, expected) in layout.crop_coords.iter().zip(expected.crop_coords.iter()) {
+            let distance = |a: u32, b: u32| (a as i64 - b as i64).abs();
+            assert!(
+                distance(coords[0], expected[0]) <= 8,
+                "{:?} {:?}",
+                coords,
+                expected
+            );
+            assert!(
+                distance(coords[1], expected[1]) <= 8,
+                "{:?} {:?}",
+                coords,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_calibrate_1080p() {
+        let calibration = calibrate(&reward_screen(1920, 1080, 1.0)).unwrap();
+        assert!((calibration.scale - 1.0).abs() < 0.02);
+        assert_eq!(calibration.layout.crop_size, Layout::default().crop_size);
+        assert_near(&calibration.layout, &Layout::default());
+    }
+
+    #[test]
+    fn test_calibrate_scaled() {
+        let scale = 2.0 / 3.0;
+        let calibration = calibrate(&reward_screen(1280, 720, scale)).unwrap();
+        assert!((calibration.scale - scale).abs() < 0.02);
+
+        let expected = Layout {
+            crop_coords: Layout::default()
+                .crop_coords
+                .iter()
+                .map(|x| [(x[0] as f32 * scale) as u32, (x[1] as f32 * scale) as u32])
+                .collect(),
+            ..Layout::default()
+        };
+        assert_near(&calibration.layout, &expected);
+        assert_eq!(calibration.layout.resolution, Some([1280, 720]));
+    }
+
+    #[test]
+    fn test_calibrate_no_bar() {
+        let img = DynamicImage::ImageLuma8(background(1920, 1080));
+        assert!(calibrate(&img).is_err());
+    }
+}
@@ -0,0 +1,465 @@
+use crate::config::Config;
+use anyhow::Result;
+use image::{DynamicImage, GenericImageView};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How the screen is captured, ignored on Windows
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureBackend {
+    /// The portal on Wayland (when `WAYLAND_DISPLAY` is set), X11 otherwise
+    Auto,
+    X11,
+    /// The xdg-desktop-portal Screenshot interface, which Wayland compositors
+    /// offer instead of letting programs read the screen
+    Portal,
+}
+
+impl Default for CaptureBackend {
+    fn default() -> Self {
+        CaptureBackend::Auto
+    }
+}
+
+#[cfg(not(windows))]
+impl CaptureBackend {
+    /// The backend `Auto` stands for in this session
+    fn resolve(self) -> CaptureBackend {
+        match self {
+            CaptureBackend::Auto if std::env::var_os("WAYLAND_DISPLAY").is_some() => {
+                CaptureBackend::Portal
+            }
+            CaptureBackend::Auto => CaptureBackend::X11,
+            x => x,
+        }
+    }
+}
+
+/// Where a window is on the desktop, which can start left of or above the
+/// primary display
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct WindowRect {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+/// A display of the desktop, as listed by `monitors`
+pub struct Monitor {
+    pub name: String,
+    rect: WindowRect,
+    pub primary: bool,
+}
+
+impl fmt::Display for Monitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}x{}+{}+{}",
+            self.name, self.rect.width, self.rect.height, self.rect.x, self.rect.y
+        )?;
+        if self.primary {
+            write!(f, " (primary)")?;
+        }
+        Ok(())
+    }
+}
+
+/// The index of the monitor `selection` refers to, either by its index or
+/// by its name (ignoring case)
+fn select_monitor(monitors: &[Monitor], selection: &str) -> Result<usize> {
+    let idx = match selection.trim().parse::<usize>() {
+        Ok(idx) if idx < monitors.len() => Some(idx),
+        Ok(_) => None,
+        Err(_) => monitors
+            .iter()
+            .position(|x| x.name.eq_ignore_ascii_case(selection.trim())),
+    };
+
+    match idx {
+        Some(x) => Ok(x),
+        None => anyhow::bail!(
+            "There is no monitor '{}', expected an index or one of: {}",
+            selection,
+            monitors
+                .iter()
+                .map(|x| x.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Captures the screen, or only the `capture_window` or `monitor` with either
+/// set, without leaving the screenshot behind
+#[cfg(not(windows))]
+pub async fn capture_image(config: &Config) -> Result<DynamicImage> {
+    match config.capture_backend.resolve() {
+        CaptureBackend::Portal if config.capture_window.is_some() || config.monitor.is_some() => {
+            anyhow::bail!(
+                "capture_window and monitor don't work with the screenshot portal, set capture_backend to x11 to use them"
+            )
+        }
+        CaptureBackend::Portal => capture_portal().await,
+        _ => {
+            let img = capture_x11()?;
+            // the screenshot covers the whole X11 screen, which starts at 0,0
+            if let Some(title) = &config.capture_window {
+                return crop_window(&img, find_window(title)?, (0, 0));
+            }
+            match &config.monitor {
+                Some(selection) => {
+                    let monitors = monitors()?;
+                    let monitor = &monitors[select_monitor(&monitors, selection)?];
+                    crop_window(&img, monitor.rect, (0, 0))
+                }
+                None => Ok(img),
+            }
+        }
+    }
+}
+
+/// Captures the primary display (which the game runs on in fullscreen), the
+/// `monitor` with that set, or only the `capture_window` with that set, on
+/// whichever display it is
+#[cfg(windows)]
+pub async fn capture_image(config: &Config) -> Result<DynamicImage> {
+    let window = match &config.capture_window {
+        Some(title) => Some(find_window(title)?),
+        None => None,
+    };
+    let screen = match (window, &config.monitor) {
+        (None, Some(selection)) => {
+            let mut screens = all_screens()?;
+            let monitors: Vec<Monitor> = screens.iter().map(screen_monitor).collect();
+            screens.swap_remove(select_monitor(&monitors, selection)?)
+        }
+        (None, None) => match all_screens()?.into_iter().find(|x| x.display_info.is_primary) {
+            Some(x) => x,
+            None => anyhow::bail!("Windows reports no primary display, set monitor to pick one"),
+        },
+        (Some(window), _) => match screenshots::Screen::from_point(window.x, window.y) {
+            Some(x) => x,
+            None => anyhow::bail!("Failed to find the display at {},{}", window.x, window.y),
+        },
+    };
+    let capture = match screen.capture() {
+        Some(x) => x,
+        None => anyhow::bail!("Failed to capture display {}", screen.display_info.id),
+    };
+    let img = image::load_from_memory(capture.buffer())?;
+
+    match window {
+        Some(window) => crop_window(
+            &img,
+            window,
+            (screen.display_info.x, screen.display_info.y),
+        ),
+        None => Ok(img),
+    }
+}
+
+/// The part of `img` covered by the window, where `origin` is the position of
+/// the top left corner of `img` on the desktop
+fn crop_window(img: &DynamicImage, window: WindowRect, origin: (i32, i32)) -> Result<DynamicImage> {
+    let left = (window.x - origin.0).max(0) as u32;
+    let top = (window.y - origin.1).max(0) as u32;
+    let right = ((window.x - origin.0) as i64 + window.width as i64).min(img.width() as i64);
+    let bottom = ((window.y - origin.1) as i64 + window.height as i64).min(img.height() as i64);
+    if right <= left as i64 || bottom <= top as i64 {
+        anyhow::bail!("The window is outside of the captured screen");
+    }
+
+    Ok(img.crop_imm(left, top, right as u32 - left, bottom as u32 - top))
+}
+
+/// Reads the whole X11 screen straight from the server
+#[cfg(target_os = "linux")]
+fn capture_x11() -> Result<DynamicImage> {
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{ConnectionExt, ImageFormat, ImageOrder};
+
+    let (conn, screen_num) = x11rb::connect(None)?;
+    let screen = &conn.setup().roots[screen_num];
+    let (width, height) = (screen.width_in_pixels, screen.height_in_pixels);
+    let reply = conn
+        .get_image(ImageFormat::Z_PIXMAP, screen.root, 0, 0, width, height, !0)?
+        .reply()?;
+
+    let bits_per_pixel = conn
+        .setup()
+        .pixmap_formats
+        .iter()
+        .find(|x| x.depth == reply.depth)
+        .map(|x| x.bits_per_pixel);
+    if bits_per_pixel != Some(32) || conn.setup().image_byte_order != ImageOrder::LSB_FIRST {
+        anyhow::bail!(
+            "Unsupported X11 screen format (depth {}), set capture_backend to portal",
+            reply.depth
+        );
+    }
+
+    match bgrx_to_image(reply.data, width as u32, height as u32) {
+        Some(x) => Ok(x),
+        None => anyhow::bail!("The X11 server sent an incomplete screenshot"),
+    }
+}
+
+/// Turns 32 bit pixels in blue, green, red, unused order into an image
+#[cfg(any(target_os = "linux", test))]
+fn bgrx_to_image(mut data: Vec<u8>, width: u32, height: u32) -> Option<DynamicImage> {
+    if data.len() != width as usize * height as usize * 4 {
+        return None;
+    }
+    for pixel in data.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+        pixel[3] = 255;
+    }
+
+    image::RgbaImage::from_raw(width, height, data).map(DynamicImage::ImageRgba8)
+}
+
+#[cfg(all(not(windows), not(target_os = "linux")))]
+fn capture_x11() -> Result<DynamicImage> {
+    let mut screenshot_path = crate::util::screenshot_path()?;
+    screenshot_path.push(format!("{}.png", crate::util::unix_timestamp()?));
+    screenshot_rs::screenshot_window(screenshot_path.to_string_lossy().to_string());
+
+    let img = image::open(&screenshot_path);
+    std::fs::remove_file(screenshot_path)?;
+
+    Ok(img?)
+}
+
+/// Asks the portal for a screenshot, which it saves to a file of its choosing
+#[cfg(target_os = "linux")]
+async fn capture_portal() -> Result<DynamicImage> {
+    use ashpd::desktop::screenshot::Screenshot;
+
+    let response = Screenshot::request()
+        .interactive(false)
+        .modal(false)
+        .send()
+        .await?
+        .response()?;
+    let uri = response.uri().to_string();
+    let path = match crate::util::file_uri_path(&uri) {
+        Some(x) => x,
+        None => anyhow::bail!("The screenshot portal returned '{}' rather than a file", uri),
+    };
+
+    let img = image::open(&path);
+    std::fs::remove_file(&path)?;
+
+    Ok(img?)
+}
+
+#[cfg(all(not(windows), not(target_os = "linux")))]
+async fn capture_portal() -> Result<DynamicImage> {
+    anyhow::bail!("The screenshot portal is only available on Linux, set capture_backend to x11")
+}
+
+/// Finds the window managed by the window manager whose title or class is
+/// `title`, ignoring case
+#[cfg(target_os = "linux")]
+fn find_window(title: &str) -> Result<WindowRect> {
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{AtomEnum, ConnectionExt};
+
+    let (conn, screen_num) = x11rb::connect(None)?;
+    let root = conn.setup().roots[screen_num].root;
+    let atom = |name: &[u8]| -> Result<u32> { Ok(conn.intern_atom(false, name)?.reply()?.atom) };
+    let client_list = atom(b"_NET_CLIENT_LIST")?;
+    let wm_name = atom(b"_NET_WM_NAME")?;
+    let utf8_string = atom(b"UTF8_STRING")?;
+
+    let clients = conn
+        .get_property(false, root, client_list, AtomEnum::WINDOW, 0, u32::MAX)?
+        .reply()?;
+    for window in clients.value32().into_iter().flatten() {
+        let name = conn
+            .get_property(false, window, wm_name, utf8_string, 0, 1024)?
+            .reply()?;
+        // the class is the instance and class name, each ending in a zero byte
+        let class = conn
+            .get_property(false, window, AtomEnum::WM_CLASS, AtomEnum::STRING, 0, 1024)?
+            .reply()?;
+        let matches = |value: &[u8]| String::from_utf8_lossy(value).eq_ignore_ascii_case(title);
+        if !matches(&name.value) && !class.value.split(|x| *x == 0).any(matches) {
+            continue;
+        }
+
+        let geometry = conn.get_geometry(window)?.reply()?;
+        let position = conn.translate_coordinates(window, root, 0, 0)?.reply()?;
+        return Ok(WindowRect {
+            x: position.dst_x as i32,
+            y: position.dst_y as i32,
+            width: geometry.width as u32,
+            height: geometry.height as u32,
+        });
+    }
+
+    anyhow::bail!("There is no window called '{}', is the game running?", title)
+}
+
+/// Finds the top-level window with the title, returning its contents without
+/// the title bar and borders
+#[cfg(windows)]
+fn find_window(title: &str) -> Result<WindowRect> {
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::shared::windef::{POINT, RECT};
+    use winapi::um::winuser::{ClientToScreen, FindWindowW, GetClientRect};
+
+    let wide_title: Vec<u16> = OsStr::new(title).encode_wide().chain(Some(0)).collect();
+    // SAFETY: the title is zero-terminated and outlives the calls, and the
+    // rectangle and point are valid to write to
+    unsafe {
+        let window = FindWindowW(std::ptr::null(), wide_title.as_ptr());
+        if window.is_null() {
+            anyhow::bail!("There is no window called '{}', is the game running?", title);
+        }
+
+        let mut rect: RECT = std::mem::zeroed();
+        let mut origin = POINT { x: 0, y: 0 };
+        if GetClientRect(window, &mut rect) == 0 || ClientToScreen(window, &mut origin) == 0 {
+            anyhow::bail!("Failed to find out where the '{}' window is", title);
+        }
+
+        Ok(WindowRect {
+            x: origin.x,
+            y: origin.y,
+            width: (rect.right - rect.left).max(0) as u32,
+            height: (rect.bottom - rect.top).max(0) as u32,
+        })
+    }
+}
+
+#[cfg(all(not(windows), not(target_os = "linux")))]
+fn find_window(_title: &str) -> Result<WindowRect> {
+    anyhow::bail!("capture_window is only supported on Linux and Windows")
+}
+
+/// The RandR monitors of the X11 screen, in the order the server lists them
+#[cfg(target_os = "linux")]
+pub fn monitors() -> Result<Vec<Monitor>> {
+    use x11rb::connection::Connection;
+    use x11rb::protocol::randr::ConnectionExt as _;
+    use x11rb::protocol::xproto::ConnectionExt as _;
+
+    let (conn, screen_num) = x11rb::connect(None)?;
+    let root = conn.setup().roots[screen_num].root;
+    let reply = conn.randr_get_monitors(root, true)?.reply()?;
+
+    let mut monitors = Vec::new();
+    for monitor in reply.monitors {
+        let name = conn.get_atom_name(monitor.name)?.reply()?.name;
+        monitors.push(Monitor {
+            name: String::from_utf8_lossy(&name).to_string(),
+            rect: WindowRect {
+                x: monitor.x as i32,
+                y: monitor.y as i32,
+                width: monitor.width as u32,
+                height: monitor.height as u32,
+            },
+            primary: monitor.primary,
+        });
+    }
+
+    Ok(monitors)
+}
+
+/// The displays Windows reports, named by their id
+#[cfg(windows)]
+pub fn monitors() -> Result<Vec<Monitor>> {
+    Ok(all_screens()?.iter().map(screen_monitor).collect())
+}
+
+#[cfg(windows)]
+fn all_screens() -> Result<Vec<screenshots::Screen>> {
+    match screenshots::Screen::all() {
+        Some(x) => Ok(x),
+        None => anyhow::bail!("Failed to list the displays"),
+    }
+}
+
+#[cfg(windows)]
+fn screen_monitor(screen: &screenshots::Screen) -> Monitor {
+    let info = &screen.display_info;
+    Monitor {
+        name: info.id.to_string(),
+        rect: WindowRect {
+            x: info.x,
+            y: info.y,
+            width: info.width,
+            height: info.height,
+        },
+        primary: info.is_primary,
+    }
+}
+
+#[cfg(all(not(windows), not(target_os = "linux")))]
+pub fn monitors() -> Result<Vec<Monitor>> {
+    anyhow::bail!("Listing monitors is only supported on Linux and Windows")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crop_window() {
+        let img = DynamicImage::new_rgb8(100, 80);
+        let window = |x, y, width, height| WindowRect {
+            x,
+            y,
+            width,
+            height,
+        };
+
+        let crop = crop_window(&img, window(10, 20, 30, 40), (0, 0)).unwrap();
+        assert_eq!(crop.dimensions(), (30, 40));
+
+        // partly outside of the capture
+        let crop = crop_window(&img, window(-10, 60, 30, 40), (0, 0)).unwrap();
+        assert_eq!(crop.dimensions(), (20, 20));
+
+        // on a display left of the primary one
+        let crop = crop_window(&img, window(-90, 0, 50, 50), (-100, 0)).unwrap();
+        assert_eq!(crop.dimensions(), (50, 50));
+
+        assert!(crop_window(&img, window(200, 0, 50, 50), (0, 0)).is_err());
+    }
+
+    #[test]
+    fn test_select_monitor() {
+        let monitor = |name: &str, x| Monitor {
+            name: name.to_string(),
+            rect: WindowRect {
+                x,
+                y: 0,
+                width: 1920,
+                height: 1080,
+            },
+            primary: x == 0,
+        };
+        let monitors = [monitor("DP-1", 0), monitor("HDMI-1", 1920)];
+
+        assert_eq!(select_monitor(&monitors, "1").unwrap(), 1);
+        assert_eq!(select_monitor(&monitors, "hdmi-1").unwrap(), 1);
+        assert_eq!(select_monitor(&monitors, " DP-1 ").unwrap(), 0);
+        assert!(select_monitor(&monitors, "2").is_err());
+        assert!(select_monitor(&monitors, "DP-2").is_err());
+    }
+
+    #[test]
+    fn test_bgrx_to_image() {
+        let img = bgrx_to_image(vec![10, 20, 30, 0, 40, 50, 60, 0], 2, 1).unwrap();
+        assert_eq!(img.get_pixel(0, 0), image::Rgba([30, 20, 10, 255]));
+        assert_eq!(img.get_pixel(1, 0), image::Rgba([60, 50, 40, 255]));
+
+        assert!(bgrx_to_image(vec![0; 7], 2, 1).is_none());
+    }
+}
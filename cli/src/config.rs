@@ -1,132 +1,1958 @@
 use crate::{
-    util::{config_path, data_path, screenshot_path, unix_timestamp},
-    ITEMS_CACHE_EXPIRY_S,
+    capture::CaptureBackend,
+    ee_log,
+    hotkey::Chord,
+    ocr::{
+        read_tesseract_config, Layout, MatchThreshold, OCRSettings, Region, UiTheme,
+        MAX_SUPERSAMPLE,
+    },
+    scan::{DealScoreWeights, PriceBasis, PriceMetric, SlotErrorBehavior},
+    template, token_store,
+    util::{
+        cache_dir, cache_path, cache_paths, config_path, data_path, legacy_config_path, rotate_log,
+        screenshot_path, thumbnail_path, unix_timestamp,
+    },
+    DATA_TESSDATA_DIR, ITEMS_CACHE_EXPIRY_S,
 };
 use anyhow::Result;
+use num_format::Locale;
 use serde::{Deserialize, Serialize};
 use serde_json;
-use std::fs::{self, File};
-use std::io::{Read, Write};
-use std::path::PathBuf;
-use std::time::SystemTime;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+use std::time::{Duration, SystemTime};
 use text_io;
 use wfm_rs::response::ShortItem;
 
-type JwtToken = String;
+const REDACTED: &str = "<redacted>";
 
+/// The warframe.market token. It is never printed in full, so it can't end up
+/// in logs or error messages by accident.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct JwtToken(String);
+
+impl JwtToken {
+    /// The raw token, only to be passed to the API
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Replaces every occurrence of the token in `text`
+    pub fn redact(&self, text: &str) -> String {
+        if self.0.is_empty() {
+            text.to_string()
+        } else {
+            text.replace(&self.0, REDACTED)
+        }
+    }
+}
+
+impl fmt::Debug for JwtToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "JwtToken({})", REDACTED)
+    }
+}
+
+impl fmt::Display for JwtToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+const MAX_DECIMALS: usize = 4;
+/// How often writing the config waits for another instance to finish writing
+const LOCK_ATTEMPTS: u32 = 20;
+const LOCK_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// The tesseract language of every game language other than English, in the
+/// same order as `wfm_rs::LANGUAGES`
+const TESSERACT_LANGUAGES: [(&str, &str); 10] = [
+    ("ru", "rus"),
+    ("ko", "kor"),
+    ("de", "deu"),
+    ("fr", "fra"),
+    ("pt", "por"),
+    ("zh-hans", "chi_sim"),
+    ("zh-hant", "chi_tra"),
+    ("es", "spa"),
+    ("it", "ita"),
+    ("pl", "pol"),
+];
+/// Where missing tesseract languages are downloaded from
+const TESSDATA_URL: &str = "https://github.com/tesseract-ocr/tessdata/raw/main";
+/// Every language there is several MB, anything smaller is an error page or cut off
+const MIN_TESSDATA_SIZE: usize = 1024 * 1024;
+
+/// Selects the profile for this run, like `--profile` does
+pub const PROFILE_ENV: &str = "WFM_CLI_PROFILE";
+/// Selects the account at the top level of the config rather than a profile
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// What is kept between runs besides the settings. It's in a file of its own,
+/// so the settings file stays short enough to edit by hand.
+#[derive(Serialize, Deserialize, Default)]
+struct Cache {
+    #[serde(default)]
+    jwt_token: JwtToken,
+    #[serde(default)]
+    items_timestamp: u64,
+    #[serde(default)]
+    items: Vec<ShortItem>,
+    #[serde(default)]
+    localized_items: Vec<ShortItem>,
+    #[serde(default)]
+    localized_items_language: Option<String>,
+}
+
+impl Cache {
+    fn of(cfg: &Config) -> Cache {
+        Cache {
+            jwt_token: if cfg.token_in_keyring {
+                JwtToken::default()
+            } else {
+                cfg.jwt_token.clone()
+            },
+            items_timestamp: cfg.items_timestamp,
+            items: cfg.items.clone(),
+            localized_items: cfg.localized_items.clone(),
+            localized_items_language: cfg.localized_items_language.clone(),
+        }
+    }
+
+    fn apply(self, cfg: &mut Config) {
+        cfg.jwt_token = self.jwt_token;
+        cfg.items_timestamp = self.items_timestamp;
+        cfg.items = self.items;
+        cfg.localized_items = self.localized_items;
+        cfg.localized_items_language = self.localized_items_language;
+    }
+}
+
+/// Another account, with a token and item cache of its own. The other
+/// settings are shared with the top level account.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Profile {
+    /// Platform of the account, like `platform` at the top level
+    #[serde(default = "default_platform")]
+    pub platform: String,
+    /// Language of the game client, like `game_language` at the top level
+    #[serde(default)]
+    pub game_language: Option<String>,
+}
+
+/// The settings, read from the TOML config file, along with the `Cache`
 #[derive(Serialize, Deserialize)]
 pub struct Config {
+    #[serde(skip)]
     jwt_token: JwtToken,
+    /// Keep the login token in the OS keyring rather than the cache file. The
+    /// file is still used if the keyring can't be reached.
+    #[serde(default = "default_use_keyring")]
+    pub use_keyring: bool,
+    /// Whether the token was loaded from (or stored in) the keyring, so it's
+    /// left out of the cache file
+    #[serde(skip)]
+    token_in_keyring: bool,
+    /// Platform of the account, one of `wfm_rs::PLATFORMS`. Prices are looked
+    /// up for it unless `price_platform` is set.
+    #[serde(default = "default_platform")]
+    pub platform: String,
+    /// Look up prices for this platform instead, without logging in again
+    #[serde(default)]
+    pub price_platform: Option<String>,
+    /// Same as `price_platform`, from `--platform`. Only applies to this run,
+    /// so it's never written back to the config file.
+    #[serde(skip)]
+    pub run_platform: Option<String>,
+    /// Never use the login, only the public endpoints. Features that need an
+    /// account, like `compare_listings`, are turned off.
+    #[serde(default)]
+    pub anonymous: bool,
+    /// Same as `anonymous`, from `--no-login`, for this run only
+    #[serde(skip)]
+    pub run_anonymous: bool,
+    /// Whether there is a terminal to ask for the login on once it expires.
+    /// Only the commands run from one set it, the daemon never does.
+    #[serde(skip)]
+    pub interactive: bool,
+    /// Accounts besides the top level one, by name
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    /// The profile used without `--profile`, the top level account if unset
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// The profile used this run, see `use_profile`
+    #[serde(skip)]
+    active_profile: Option<String>,
+    #[serde(skip)]
     items_timestamp: u64,
+    #[serde(skip)]
     pub items: Vec<wfm_rs::response::ShortItem>,
+    /// Language of the game client, one of `wfm_rs::LANGUAGES`. The reward
+    /// names are read in it and matched against the item names in it.
+    #[serde(default)]
+    pub game_language: Option<String>,
+    /// The item list in `game_language`, fetched along with `items`
+    #[serde(skip)]
+    localized_items: Vec<wfm_rs::response::ShortItem>,
+    #[serde(skip)]
+    localized_items_language: Option<String>,
+    /// Item names that get starred and preferred when picking the best reward
+    #[serde(default)]
+    pub watchlist: Vec<String>,
+    /// Item names that are never reported in scan results
+    #[serde(default)]
+    pub ignore_items: Vec<String>,
+    /// Bounds of the game's monitor within the screenshot, when the capture
+    /// contains the whole desktop rather than just the game window
+    #[serde(default)]
+    pub game_region: Option<Region>,
+    /// Reads further from any item name than this are reported as unreadable
+    #[serde(default)]
+    pub match_threshold: MatchThreshold,
+    /// Refuse to scan screens that are recognizably not the reward screen, such
+    /// as the void fissure selection
+    #[serde(default = "default_detect_wrong_screen")]
+    pub detect_wrong_screen: bool,
+    /// Slots with a smaller share of text colored pixels are considered empty
+    #[serde(default = "default_empty_slot_density")]
+    pub empty_slot_density: f32,
+    /// How many captures in a row a slot has to look empty in to be reported as
+    /// empty. Only those slots are read again, on captures a moment apart.
+    #[serde(default = "default_empty_slot_reads")]
+    pub empty_slot_reads: usize,
+    /// Hand the reward crops to whichever OCR worker is free, rather than every
+    /// slot to its own worker, so one hard to read slot doesn't hold up the others
+    #[serde(default)]
+    pub shared_work_queue: bool,
+    /// Factor the reward crops are upscaled by before the text filter and OCR,
+    /// which can recover small or blurry text at the cost of speed
+    #[serde(default = "default_supersample")]
+    pub supersample: u32,
+    /// Named crop layouts, in addition to the built-in "default" one
+    #[serde(default)]
+    pub layouts: HashMap<String, Layout>,
+    /// The layout used when none is passed with `--layout`
+    #[serde(default)]
+    pub layout: Option<String>,
+    /// Scale layouts made for another resolution to the screenshot, rather
+    /// than only warning about the mismatch
+    #[serde(default = "default_scale_layout")]
+    pub scale_layout: bool,
+    /// Find the reward row on the first screenshot of every resolution, the
+    /// way `calibrate` does, and use the layout derived from it instead
+    #[serde(default)]
+    pub detect_layout: bool,
+    /// Only read the slots of the reward cards on the screen, which are fewer
+    /// and centered in smaller squads
+    #[serde(default = "default_count_cards")]
+    pub count_cards: bool,
+    /// The in-game UI theme, which decides the color the text filter keeps,
+    /// or `auto` to pick it on every scan
+    #[serde(default)]
+    pub ui_theme: UiTheme,
+    /// Keys that scan the reward screen, each with its own layout
+    #[serde(default = "default_hotkeys")]
+    pub hotkeys: Vec<Hotkey>,
+    /// Whether prices come from closed trades, live sell orders, or both
+    #[serde(default)]
+    pub price_basis: PriceBasis,
+    /// How the closed trade prices are combined: mean, median or quick-sell
+    #[serde(default)]
+    pub price_metric: PriceMetric,
+    /// Percentile of the closed trade prices used as the quick-sell price
+    #[serde(default = "default_quick_sell_percentile")]
+    pub quick_sell_percentile: f32,
+    /// Moves the reward row of the layout down (or up, when negative) by this many pixels
+    #[serde(default)]
+    pub reward_row_offset: i32,
+    /// Only count the live sell orders of sellers that are online (or in game)
+    #[serde(default = "default_online_only")]
+    pub online_only: bool,
+    /// Show the lowest sell and the highest buy order of every item, and the
+    /// spread between them, for flipping
+    #[serde(default)]
+    pub show_spread: bool,
+    /// Show the cheapest current sell orders and the best buy order of every
+    /// item next to its average price
+    #[serde(default)]
+    pub show_order_book: bool,
+    /// How every result line looks, with placeholders such as `{name}` and
+    /// `{price}` (see `template::PLACEHOLDERS`), instead of the default format
+    #[serde(default)]
+    pub output_template: Option<String>,
+    /// Number of decimals prices are shown with
+    #[serde(default = "default_price_decimals")]
+    pub price_decimals: usize,
+    /// Number of decimals trade volumes are shown with
+    #[serde(default)]
+    pub volume_decimals: usize,
+    /// Name of the locale (e.g. "de") used for decimal and thousands separators
+    #[serde(default)]
+    pub locale: Option<String>,
+    /// Only beep when the best item is worth at least this much platinum
+    #[serde(default)]
+    pub beep_min_value: f32,
+    /// Length of the fade in and out of every beep in milliseconds, which keeps
+    /// them from clicking
+    #[serde(default = "default_beep_fade_ms")]
+    pub beep_fade_ms: u64,
+    /// Audio file played instead of the beep when a scan is done
+    #[serde(default)]
+    pub scan_sound: Option<String>,
+    /// Audio file played when a scan fails
+    #[serde(default)]
+    pub error_sound: Option<String>,
+    /// How the screen is captured: `auto` uses the screenshot portal on
+    /// Wayland and X11 otherwise, `x11` or `portal` force one of them
+    #[serde(default)]
+    pub capture_backend: CaptureBackend,
+    /// Only capture the window with this title or X11 class (e.g. Warframe),
+    /// so the crops are relative to the window wherever it is
+    #[serde(default)]
+    pub capture_window: Option<String>,
+    /// Only capture this display, by its index or name as listed by the
+    /// `monitors` command, so the crops are relative to its top left corner
+    #[serde(default)]
+    pub monitor: Option<String>,
+    /// Count down this many seconds after pressing the hotkey before capturing
+    #[serde(default)]
+    pub capture_countdown_s: u64,
+    /// Capture again every `settle_interval_ms` until the reward crops stop
+    /// changing by more than `settle_threshold` (from 0 to 1), for up to
+    /// `settle_timeout_ms`, so the rewards are scanned after they've faded in
+    #[serde(default)]
+    pub wait_for_settle: bool,
+    #[serde(default = "default_settle_interval_ms")]
+    pub settle_interval_ms: u64,
+    #[serde(default = "default_settle_threshold")]
+    pub settle_threshold: f32,
+    #[serde(default = "default_settle_timeout_ms")]
+    pub settle_timeout_ms: u64,
+    /// Captures darker on average than this (from 0 to 1) are rejected before
+    /// OCR, since they're taken while the game is minimized or loading
+    #[serde(default = "default_min_brightness")]
+    pub min_brightness: f32,
+    /// Captures with a lower standard deviation of brightness than this (from
+    /// 0 to 1) are rejected too, which catches murky transition frames
+    #[serde(default = "default_min_contrast")]
+    pub min_contrast: f32,
+    /// Suggest whether refining the relic is worth it, at the cost of an
+    /// extra API request per item
+    #[serde(default)]
+    pub refinement_advice: bool,
+    /// Pad the results of every scan with blank lines to this many lines, so
+    /// the output stays in place between scans
+    #[serde(default)]
+    pub output_lines: Option<usize>,
+    /// Tag every reward with its drop rarity (common, uncommon or rare), at the
+    /// cost of an extra API request per item
+    #[serde(default)]
+    pub show_rarity: bool,
+    /// Show how much platinum every ducat of an item is worth, at the cost of
+    /// an extra API request per item
+    #[serde(default)]
+    pub ducat_ratio: bool,
+    /// Rank items by their platinum per ducat, lowest first, for trading them in
+    #[serde(default)]
+    pub rank_by_ducat_ratio: bool,
+    /// Group the parts of the same Prime set together and rank the sets by
+    /// the total price of their scanned parts, at the cost of an extra API
+    /// request per item
+    #[serde(default)]
+    pub group_by_set: bool,
+    /// Keep fetched statistics in a file across sessions, and use them instead
+    /// of fetching them again while they are at most `price_cache_max_age_s` old
+    #[serde(default)]
+    pub price_cache: bool,
+    #[serde(default = "default_price_cache_max_age_s")]
+    pub price_cache_max_age_s: u64,
+    /// Show item thumbnails next to the results, in terminals supporting the
+    /// kitty or iTerm image protocol. They are downloaded once and kept in the
+    /// cache directory
+    #[serde(default)]
+    pub show_thumbnails: bool,
+    /// Point out scanned items you are already selling, and how your price
+    /// compares to the market
+    #[serde(default)]
+    pub compare_listings: bool,
+    /// Rank items by a combination of price, volume and OCR confidence
+    #[serde(default)]
+    pub deal_score: Option<DealScoreWeights>,
+    /// Use the traineddata in this directory instead of the bundled one
+    #[serde(default)]
+    pub tessdata_dir: Option<String>,
+    /// Tesseract config file (in tesseract's `name value` format) applied to every worker
+    #[serde(default)]
+    pub tesseract_config: Option<String>,
+    /// Tesseract variables applied after the config file, e.g. `tessedit_char_whitelist`
+    #[serde(default)]
+    pub tesseract_variables: HashMap<String, String>,
+    /// Token that `daemon` requests have to pass as `Authorization: Bearer <token>`
+    #[serde(default)]
+    pub daemon_token: Option<String>,
+    /// Directory the game (or Steam) saves screenshots to, for `watch-dir`
+    #[serde(default)]
+    pub screenshot_dir: Option<String>,
+    /// Scan in watch mode whenever the game logs that the reward screen opened
+    #[serde(default)]
+    pub scan_on_log: bool,
+    /// Where the game's EE.log is, if not in the default location
+    #[serde(default)]
+    pub ee_log_path: Option<String>,
+    /// How long to wait after the reward screen opened before capturing it
+    #[serde(default = "default_log_scan_delay_ms")]
+    pub log_scan_delay_ms: u64,
+    /// Keep every scan in the history file, for `export`
+    #[serde(default = "default_record_history")]
+    pub record_history: bool,
+    /// Named pipe every scan is written to as a line of JSON, if a reader is attached
+    #[serde(default)]
+    pub fifo_path: Option<String>,
+    /// Append the text of low-confidence and not found matches to the misses log,
+    /// to find systematic misreads
+    #[serde(default)]
+    pub log_misses: bool,
+    /// Base URL of a server with the same API as warframe.market (e.g. a cache
+    /// shared by a group), asked before warframe.market itself
+    #[serde(default)]
+    pub cache_server: Option<String>,
+    /// Whether items whose statistics can't be fetched are skipped, marked, or fail the scan
+    #[serde(default)]
+    pub on_slot_error: SlotErrorBehavior,
+    /// How many warframe.market requests may be in flight at once
+    #[serde(default = "default_max_concurrent_requests")]
+    pub max_concurrent_requests: usize,
+    /// Never refresh the item list, not even when it is outdated
+    #[serde(default)]
+    pub no_refresh: bool,
+    /// How many "item not found" responses in a row trigger an item list refresh
+    #[serde(default = "default_not_found_refresh_threshold")]
+    pub not_found_refresh_threshold: usize,
+    /// Minimum time between two automatic item list refreshes, in seconds
+    #[serde(default = "default_auto_refresh_cooldown_s")]
+    pub auto_refresh_cooldown_s: u64,
+    /// When the history and misses logs are rotated
+    #[serde(default)]
+    pub log_rotation: LogRotation,
+}
+
+/// Keys used for other things than scanning in watch mode
+const RESERVED_KEYS: [&str; 2] = ["F7", "F8"];
+
+/// A key that scans the reward screen when pressed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Hotkey {
+    /// Name of the key, such as "F6" or "Key1", after any modifiers to hold
+    /// with it, as in "Ctrl+Shift+F6"
+    pub key: String,
+    /// Layout to scan with, instead of the one selected by `--layout` or the config
+    #[serde(default)]
+    pub layout: Option<String>,
+}
+
+/// Size limit of the history and misses logs. Once a log reaches it, it's
+/// moved to `<name>.1` (and older ones to `<name>.2` and so on), keeping up to
+/// `max_files` of them.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct LogRotation {
+    #[serde(default = "default_log_max_size_mb")]
+    pub max_size_mb: u64,
+    #[serde(default = "default_log_max_files")]
+    pub max_files: usize,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        LogRotation {
+            max_size_mb: default_log_max_size_mb(),
+            max_files: default_log_max_files(),
+        }
+    }
+}
+
+impl LogRotation {
+    /// Rotates the log at `path` if it has reached the size limit
+    pub fn rotate(&self, path: &Path) -> Result<()> {
+        rotate_log(path, self.max_size_mb * 1024 * 1024, self.max_files)
+    }
+}
+
+fn default_log_max_size_mb() -> u64 {
+    5
+}
+
+fn default_log_max_files() -> usize {
+    5
+}
+
+impl Hotkey {
+    pub fn chord(&self) -> Result<Chord> {
+        Chord::parse(&self.key)
+    }
+}
+
+/// Checks that the hotkeys can be told apart from each other and the reserved keys
+fn check_hotkeys(hotkeys: &[Hotkey]) -> Result<()> {
+    let chords = hotkeys.iter().map(Hotkey::chord).collect::<Result<Vec<_>>>()?;
+    for (idx, chord) in chords.iter().enumerate() {
+        if RESERVED_KEYS.iter().any(|x| Chord::parse(x).unwrap() == *chord) {
+            anyhow::bail!("Hotkey '{}' is reserved", chord);
+        }
+        if chords[..idx].contains(chord) {
+            anyhow::bail!("Hotkey '{}' is bound more than once", chord);
+        }
+    }
+
+    Ok(())
+}
+
+fn default_hotkeys() -> Vec<Hotkey> {
+    vec![Hotkey {
+        key: "F6".to_string(),
+        layout: None,
+    }]
+}
+
+fn default_log_scan_delay_ms() -> u64 {
+    1000
+}
+
+fn default_use_keyring() -> bool {
+    true
+}
+
+fn default_platform() -> String {
+    "pc".to_string()
+}
+
+fn default_settle_interval_ms() -> u64 {
+    150
+}
+
+fn default_settle_threshold() -> f32 {
+    0.02
+}
+
+fn default_settle_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_price_cache_max_age_s() -> u64 {
+    60 * 60
+}
+
+fn default_min_brightness() -> f32 {
+    0.03
+}
+
+fn default_min_contrast() -> f32 {
+    0.02
+}
+
+fn default_beep_fade_ms() -> u64 {
+    10
+}
+
+fn default_detect_wrong_screen() -> bool {
+    true
+}
+
+fn default_scale_layout() -> bool {
+    true
+}
+
+fn default_count_cards() -> bool {
+    true
+}
+
+fn default_empty_slot_density() -> f32 {
+    // reward names cover several percent of their crop, so this only catches
+    // crops with next to no text
+    0.005
+}
+
+fn default_empty_slot_reads() -> usize {
+    2
+}
+
+fn default_quick_sell_percentile() -> f32 {
+    25.0
+}
+
+fn default_supersample() -> u32 {
+    1
+}
+
+fn default_online_only() -> bool {
+    true
+}
+
+fn default_price_decimals() -> usize {
+    1
+}
+
+fn default_record_history() -> bool {
+    true
+}
+
+fn default_max_concurrent_requests() -> usize {
+    wfm_rs::DEFAULT_MAX_CONCURRENT_REQUESTS
+}
+
+fn default_not_found_refresh_threshold() -> usize {
+    3
+}
+
+fn default_auto_refresh_cooldown_s() -> u64 {
+    10 * 60
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            jwt_token: JwtToken::default(),
+            use_keyring: default_use_keyring(),
+            token_in_keyring: false,
+            platform: default_platform(),
+            price_platform: None,
+            run_platform: None,
+            anonymous: false,
+            run_anonymous: false,
+            interactive: false,
+            profiles: HashMap::new(),
+            profile: None,
+            active_profile: None,
+            items_timestamp: 0,
+            items: Vec::new(),
+            game_language: None,
+            localized_items: Vec::new(),
+            localized_items_language: None,
+            watchlist: Vec::new(),
+            ignore_items: Vec::new(),
+            game_region: None,
+            match_threshold: MatchThreshold::default(),
+            detect_wrong_screen: default_detect_wrong_screen(),
+            empty_slot_density: default_empty_slot_density(),
+            empty_slot_reads: default_empty_slot_reads(),
+            supersample: default_supersample(),
+            shared_work_queue: false,
+            layouts: HashMap::new(),
+            layout: None,
+            scale_layout: default_scale_layout(),
+            detect_layout: false,
+            count_cards: default_count_cards(),
+            ui_theme: UiTheme::default(),
+            hotkeys: default_hotkeys(),
+            reward_row_offset: 0,
+            price_basis: PriceBasis::default(),
+            price_metric: PriceMetric::default(),
+            quick_sell_percentile: default_quick_sell_percentile(),
+            online_only: default_online_only(),
+            show_spread: false,
+            show_order_book: false,
+            output_template: None,
+            price_decimals: default_price_decimals(),
+            volume_decimals: 0,
+            locale: None,
+            beep_min_value: 0.0,
+            beep_fade_ms: default_beep_fade_ms(),
+            scan_sound: None,
+            error_sound: None,
+            capture_backend: CaptureBackend::default(),
+            capture_window: None,
+            monitor: None,
+            capture_countdown_s: 0,
+            wait_for_settle: false,
+            settle_interval_ms: default_settle_interval_ms(),
+            settle_threshold: default_settle_threshold(),
+            settle_timeout_ms: default_settle_timeout_ms(),
+            min_brightness: default_min_brightness(),
+            min_contrast: default_min_contrast(),
+            refinement_advice: false,
+            show_rarity: false,
+            output_lines: None,
+            ducat_ratio: false,
+            rank_by_ducat_ratio: false,
+            group_by_set: false,
+            price_cache: false,
+            price_cache_max_age_s: default_price_cache_max_age_s(),
+            show_thumbnails: false,
+            compare_listings: false,
+            deal_score: None,
+            tessdata_dir: None,
+            tesseract_config: None,
+            tesseract_variables: HashMap::new(),
+            daemon_token: None,
+            screenshot_dir: None,
+            scan_on_log: false,
+            ee_log_path: None,
+            log_scan_delay_ms: default_log_scan_delay_ms(),
+            record_history: default_record_history(),
+            fifo_path: None,
+            log_misses: false,
+            on_slot_error: SlotErrorBehavior::default(),
+            cache_server: None,
+            max_concurrent_requests: default_max_concurrent_requests(),
+            no_refresh: false,
+            not_found_refresh_threshold: default_not_found_refresh_threshold(),
+            auto_refresh_cooldown_s: default_auto_refresh_cooldown_s(),
+            log_rotation: LogRotation::default(),
+        }
+    }
 }
 
 impl Config {
     pub fn user(&self) -> wfm_rs::User {
-        wfm_rs::User::_from_jwt_token(&self.jwt_token)
+        let user = wfm_rs::User::_from_jwt_token(self.api_token())
+            .with_platform(self.stats_platform())
+            .with_max_concurrent_requests(self.max_concurrent_requests);
+
+        match &self.cache_server {
+            Some(url) => user.with_mirror(url),
+            None => user,
+        }
+    }
+
+    /// The token requests are made with, empty when they're made anonymously
+    fn api_token(&self) -> &str {
+        if self.anonymous || self.run_anonymous {
+            ""
+        } else {
+            self.jwt_token.expose()
+        }
+    }
+
+    /// Whether requests are made with a login, rather than anonymously
+    pub fn logged_in(&self) -> bool {
+        !self.api_token().is_empty()
+    }
+
+    /// The game's log, from `ee_log_path` or wherever the game keeps it by default
+    pub fn ee_log_path(&self) -> Option<PathBuf> {
+        self.ee_log_path
+            .as_ref()
+            .map(PathBuf::from)
+            .or_else(ee_log::default_path)
+    }
+
+    /// Switches to the profile for this run. Its platform and language are
+    /// swapped with the top level ones while the config is in memory, so
+    /// everything else keeps using `platform` and `game_language`.
+    fn use_profile(&mut self, name: &str) -> Result<()> {
+        self.check_profile(name)?;
+        if name == DEFAULT_PROFILE {
+            return Ok(());
+        }
+
+        let profile = self.profiles.get_mut(name).unwrap();
+        std::mem::swap(&mut self.platform, &mut profile.platform);
+        std::mem::swap(&mut self.game_language, &mut profile.game_language);
+        self.active_profile = Some(name.to_string());
+        Ok(())
+    }
+
+    fn check_profile(&self, name: &str) -> Result<()> {
+        if name != DEFAULT_PROFILE && !self.profiles.contains_key(name) {
+            let mut names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            names.sort_unstable();
+            names.insert(0, DEFAULT_PROFILE);
+            anyhow::bail!(
+                "Unknown profile '{}', expected one of: {}",
+                name,
+                names.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Name of the profile used this run
+    pub fn active_profile(&self) -> &str {
+        self.active_profile.as_deref().unwrap_or(DEFAULT_PROFILE)
+    }
+
+    /// Where the token and item cache of the active profile are kept
+    fn cache_path(&self) -> Result<PathBuf> {
+        cache_path(self.active_profile.as_deref())
+    }
+
+    /// Platform prices are looked up for, which is the login platform unless
+    /// it's overridden
+    pub fn stats_platform(&self) -> &str {
+        self.run_platform
+            .as_deref()
+            .or(self.price_platform.as_deref())
+            .unwrap_or(&self.platform)
+    }
+
+    /// `layout` overrides the layout selected in the config
+    pub fn ocr_settings(
+        &self,
+        layout: Option<&str>,
+        tessdata_dir: Option<String>,
+    ) -> Result<OCRSettings> {
+        let mut tesseract_variables = match &self.tesseract_config {
+            Some(path) => read_tesseract_config(path)?,
+            None => Vec::new(),
+        };
+        tesseract_variables.extend(
+            self.tesseract_variables
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+
+        let mut layout_resolutions: Vec<_> = self
+            .layouts
+            .iter()
+            .filter_map(|(name, layout)| Some((name.clone(), layout.resolution?)))
+            .collect();
+        layout_resolutions.sort();
+        if !self.layouts.contains_key("default") {
+            if let Some(resolution) = Layout::default().resolution {
+                layout_resolutions.push(("default".to_string(), resolution));
+            }
+        }
+
+        Ok(OCRSettings {
+            game_region: self.game_region,
+            match_threshold: self.match_threshold,
+            layout: self.layout(layout)?,
+            scale_layout: self.scale_layout,
+            detect_layout: self.detect_layout,
+            count_cards: self.count_cards,
+            ui_theme: self.ui_theme,
+            tessdata_dir,
+            tesseract_language: self.tesseract_language().map(String::from),
+            tesseract_variables,
+            empty_slot_density: self.empty_slot_density,
+            supersample: self.supersample,
+            shared_queue: self.shared_work_queue,
+            layout_resolutions,
+        })
+    }
+
+    /// The layout called `name`, or the one selected in the config if `None`,
+    /// with the reward row offset applied
+    pub fn layout(&self, name: Option<&str>) -> Result<Layout> {
+        let layout = match name.or(self.layout.as_deref()) {
+            None => Layout::default(),
+            Some(name) => match self.layouts.get(name) {
+                Some(layout) => layout.clone(),
+                None if name == "default" => Layout::default(),
+                None => anyhow::bail!("Unknown layout '{}'", name),
+            },
+        };
+
+        Ok(layout.shifted_vertically(self.reward_row_offset))
+    }
+
+    /// Removes the token from text that is about to be shown, such as errors
+    pub fn redact(&self, text: &str) -> String {
+        self.jwt_token.redact(text)
+    }
+
+    /// The game language, unless it's English
+    pub fn game_language(&self) -> Option<&str> {
+        self.game_language.as_deref().filter(|x| *x != "en")
+    }
+
+    /// The tesseract language reward names are read in, `None` for the
+    /// bundled English one
+    pub fn tesseract_language(&self) -> Option<&'static str> {
+        let language = self.game_language()?;
+        TESSERACT_LANGUAGES
+            .iter()
+            .find(|(game, _)| *game == language)
+            .map(|(_, tesseract)| *tesseract)
+    }
+
+    /// The English item with the same url name as the (localized) `item`, or
+    /// `item` itself if there is none
+    pub fn canonical_item(&self, item: ShortItem) -> ShortItem {
+        match self.items.iter().find(|x| x.url_name == item.url_name) {
+            Some(x) => x.clone(),
+            None => item,
+        }
+    }
+
+    /// The items the OCR engine matches against, in the game language,
+    /// including ones that can show up as rewards but aren't tradeable on
+    /// warframe.market
+    pub fn ocr_items(&self) -> Vec<ShortItem> {
+        let mut items = if self.game_language().is_some() && !self.localized_items.is_empty() {
+            self.localized_items.clone()
+        } else {
+            self.items.clone()
+        };
+        items.push(ShortItem {
+            url_name: "".to_string(),
+            thumb: "".to_string(),
+            id: "".to_string(),
+            item_name: "Forma Blueprint".to_string(),
+        });
+        items
+    }
+
+    pub fn number_locale(&self) -> Option<Locale> {
+        self.locale
+            .as_ref()
+            .and_then(|x| Locale::from_name(x).ok())
+    }
+
+    /// Whether the rarity and ducats of items have to be looked up
+    pub fn needs_item_details(&self) -> bool {
+        self.refinement_advice || self.show_rarity || self.group_by_set || self.needs_ducats()
+    }
+
+    /// Whether the ducat value of items has to be looked up
+    pub fn needs_ducats(&self) -> bool {
+        self.ducat_ratio || self.rank_by_ducat_ratio
+    }
+
+    pub fn is_watched(&self, item: &ShortItem) -> bool {
+        self.watchlist
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(&item.item_name))
+    }
+
+    pub fn is_ignored(&self, item: &ShortItem) -> bool {
+        self.ignore_items
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(&item.item_name))
     }
 }
 
-#[allow(unused_must_use)]
-pub async fn run() -> Result<Config> {
+/// Loads the config, creating it on the first run. With `no_refresh` (or
+/// `no_refresh` in the config) the cached item list is used no matter its age,
+/// and with `no_login` the API is only used anonymously. The tessdata is only
+/// extracted after this succeeded, once it's clear the program can run.
+pub async fn run(no_refresh: bool, no_login: bool) -> Result<Config> {
     let data_path = data_path()?;
     let data_path_screenshot = screenshot_path()?;
     let data_path_config = config_path()?;
 
-    let mut config = {
-        if let Ok(mut file) = File::open(&data_path_config) {
-            let mut strbuf = String::new();
-            file.read_to_string(&mut strbuf)?;
-            let mut cfg = serde_json::from_str::<Config>(&strbuf)?;
+    migrate_legacy_config()?;
+    let config = {
+        if data_path_config.exists() {
+            // the data and cache directories are apart from the config, and may be new
+            fs::create_dir_all(&data_path)?;
+            fs::create_dir_all(&data_path_screenshot)?;
+            let mut cfg = load()?;
+            // only kept in memory, refreshing only writes the cache back
+            cfg.no_refresh |= no_refresh;
+            cfg.run_anonymous = no_login;
+            cfg.interactive = atty::is(atty::Stream::Stdin);
 
-            if (unix_timestamp()? - cfg.items_timestamp) > ITEMS_CACHE_EXPIRY_S {
+            let outdated = (unix_timestamp()? - cfg.items_timestamp) > ITEMS_CACHE_EXPIRY_S;
+            // the game language was changed since the items were fetched
+            let untranslated = cfg.localized_items_language.as_deref() != cfg.game_language();
+            if (outdated || untranslated) && !cfg.no_refresh {
                 print!("Refreshing items...   ");
-                let mut items = wfm_rs::User::_from_jwt_token(&cfg.jwt_token)
-                    .get_items()
-                    .await?;
-                fix_items(&mut items);
-                cfg.items = items;
-                cfg.items_timestamp = SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)?
-                    .as_secs();
-                write_config_to_file(&data_path_config, &cfg)?;
+                refresh_items(&mut cfg).await?;
                 println!("success!");
+            } else if untranslated {
+                eprintln!("The cached item names aren't in the game language yet, run once without --no-refresh to fetch them");
+            }
+
+            if let Some(language) = cfg.tesseract_language() {
+                if cfg.tessdata_dir.is_none() && std::env::var_os("TESSDATA_PREFIX").is_none() {
+                    if let Err(e) = download_tessdata(language).await {
+                        eprintln!(
+                            "Failed to download the {} tesseract data ({}), reward names can't be read until it's downloaded",
+                            language, e
+                        );
+                    }
+                }
             }
 
             cfg
+        } else if let Some(profile) = run_profile().filter(|x| x != DEFAULT_PROFILE) {
+            anyhow::bail!(
+                "There is no config to find profile '{}' in yet, run once without --profile and add it to {}",
+                profile,
+                data_path_config.to_string_lossy()
+            );
+        } else if no_refresh {
+            anyhow::bail!(
+                "There is no cached item list yet ({} doesn't exist), run once without --no-refresh to fetch it",
+                data_path_config.to_string_lossy()
+            );
         } else {
-            fs::create_dir(&data_path);
-            fs::create_dir(&data_path_screenshot);
-            File::create(&data_path_config);
-
             let token = JwtToken::default();
 
+            // make sure the API is reachable before creating anything, so a
+            // failed first run doesn't leave a half-written setup behind
             print!("Building config...   ");
-            let cfg = Config {
-                items: wfm_rs::User::_from_jwt_token(&token).get_items().await?,
+            let mut cfg = Config {
                 items_timestamp: unix_timestamp()?,
                 jwt_token: token,
+                run_anonymous: no_login,
+                interactive: atty::is(atty::Stream::Stdin),
+                ..Default::default()
             };
+            cfg.items = cfg.user().get_items().await?;
             println!("success!");
 
-            write_config_to_file(&data_path_config, &cfg)?;
+            fs::create_dir_all(&data_path)?;
+            fs::create_dir_all(&data_path_screenshot)?;
+            write_settings(&cfg)?;
+            write_cache(&cfg.cache_path()?, &Cache::of(&cfg))?;
 
             cfg
         }
     };
 
-    config.items.push(ShortItem {
-        url_name: "".to_string(),
-        thumb: "".to_string(),
-        id: "".to_string(),
-        item_name: "Forma Blueprint".to_string(),
-    });
+    if config.compare_listings && !config.logged_in() {
+        eprintln!("compare_listings needs a login, run `wfm_cli login` to use it");
+    }
 
     Ok(config)
 }
 
-// guide user through login process
-async fn login_process() -> Result<JwtToken> {
-    println!("You need to log in with your warframe.market account!");
-    println!("This program does not store your e-mail and/or password, they are both only used once, to log into the warframe.market API.");
-    println!("The only thing related to your account this program stores is the token received from the API.");
+/// Reads the config file again, without refreshing anything
+pub fn load() -> Result<Config> {
+    migrate_legacy_config()?;
+    let path = config_path()?;
+    let settings = fs::read_to_string(&path)?;
+    let mut cfg = read_config(&settings, Cache::default())
+        .map_err(|e| anyhow::anyhow!("{}: {}", path.to_string_lossy(), e))?;
+    if let Some(profile) = run_profile().or_else(|| cfg.profile.clone()) {
+        cfg.use_profile(&profile)?;
+    }
+    read_cache(&cfg.cache_path()?)?.apply(&mut cfg);
+    if cfg.use_keyring {
+        load_keyring_token(&mut cfg)?;
+    }
+
+    Ok(cfg)
+}
+
+/// Reads the token from the keyring, moving it there first if the cache file
+/// still has it (as it did before the keyring was used)
+fn load_keyring_token(cfg: &mut Config) -> Result<()> {
+    if cfg.jwt_token.0.is_empty() {
+        match token_store::load(cfg.active_profile()) {
+            Ok(token) => {
+                cfg.jwt_token = JwtToken(token.unwrap_or_default());
+                cfg.token_in_keyring = true;
+            }
+            Err(e) => keyring_warning(&e),
+        }
+    } else if store_keyring_token(cfg) {
+        write_cache(&cfg.cache_path()?, &Cache::of(cfg))?;
+        eprintln!("Moved the login token from the cache file to the keyring");
+    }
+
+    Ok(())
+}
+
+/// Stores the token in the keyring, returning whether that worked. It's kept
+/// in the cache file otherwise.
+fn store_keyring_token(cfg: &mut Config) -> bool {
+    match token_store::store(cfg.active_profile(), cfg.jwt_token.expose()) {
+        Ok(()) => cfg.token_in_keyring = true,
+        Err(e) => {
+            keyring_warning(&e);
+            cfg.token_in_keyring = false;
+        }
+    }
+
+    cfg.token_in_keyring
+}
+
+/// Warns about the keyring, only the first time in a run
+fn keyring_warning(e: &anyhow::Error) {
+    static WARNING: Once = Once::new();
+    WARNING.call_once(|| {
+        eprintln!(
+            "Couldn't use the keyring ({}), the login token is kept in the cache file (set use_keyring = false to skip the keyring)",
+            e
+        )
+    });
+}
+
+/// The profile selected for this run by `--profile`
+fn run_profile() -> Option<String> {
+    std::env::var(PROFILE_ENV).ok()
+}
+
+/// The cache, which is empty (and so refreshed) if it's missing
+fn read_cache(path: &Path) -> Result<Cache> {
+    match fs::read_to_string(path) {
+        Ok(x) => Ok(serde_json::from_str(&x)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Cache::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Splits the JSON config of older versions into the settings and the cache,
+/// keeping the old file, minus the login token, as a backup
+fn migrate_legacy_config() -> Result<()> {
+    let legacy_path = legacy_config_path()?;
+    let settings_path = config_path()?;
+    if settings_path.exists() || !legacy_path.exists() {
+        return Ok(());
+    }
+
+    let legacy = fs::read_to_string(&legacy_path)?;
+    let mut cfg = serde_json::from_str::<Config>(&legacy)?;
+    // the cache fields have the same names there
+    serde_json::from_str::<Cache>(&legacy)?.apply(&mut cfg);
+    write_settings(&cfg)?;
+    write_cache(&cache_path(None)?, &Cache::of(&cfg))?;
+
+    let mut backup_path = legacy_path.clone().into_os_string();
+    backup_path.push(".bak");
+    // the token lives on in the cache (or keyring), a second copy would only be left behind
+    let mut backup: serde_json::Value = serde_json::from_str(&legacy)?;
+    if let Some(fields) = backup.as_object_mut() {
+        fields.remove("jwt_token");
+    }
+    fs::write(&backup_path, serde_json::to_string_pretty(&backup)?)?;
+    fs::remove_file(&legacy_path)?;
+    eprintln!(
+        "Moved the config from {} to {}, the old file is kept as {}",
+        legacy_path.to_string_lossy(),
+        settings_path.to_string_lossy(),
+        backup_path.to_string_lossy()
+    );
+
+    Ok(())
+}
+
+fn read_config(settings: &str, cache: Cache) -> Result<Config> {
+    let mut cfg = toml::from_str::<Config>(settings)?;
+    cache.apply(&mut cfg);
+
+    cfg.price_decimals = cfg.price_decimals.min(MAX_DECIMALS);
+    cfg.volume_decimals = cfg.volume_decimals.min(MAX_DECIMALS);
+    cfg.supersample = cfg.supersample.clamp(1, MAX_SUPERSAMPLE);
+
+    if !(0.0..=100.0).contains(&cfg.quick_sell_percentile) {
+        anyhow::bail!(
+            "quick_sell_percentile has to be between 0 and 100, got {}",
+            cfg.quick_sell_percentile
+        );
+    }
+
+    for (name, value) in [
+        ("min_brightness", cfg.min_brightness),
+        ("min_contrast", cfg.min_contrast),
+    ]
+    .iter()
+    {
+        if !(0.0..=1.0).contains(value) {
+            anyhow::bail!("{} has to be between 0 and 1, got {}", name, value);
+        }
+    }
+
+    if let Some(output_template) = &cfg.output_template {
+        for name in template::unknown_placeholders(output_template) {
+            eprintln!(
+                "Unknown placeholder '{{{}}}' in output_template, it is left empty (known ones are: {})",
+                name,
+                template::PLACEHOLDERS.join(", ")
+            );
+        }
+    }
+
+    if cfg.capture_window.is_some() && cfg.monitor.is_some() {
+        anyhow::bail!("Set either capture_window or monitor, the window is found on any monitor");
+    }
+
+    if let Some(locale) = &cfg.locale {
+        if Locale::from_name(locale).is_err() {
+            anyhow::bail!("Unknown locale '{}'", locale);
+        }
+    }
+
+    check_hotkeys(&cfg.hotkeys)?;
+    for hotkey in &cfg.hotkeys {
+        if let Some(layout) = &hotkey.layout {
+            cfg.layout(Some(layout))
+                .map_err(|e| anyhow::anyhow!("Hotkey '{}': {}", hotkey.key, e))?;
+        }
+    }
+
+    if let Some(language) = &cfg.game_language {
+        check_language(language)?;
+    }
+
+    check_platform(&cfg.platform)?;
+    if let Some(platform) = &cfg.price_platform {
+        check_platform(platform)?;
+    }
+
+    for (name, profile) in &cfg.profiles {
+        // the name is part of the cache file name
+        if name == DEFAULT_PROFILE
+            || name.is_empty()
+            || !name.chars().all(|x| x.is_ascii_alphanumeric() || x == '-' || x == '_')
+        {
+            anyhow::bail!(
+                "Invalid profile name '{}', use letters, digits, - and _ (other than '{}')",
+                name,
+                DEFAULT_PROFILE
+            );
+        }
+        check_platform(&profile.platform)
+            .map_err(|e| anyhow::anyhow!("profile '{}': {}", name, e))?;
+        if let Some(language) = &profile.game_language {
+            check_language(language).map_err(|e| anyhow::anyhow!("profile '{}': {}", name, e))?;
+        }
+    }
+    if let Some(profile) = &cfg.profile {
+        cfg.check_profile(profile)?;
+    }
+
+    Ok(cfg)
+}
+
+fn check_language(language: &str) -> Result<()> {
+    if !wfm_rs::LANGUAGES.contains(&language) {
+        anyhow::bail!(
+            "Unsupported game_language '{}', expected one of: {}",
+            language,
+            wfm_rs::LANGUAGES.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Fails unless the platform is one of `wfm_rs::PLATFORMS`
+pub fn check_platform(platform: &str) -> Result<()> {
+    if !wfm_rs::PLATFORMS.contains(&platform) {
+        anyhow::bail!(
+            "Unsupported platform '{}', expected one of: {}",
+            platform,
+            wfm_rs::PLATFORMS.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Removes leftover screenshots and makes the item list refresh on the next
+/// run, plus the extracted tessdata with `all`. Returns what was cleared.
+pub fn clear_cache(all: bool) -> Result<Vec<String>> {
+    let mut cleared = Vec::new();
+
+    let screenshot_path = screenshot_path()?;
+    if screenshot_path.exists() {
+        let mut count = 0;
+        for entry in fs::read_dir(&screenshot_path)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                fs::remove_dir_all(&path)?;
+            } else {
+                fs::remove_file(&path)?;
+            }
+            count += 1;
+        }
+        cleared.push(format!(
+            "{} file(s) in {}",
+            count,
+            screenshot_path.to_string_lossy()
+        ));
+    }
+
+    let thumbnail_path = thumbnail_path()?;
+    if thumbnail_path.exists() {
+        fs::remove_dir_all(&thumbnail_path)?;
+        cleared.push(thumbnail_path.to_string_lossy().to_string());
+    }
+
+    migrate_legacy_config()?;
+    for path in cache_paths()? {
+        let mut cache = read_cache(&path)?;
+        cache.items_timestamp = 0;
+        write_cache(&path, &cache)?;
+        cleared.push(format!("item cache timestamp in {}", path.to_string_lossy()));
+    }
+
+    let mut tessdata_path = cache_dir()?;
+    tessdata_path.push(DATA_TESSDATA_DIR);
+    if all && tessdata_path.exists() {
+        fs::remove_dir_all(&tessdata_path)?;
+        cleared.push(tessdata_path.to_string_lossy().to_string());
+    }
+
+    Ok(cleared)
+}
+
+/// Makes the profile the one used without `--profile`
+pub fn switch_profile(name: &str) -> Result<()> {
+    load()?.check_profile(name)?;
+    update_settings(|doc| {
+        if name == DEFAULT_PROFILE {
+            doc.remove("profile");
+            Ok(())
+        } else {
+            set_setting(doc, &["profile"], toml_edit::value(name))
+        }
+    })
+}
+
+/// Adds the layout to the config file as `name`, replacing any layout of that name
+pub fn save_layout(name: &str, layout: &Layout) -> Result<()> {
+    let layout = toml_item(toml::Value::try_from(layout)?);
+    update_settings(|doc| set_setting(doc, &["layouts", name], layout))
+}
+
+/// Binds the chord to scanning with the default layout in the config file,
+/// replacing the key that was bound to it
+pub fn set_hotkey(chord: &Chord) -> Result<()> {
+    let mut cfg = load()?;
+    let key = chord.to_string();
+    match cfg.hotkeys.iter_mut().find(|x| x.layout.is_none()) {
+        Some(hotkey) => hotkey.key = key,
+        None => cfg.hotkeys.insert(0, Hotkey { key, layout: None }),
+    }
+    check_hotkeys(&cfg.hotkeys)?;
+    let hotkeys = toml_item(toml::Value::try_from(&cfg.hotkeys)?);
+    update_settings(|doc| set_setting(doc, &["hotkeys"], hotkeys))
+}
+
+/// Fetches the item list from the API and persists it to the cache
+pub async fn refresh_items(cfg: &mut Config) -> Result<()> {
+    let mut items = fetch_items(cfg, None).await?;
+    let language = cfg.game_language().map(String::from);
+    cfg.localized_items = match &language {
+        Some(language) => {
+            let mut localized = fetch_items(cfg, Some(language)).await?;
+            fix_localized_items(&items, &mut localized);
+            localized
+        }
+        None => Vec::new(),
+    };
+    fix_items(&mut items);
+    cfg.items = items;
+    cfg.localized_items_language = language;
+    cfg.items_timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs();
+    write_cache(&cfg.cache_path()?, &Cache::of(cfg))?;
+
+    Ok(())
+}
+
+/// Fetches the item list, in `language` if given, logging in again first if
+/// the token expired
+async fn fetch_items(cfg: &mut Config, language: Option<&str>) -> Result<Vec<ShortItem>> {
+    let user = |cfg: &Config| match language {
+        Some(language) => cfg.user().with_language(language),
+        None => cfg.user(),
+    };
+    match user(cfg).get_items().await {
+        Err(e) if cfg.logged_in() && is_expired_login(&e) => {
+            relogin(cfg).await?;
+            user(cfg).get_items().await
+        }
+        x => x,
+    }
+}
+
+/// Downloads the tesseract data of `language` to the cache directory, unless
+/// it's already there. A download that was cut off isn't kept, so it's tried
+/// again next time.
+async fn download_tessdata(language: &str) -> Result<()> {
+    let mut path = cache_dir()?;
+    path.push(DATA_TESSDATA_DIR);
+    path.push(format!("{}.traineddata", language));
+    if path.exists() {
+        return Ok(());
+    }
+
+    print!("Downloading the {} tesseract data...   ", language);
+    let url = format!("{}/{}.traineddata", TESSDATA_URL, language);
+    let response = reqwest::get(&url).await?.error_for_status()?;
+    let expected_size = response.content_length();
+    let data = response.bytes().await?;
+    if data.len() < MIN_TESSDATA_SIZE || expected_size.map_or(false, |x| x != data.len() as u64) {
+        anyhow::bail!("the download is incomplete, got {} bytes", data.len());
+    }
+    fs::create_dir_all(path.parent().unwrap())?;
+    let mut tmp_path = path.clone();
+    tmp_path.set_extension("traineddata.tmp");
+    fs::write(&tmp_path, &data)?;
+    fs::rename(tmp_path, path)?;
+    println!("success!");
+
+    Ok(())
+}
+
+/// Logs in to warframe.market, storing the token in the cache and the
+/// platform in the config file
+pub async fn login(cfg: &mut Config) -> Result<()> {
+    let (token, platform) = login_process().await?;
+    update_settings(|doc| set_login_settings(doc, cfg.active_profile.as_deref(), &platform))?;
+    cfg.jwt_token = token;
+    cfg.platform = platform;
+    cfg.anonymous = false;
+    cfg.run_anonymous = false;
+    if cfg.use_keyring {
+        store_keyring_token(cfg);
+    }
+    write_cache(&cfg.cache_path()?, &Cache::of(cfg))
+}
+
+/// Sets the platform of the account that was logged in to, the top level one
+/// or that of `profile`. Logging in is also what turns anonymous mode off again.
+fn set_login_settings(doc: &mut toml_edit::Document, profile: Option<&str>, platform: &str) -> Result<()> {
+    match profile {
+        Some(name) => set_setting(doc, &["profiles", name, "platform"], toml_edit::value(platform))?,
+        None => set_setting(doc, &["platform"], toml_edit::value(platform))?,
+    }
+    if doc.as_table().contains_key("anonymous") {
+        set_setting(doc, &["anonymous"], toml_edit::value(false))?;
+    }
+
+    Ok(())
+}
+
+/// Whether the API rejected the request because the login token expired
+pub fn is_expired_login(e: &anyhow::Error) -> bool {
+    wfm_rs::error_status(e) == Some(401)
+}
+
+/// Asks to log in again once the token expired. The password isn't stored,
+/// so there is no way around asking, and without a terminal to ask on (such
+/// as in the daemon) it fails instead.
+pub async fn relogin(cfg: &mut Config) -> Result<()> {
+    if !cfg.interactive {
+        anyhow::bail!("The warframe.market login has expired, run `wfm_cli login` to log in again");
+    }
+    eprintln!("The warframe.market login has expired, log in again to continue");
+    login(cfg).await
+}
+
+// guide user through login process, returning the token and platform
+async fn login_process() -> Result<(JwtToken, String)> {
+    eprintln!("You need to log in with your warframe.market account!");
+    eprintln!("This program does not store your e-mail and/or password, they are both only used once, to log into the warframe.market API.");
+    eprintln!("The only thing related to your account this program stores is the token received from the API.");
 
     let email = prompt("E-mail:");
     let password = prompt("Password:");
-    let platform = prompt("Platform (pc, xbox or ps4):");
+    let platform = loop {
+        let platform = prompt(&format!("Platform ({}):", wfm_rs::PLATFORMS.join(", ")));
+        if wfm_rs::PLATFORMS.contains(&platform.as_str()) {
+            break platform;
+        }
+        eprintln!("'{}' is not a supported platform!", platform);
+    };
 
-    println!("\n");
+    eprintln!("\n");
 
-    print!("Fetching token from API...   ");
+    eprint!("Fetching token from API...   ");
     let user = wfm_rs::User::login(&email, &password, &platform, "en").await?;
-    println!("success!");
+    eprintln!("success!");
 
-    Ok(user._jwt_token())
+    Ok((JwtToken(user._jwt_token()), platform))
 }
 
 fn prompt(text: &str) -> String {
-    println!("\n{}", text);
+    eprintln!("\n{}", text);
     text_io::read!("{}\n")
 }
 
-fn write_config_to_file(path: &PathBuf, config: &Config) -> Result<()> {
-    let mut file = fs::OpenOptions::new().write(true).open(path)?;
-    let config_str = serde_json::to_string(config)?;
-    let bytes = config_str.as_bytes();
-    let written = file.write(&bytes)?;
-    if written < bytes.len() {
-        anyhow::bail!("Not all bytes written!");
+/// Removes the lock file again when dropped
+struct ConfigLock(PathBuf);
+
+impl ConfigLock {
+    /// Waits for another instance writing the config at the same time to
+    /// finish, failing if it takes too long. The directory of the config is
+    /// created if it's missing.
+    fn acquire(config_path: &Path) -> Result<ConfigLock> {
+        if let Some(parent) = config_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut path = config_path.as_os_str().to_owned();
+        path.push(".lock");
+        let path = PathBuf::from(path);
+
+        for _ in 0..LOCK_ATTEMPTS {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(ConfigLock(path)),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    std::thread::sleep(LOCK_RETRY_DELAY)
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        anyhow::bail!(
+            "Another instance of wfm_cli is writing the config, remove {} if none is running",
+            path.to_string_lossy()
+        )
+    }
+}
+
+impl Drop for ConfigLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn write_locked(path: &Path, contents: &str) -> Result<()> {
+    let _lock = ConfigLock::acquire(path)?;
+    write_file(path, contents)
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    file.write_all(contents.as_bytes())?;
+
+    Ok(())
+}
+
+/// The settings that differ from the defaults, so a new config file only
+/// holds what was actually chosen
+fn settings_toml(config: &Config) -> Result<String> {
+    // through a toml::Value, which puts the tables after the plain values like TOML requires
+    let mut value = toml::Value::try_from(config)?;
+    let defaults = toml::Value::try_from(Config::default())?;
+    if let (Some(table), Some(defaults)) = (value.as_table_mut(), defaults.as_table()) {
+        let unchanged: Vec<String> = table
+            .iter()
+            .filter(|(key, value)| defaults.get(key.as_str()) == Some(value))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in unchanged {
+            table.remove(&key);
+        }
     }
+
+    Ok(toml::to_string_pretty(&value)?)
+}
+
+/// Changes the config file with `edit`, leaving the rest of it as it was,
+/// comments and the order of the settings included
+fn update_settings(edit: impl FnOnce(&mut toml_edit::Document) -> Result<()>) -> Result<()> {
+    let path = config_path()?;
+    let _lock = ConfigLock::acquire(&path)?;
+
+    let mut doc = match fs::read_to_string(&path) {
+        Ok(x) => x
+            .parse::<toml_edit::Document>()
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.to_string_lossy(), e))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => toml_edit::Document::new(),
+        Err(e) => return Err(e.into()),
+    };
+    edit(&mut doc)?;
+
+    write_file(&path, &doc.to_string())
+}
+
+/// Sets the setting at `path` (e.g. `["layouts", "1440p"]`), adding the
+/// tables on the way that are missing
+fn set_setting(doc: &mut toml_edit::Document, path: &[&str], item: toml_edit::Item) -> Result<()> {
+    let (key, tables) = path.split_last().unwrap();
+    let mut table: &mut dyn toml_edit::TableLike = doc.as_table_mut();
+    for name in tables {
+        table = table
+            .entry(name)
+            .or_insert_with(|| {
+                // only the tables below it get a header
+                let mut table = toml_edit::Table::new();
+                table.set_implicit(true);
+                toml_edit::Item::Table(table)
+            })
+            .as_table_like_mut()
+            .ok_or_else(|| anyhow::anyhow!("'{}' in the config isn't a table", name))?;
+    }
+    table.insert(key, item);
+
     Ok(())
 }
 
+/// The value as it's written to the config file, tables (and arrays of them)
+/// getting headers of their own
+fn toml_item(value: toml::Value) -> toml_edit::Item {
+    match value {
+        toml::Value::Table(values) => {
+            let mut table = toml_edit::Table::new();
+            for (key, value) in values {
+                table.insert(&key, toml_item(value));
+            }
+            toml_edit::Item::Table(table)
+        }
+        toml::Value::Array(values) if !values.is_empty() && values.iter().all(toml::Value::is_table) => {
+            let mut tables = toml_edit::ArrayOfTables::new();
+            for value in values {
+                if let toml_edit::Item::Table(table) = toml_item(value) {
+                    tables.push(table);
+                }
+            }
+            toml_edit::Item::ArrayOfTables(tables)
+        }
+        value => toml_edit::value(toml_value(value)),
+    }
+}
+
+fn toml_value(value: toml::Value) -> toml_edit::Value {
+    match value {
+        toml::Value::String(x) => x.into(),
+        toml::Value::Integer(x) => x.into(),
+        toml::Value::Float(x) => x.into(),
+        toml::Value::Boolean(x) => x.into(),
+        toml::Value::Datetime(x) => x
+            .to_string()
+            .parse()
+            .unwrap_or_else(|_| x.to_string().into()),
+        toml::Value::Array(values) => values
+            .into_iter()
+            .map(toml_value)
+            .collect::<toml_edit::Array>()
+            .into(),
+        toml::Value::Table(values) => values
+            .into_iter()
+            .map(|(key, value)| (key, toml_value(value)))
+            .collect::<toml_edit::InlineTable>()
+            .into(),
+    }
+}
+
+fn write_settings(config: &Config) -> Result<()> {
+    write_locked(&config_path()?, &settings_toml(config)?)
+}
+
+fn write_cache(path: &Path, cache: &Cache) -> Result<()> {
+    write_locked(path, &serde_json::to_string(cache)?)
+}
+
 fn fix_items(items: &mut Vec<ShortItem>) {
     for i in items.iter_mut() {
-        if i.item_name.contains("Neuroptics")
-            | i.item_name.contains("Systems")
-            | i.item_name.contains("Chassis")
-        {
-            i.item_name.push_str(" blueprint")
+        i.item_name = normalize_item_name(&i.item_name);
+    }
+}
+
+/// Turns an API item name into the name shown on the reward screen. Warframe
+/// component blueprints are called e.g. "Mesa Prime Neuroptics" by the API,
+/// but "Mesa Prime Neuroptics Blueprint" in game.
+pub fn normalize_item_name(name: &str) -> String {
+    if name.contains("Neuroptics") | name.contains("Systems") | name.contains("Chassis") {
+        format!("{} blueprint", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Adds the translated "Blueprint" to the localized names of the items
+/// `normalize_item_name` adds it to, going by the id. The word, and whether it
+/// comes before or after the name, is taken from a main blueprint like "Mesa
+/// Prime Blueprint", compared to the localized name of the same item. If no
+/// such name has the English one's other words, the names are left as they are.
+fn fix_localized_items(items: &[ShortItem], localized: &mut [ShortItem]) {
+    let localized_name = |id: &str| {
+        localized
+            .iter()
+            .find(|x| !x.id.is_empty() && x.id == id)
+            .map(|x| x.item_name.clone())
+    };
+    let blueprint = items.iter().find_map(|item| {
+        let name = item.item_name.strip_suffix(" Blueprint")?;
+        let localized = localized_name(&item.id)?;
+        if let Some(word) = localized.strip_prefix(&format!("{} ", name)) {
+            Some((word.to_string(), false))
+        } else {
+            localized
+                .strip_suffix(&format!(" {}", name))
+                .map(|word| (word.to_string(), true))
+        }
+    });
+    let (word, before) = match blueprint {
+        Some(x) => x,
+        None => return,
+    };
+
+    for item in items {
+        if normalize_item_name(&item.item_name) == item.item_name || item.id.is_empty() {
+            continue;
+        }
+        if let Some(x) = localized.iter_mut().find(|x| x.id == item.id) {
+            x.item_name = if before {
+                format!("{} {}", word, x.item_name)
+            } else {
+                format!("{} {}", x.item_name, word)
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_jwt_token_redaction() {
+        let token = JwtToken("JWT secret".to_string());
+        assert_eq!(format!("{} {:?}", token, token), "<redacted> JwtToken(<redacted>)");
+        assert_eq!(token.redact("bad token JWT secret"), "bad token <redacted>");
+        assert_eq!(JwtToken::default().redact("no token"), "no token");
+    }
+
+    #[test]
+    fn test_normalize_item_name() {
+        let cases = [
+            ("Mesa Prime Neuroptics", "Mesa Prime Neuroptics blueprint"),
+            ("Ash Prime Systems", "Ash Prime Systems blueprint"),
+            ("Rhino Prime Chassis", "Rhino Prime Chassis blueprint"),
+            ("Mesa Prime Blueprint", "Mesa Prime Blueprint"),
+            ("Akstiletto Prime Barrel", "Akstiletto Prime Barrel"),
+            ("Forma Blueprint", "Forma Blueprint"),
+        ];
+
+        for (name, expected) in cases.iter() {
+            assert_eq!(normalize_item_name(name), *expected);
+        }
+    }
+    #[test]
+    fn test_stats_platform() {
+        let mut cfg = Config::default();
+        assert_eq!(cfg.stats_platform(), cfg.platform);
+        cfg.price_platform = Some("ps4".to_string());
+        assert_eq!(cfg.stats_platform(), "ps4");
+        cfg.run_platform = Some("switch".to_string());
+        assert_eq!(cfg.stats_platform(), "switch");
+        assert!(check_platform("switch").is_ok());
+        assert!(check_platform("gamecube").is_err());
+    }
+
+    #[test]
+    fn test_settings_toml() {
+        let mut cfg = Config {
+            items: vec![ShortItem {
+                url_name: "lex_prime_barrel".to_string(),
+                thumb: String::new(),
+                id: String::new(),
+                item_name: "Lex Prime Barrel".to_string(),
+            }],
+            jwt_token: JwtToken("JWT secret".to_string()),
+            ..Default::default()
+        };
+        cfg.layouts.insert("1440p".to_string(), Layout::default());
+
+        let settings = settings_toml(&cfg).unwrap();
+        assert!(!settings.contains("lex_prime_barrel"));
+        assert!(!settings.contains("JWT secret"));
+        // only what differs from the defaults is written
+        assert!(!settings.contains("platform"));
+        assert_eq!(settings_toml(&Config::default()).unwrap(), "");
+
+        let read = read_config(&settings, Cache::of(&cfg)).unwrap();
+        assert_eq!(read.items.len(), 1);
+        assert_eq!(read.jwt_token, cfg.jwt_token);
+        assert_eq!(read.hotkeys, cfg.hotkeys);
+        assert_eq!(read.platform, cfg.platform);
+        assert!(read.layouts.contains_key("1440p"));
+    }
+
+    #[test]
+    fn test_set_setting() {
+        let mut doc = "# my settings\nprice_decimals = 1 # rounded\n\n[match_threshold]\nmin_confidence = 0.6\n\n[profiles.switch]\nplatform = \"switch\"\n"
+            .parse::<toml_edit::Document>()
+            .unwrap();
+        set_setting(&mut doc, &["profile"], toml_edit::value("switch")).unwrap();
+        set_setting(
+            &mut doc,
+            &["layouts", "1440p"],
+            toml_item(toml::Value::try_from(Layout::default()).unwrap()),
+        )
+        .unwrap();
+        let hotkeys = vec![Hotkey {
+            key: "Ctrl+F6".to_string(),
+            layout: Some("1440p".to_string()),
+        }];
+        set_setting(&mut doc, &["hotkeys"], toml_item(toml::Value::try_from(&hotkeys).unwrap())).unwrap();
+
+        let settings = doc.to_string();
+        assert!(settings.starts_with("# my settings\nprice_decimals = 1 # rounded\nprofile = \"switch\"\n"));
+        assert!(settings.contains("[match_threshold]\nmin_confidence = 0.6\n"));
+        assert!(!settings.contains("[layouts]\n"));
+
+        let read = read_config(&settings, Cache::default()).unwrap();
+        assert_eq!(read.price_decimals, 1);
+        assert_eq!(read.profile.as_deref(), Some("switch"));
+        assert_eq!(read.hotkeys, hotkeys);
+        assert!(read.layouts.contains_key("1440p"));
+
+        assert!(set_setting(&mut doc, &["profile", "name"], toml_edit::value("x")).is_err());
+    }
+
+    #[test]
+    fn test_set_login_settings() {
+        let mut doc = "anonymous = true\nno_refresh = false\n\n[profiles.switch]\ngame_language = \"de\"\n"
+            .parse::<toml_edit::Document>()
+            .unwrap();
+        set_login_settings(&mut doc, None, "xbox").unwrap();
+        set_login_settings(&mut doc, Some("switch"), "switch").unwrap();
+        let settings = doc.to_string();
+        assert!(settings.contains("no_refresh = false\n"));
+
+        let read = read_config(&settings, Cache::default()).unwrap();
+        assert!(!read.anonymous);
+        assert_eq!(read.platform, "xbox");
+        assert_eq!(read.profiles["switch"].platform, "switch");
+        assert_eq!(read.profiles["switch"].game_language.as_deref(), Some("de"));
+
+        let mut doc = toml_edit::Document::new();
+        set_login_settings(&mut doc, None, "pc").unwrap();
+        assert_eq!(doc.to_string(), "platform = \"pc\"\n");
+    }
+
+    #[test]
+    fn test_profiles() {
+        let mut cfg = Config::default();
+        cfg.profiles.insert(
+            "switch".to_string(),
+            Profile {
+                platform: "switch".to_string(),
+                game_language: Some("de".to_string()),
+            },
+        );
+        assert!(cfg.use_profile("xbox").is_err());
+        cfg.use_profile("switch").unwrap();
+        assert_eq!(cfg.active_profile.as_deref(), Some("switch"));
+        assert_eq!(cfg.platform, "switch");
+        assert_eq!(cfg.game_language(), Some("de"));
+
+        assert!(read_config("[profiles.default]\nplatform = \"ps4\"", Cache::default()).is_err());
+        assert!(read_config("profile = \"ps4\"", Cache::default()).is_err());
+    }
+
+    #[test]
+    fn test_cache_token_in_keyring() {
+        let mut cfg = Config {
+            jwt_token: JwtToken("JWT secret".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(Cache::of(&cfg).jwt_token.expose(), "JWT secret");
+
+        cfg.token_in_keyring = true;
+        assert_eq!(Cache::of(&cfg).jwt_token.expose(), "");
+        assert!(!serde_json::to_string(&Cache::of(&cfg)).unwrap().contains("secret"));
+    }
+
+    #[test]
+    fn test_anonymous() {
+        let mut cfg = Config::default();
+        assert!(!cfg.logged_in());
+
+        cfg.jwt_token = JwtToken("JWT secret".to_string());
+        assert!(cfg.logged_in());
+        assert_eq!(cfg.api_token(), "JWT secret");
+
+        cfg.run_anonymous = true;
+        assert!(!cfg.logged_in());
+        assert_eq!(cfg.api_token(), "");
+    }
+
+    #[test]
+    fn test_is_expired_login() {
+        assert!(is_expired_login(&wfm_rs::StatusError { status: 401 }.into()));
+        assert!(!is_expired_login(&wfm_rs::StatusError { status: 404 }.into()));
+        assert!(!is_expired_login(&anyhow::anyhow!("timed out")));
+    }
+
+    #[test]
+    fn test_check_hotkeys() {
+        let hotkey = |key: &str| Hotkey {
+            key: key.to_string(),
+            layout: None,
+        };
+
+        assert!(check_hotkeys(&default_hotkeys()).is_ok());
+        assert!(check_hotkeys(&[hotkey("F6"), hotkey("Ctrl+F6"), hotkey("Ctrl+F7")]).is_ok());
+        assert!(check_hotkeys(&[hotkey("f7")]).is_err());
+        assert!(check_hotkeys(&[hotkey("Ctrl+Alt+F6"), hotkey("alt+ctrl+f6")]).is_err());
+        assert!(check_hotkeys(&[hotkey("Super+F6")]).is_err());
+    }
+
+    #[test]
+    fn test_fix_localized_items() {
+        let item = |id: &str, name: &str| ShortItem {
+            url_name: String::new(),
+            thumb: String::new(),
+            id: id.to_string(),
+            item_name: name.to_string(),
+        };
+        let items = vec![
+            item("1", "Mesa Prime Blueprint"),
+            item("2", "Mesa Prime Neuroptics"),
+            item("3", "Lex Prime Barrel"),
+        ];
+        let names = |localized: &[ShortItem]| -> Vec<String> {
+            localized.iter().map(|x| x.item_name.clone()).collect()
+        };
+
+        let mut localized = vec![
+            item("1", "Mesa Prime Blaupause"),
+            item("2", "Mesa Prime Neuroptik"),
+            item("3", "Lex Prime Lauf"),
+        ];
+        fix_localized_items(&items, &mut localized);
+        assert_eq!(
+            names(&localized),
+            ["Mesa Prime Blaupause", "Mesa Prime Neuroptik Blaupause", "Lex Prime Lauf"]
+        );
+
+        let mut localized = vec![item("1", "Plan de Mesa Prime"), item("2", "Neuroptique de Mesa Prime")];
+        fix_localized_items(&items, &mut localized);
+        assert_eq!(
+            names(&localized),
+            ["Plan de Mesa Prime", "Plan de Neuroptique de Mesa Prime"]
+        );
+
+        // the word can't be told apart from the name
+        let mut localized = vec![item("1", "Меса Прайм: чертёж"), item("2", "Меса Прайм: нейрооптика")];
+        fix_localized_items(&items, &mut localized);
+        assert_eq!(names(&localized), ["Меса Прайм: чертёж", "Меса Прайм: нейрооптика"]);
+    }
+
+    #[test]
+    fn test_game_language() {
+        let item = |name: &str| ShortItem {
+            url_name: "lex_prime_barrel".to_string(),
+            thumb: String::new(),
+            id: String::new(),
+            item_name: name.to_string(),
+        };
+        let mut cfg = Config {
+            items: vec![item("Lex Prime Barrel")],
+            localized_items: vec![item("Lex Prime Lauf")],
+            ..Default::default()
+        };
+
+        cfg.game_language = Some("en".to_string());
+        assert_eq!(cfg.tesseract_language(), None);
+        assert_eq!(cfg.ocr_items().len(), 2);
+
+        cfg.game_language = Some("de".to_string());
+        assert_eq!(cfg.tesseract_language(), Some("deu"));
+        let ocr_items = cfg.ocr_items();
+        assert_eq!(ocr_items.len(), 2);
+        assert_eq!(ocr_items[1].item_name, "Forma Blueprint");
+        assert_eq!(
+            cfg.canonical_item(ocr_items[0].clone()).item_name,
+            "Lex Prime Barrel"
+        );
+
+        // every game language but English has a tesseract language
+        for language in wfm_rs::LANGUAGES.iter().filter(|x| **x != "en") {
+            cfg.game_language = Some(language.to_string());
+            assert!(cfg.tesseract_language().is_some(), "{}", language);
         }
     }
 }
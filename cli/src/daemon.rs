@@ -0,0 +1,108 @@
+use crate::{config::Config, history, scan::Scanner, util::unix_timestamp};
+use anyhow::Result;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tokio::sync::Mutex;
+use wfm_rs::User;
+
+struct Daemon {
+    config: Config,
+    user: User,
+    scanner: Scanner,
+    /// When the daemon started, which the scans are recorded under
+    session: u64,
+}
+
+/// Serves scans over HTTP: every `POST /scan` captures the screen and responds
+/// with the scan result as JSON
+pub async fn run(mut config: Config, user: User, scanner: Scanner, addr: SocketAddr) -> Result<()> {
+    // nobody is there to type the login in, even when started from a terminal
+    config.interactive = false;
+    let token = Arc::new(config.daemon_token.clone());
+    let daemon = Arc::new(Mutex::new(Daemon {
+        config,
+        user,
+        scanner,
+        session: unix_timestamp()?,
+    }));
+
+    let make_service = make_service_fn(move |_| {
+        let daemon = daemon.clone();
+        let token = token.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| handle(daemon.clone(), token.clone(), req)))
+        }
+    });
+
+    eprintln!("Listening on http://{}", addr);
+    Server::try_bind(&addr)?.serve(make_service).await?;
+
+    Ok(())
+}
+
+async fn handle(
+    daemon: Arc<Mutex<Daemon>>,
+    token: Arc<Option<String>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    // rejected requests are answered right away, even while a scan is running
+    if let Some(token) = &*token {
+        let expected = format!("Bearer {}", token);
+        let provided = req.headers().get(AUTHORIZATION).map_or(&[][..], |x| x.as_bytes());
+        // compared in constant time, so the token can't be guessed byte by byte
+        if !bool::from(provided.ct_eq(expected.as_bytes())) {
+            return Ok(error_response(StatusCode::UNAUTHORIZED, "Invalid token"));
+        }
+    }
+
+    if req.method() != Method::POST || req.uri().path() != "/scan" {
+        return Ok(error_response(StatusCode::NOT_FOUND, "Not found"));
+    }
+
+    // only one scan can run at a time, since they all share the screen and OCR workers
+    let mut daemon = daemon.lock().await;
+    let Daemon {
+        config,
+        user,
+        scanner,
+        session,
+    } = &mut *daemon;
+
+    let response = match scanner.scan(config, user).await {
+        Ok(scan) => match serde_json::to_string(&scan) {
+            Ok(json) if config.record_history => {
+                let entry = history::HistoryEntry::new(*session, &scan);
+                if let Err(e) = history::record(&entry, &config.log_rotation) {
+                    eprintln!("Failed to record the scan: {}", e);
+                }
+                json_response(StatusCode::OK, json)
+            }
+            Ok(json) => json_response(StatusCode::OK, json),
+            Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+        },
+        Err(e) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &config.redact(&e.to_string()),
+        ),
+    };
+
+    Ok(response)
+}
+
+fn json_response(status: StatusCode, body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    response
+}
+
+fn error_response(status: StatusCode, msg: &str) -> Response<Body> {
+    json_response(status, serde_json::json!({ "error": msg }).to_string())
+}
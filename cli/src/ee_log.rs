@@ -0,0 +1,146 @@
+use anyhow::Result;
+use crossbeam_channel::Receiver;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// How often the log is checked for new lines
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Lines the game logs as the relic reward screen opens, the same ones WFInfo
+/// triggers on
+const REWARD_SCREEN_LINES: [&str; 2] = ["Pause countdown done", "Got rewards"];
+
+/// Steam app id of Warframe, naming its Proton prefix
+#[cfg(target_os = "linux")]
+const WARFRAME_APP_ID: &str = "230410";
+
+/// Where the game writes its log, unless `ee_log_path` says otherwise
+#[cfg(windows)]
+pub fn default_path() -> Option<PathBuf> {
+    let mut path = PathBuf::from(std::env::var_os("LOCALAPPDATA")?);
+    path.push("Warframe/EE.log");
+    Some(path)
+}
+
+/// Where the game writes its log when it runs through Steam's Proton, unless
+/// `ee_log_path` says otherwise
+#[cfg(target_os = "linux")]
+pub fn default_path() -> Option<PathBuf> {
+    let mut path = home::home_dir()?;
+    path.push(".steam/steam/steamapps/compatdata");
+    path.push(WARFRAME_APP_ID);
+    path.push("pfx/drive_c/users/steamuser/AppData/Local/Warframe/EE.log");
+    Some(path)
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn default_path() -> Option<PathBuf> {
+    None
+}
+
+pub fn is_reward_screen(line: &str) -> bool {
+    REWARD_SCREEN_LINES.iter().any(|x| line.contains(x))
+}
+
+/// Reads the lines appended to a log since the last read
+struct LogTail {
+    path: PathBuf,
+    offset: u64,
+    /// The end of the last read, if it stopped in the middle of a line
+    partial: String,
+}
+
+impl LogTail {
+    /// Starts at the current end of the log, so only lines written from now on are read
+    fn new(path: &Path) -> LogTail {
+        LogTail {
+            path: path.to_path_buf(),
+            offset: path.metadata().map(|x| x.len()).unwrap_or(0),
+            partial: String::new(),
+        }
+    }
+
+    fn read_lines(&mut self) -> Result<Vec<String>> {
+        let mut file = File::open(&self.path)?;
+        // the game starts the log over on every launch
+        if file.metadata()?.len() < self.offset {
+            self.offset = 0;
+            self.partial.clear();
+        }
+
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        self.offset += bytes.len() as u64;
+        self.partial.push_str(&String::from_utf8_lossy(&bytes));
+
+        let mut lines: Vec<String> = self
+            .partial
+            .split('\n')
+            .map(|x| x.trim_end().to_string())
+            .collect();
+        self.partial = lines.pop().unwrap_or_default();
+        Ok(lines)
+    }
+}
+
+/// Sends whenever the game logs that the reward screen opened. The log not
+/// existing (yet) isn't an error, since the game may not be running.
+pub fn watch(path: PathBuf) -> Receiver<()> {
+    let (tx, rx) = crossbeam_channel::unbounded();
+    thread::spawn(move || {
+        let mut tail = LogTail::new(&path);
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let lines = match tail.read_lines() {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            if lines.iter().any(|x| is_reward_screen(x)) && tx.send(()).is_err() {
+                return;
+            }
+        }
+    });
+
+    rx
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    #[test]
+    fn test_log_tail() {
+        let dir = std::env::temp_dir().join(format!("wfm_cli_ee_log_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("EE.log");
+        fs::write(&log, "12.345 Sys [Info]: Got rewards\n").unwrap();
+
+        let mut tail = LogTail::new(&log);
+        assert!(tail.read_lines().unwrap().is_empty());
+
+        let mut file = OpenOptions::new().append(true).open(&log).unwrap();
+        write!(file, "20.000 Script [Info]: Pause countdown done\r\n21.0").unwrap();
+        let lines = tail.read_lines().unwrap();
+        assert_eq!(lines, vec!["20.000 Script [Info]: Pause countdown done"]);
+        assert!(is_reward_screen(&lines[0]));
+
+        writeln!(file, "00 Sys [Info]: Loading").unwrap();
+        assert_eq!(
+            tail.read_lines().unwrap(),
+            vec!["21.000 Sys [Info]: Loading"]
+        );
+
+        // started over by a new launch
+        fs::write(&log, "0.5 Sys [Info]: Started\n").unwrap();
+        assert_eq!(tail.read_lines().unwrap(), vec!["0.5 Sys [Info]: Started"]);
+        assert!(!is_reward_screen("0.5 Sys [Info]: Started"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+use crate::history::HistoryEntry;
+use anyhow::Result;
+use rust_xlsxwriter::{Format, Workbook, Worksheet};
+
+/// Days between the spreadsheet epoch (1899-12-30) and the unix epoch
+const SPREADSHEET_UNIX_EPOCH_DAYS: f64 = 25569.0;
+
+/// Writes the history to a spreadsheet, with a row per scanned item on the
+/// first sheet and a row per session on the second
+pub fn export_xlsx(entries: &[HistoryEntry], path: &str) -> Result<()> {
+    let mut workbook = Workbook::new();
+    let header = Format::new().set_bold();
+    let date = Format::new().set_num_format("yyyy-mm-dd hh:mm:ss");
+
+    let scans = workbook.add_worksheet();
+    scans.set_name("Scans")?;
+    write_header(
+        scans,
+        &header,
+        &[
+            "Session", "Time", "Slot", "Item", "Average", "Min", "Max", "Volume", "Watched",
+            "Best pick",
+        ],
+    )?;
+
+    let mut row = 1;
+    for entry in entries {
+        for (idx, item) in entry.items.iter().enumerate() {
+            scans.write_number_with_format(row, 0, spreadsheet_time(entry.session), &date)?;
+            scans.write_number_with_format(row, 1, spreadsheet_time(entry.timestamp), &date)?;
+            scans.write_number(row, 2, (item.slot + 1) as f64)?;
+            scans.write_string(row, 3, &item.item_name)?;
+            write_price(scans, row, 4, item.avg_price)?;
+            write_price(scans, row, 5, item.min_price)?;
+            write_price(scans, row, 6, item.max_price)?;
+            scans.write_number(row, 7, item.volume)?;
+            scans.write_boolean(row, 8, item.watched)?;
+            scans.write_boolean(row, 9, idx == 0)?;
+            row += 1;
+        }
+    }
+
+    let summary = workbook.add_worksheet();
+    summary.set_name("Sessions")?;
+    write_header(
+        summary,
+        &header,
+        &["Session", "Scans", "Best picks total", "Average best pick"],
+    )?;
+
+    for (idx, session) in sessions(entries).iter().enumerate() {
+        let row = idx as u32 + 1;
+        summary.write_number_with_format(row, 0, spreadsheet_time(session.session), &date)?;
+        summary.write_number(row, 1, session.scans as f64)?;
+        summary.write_number(row, 2, session.total)?;
+        summary.write_number(row, 3, session.total / session.scans as f32)?;
+    }
+
+    workbook.save(path)?;
+
+    Ok(())
+}
+
+/// The scans of a session, and the platinum of their best picks
+#[derive(Debug, PartialEq)]
+struct SessionSummary {
+    session: u64,
+    scans: usize,
+    total: f32,
+}
+
+/// Every session in the order they first show up. The scans of sessions that
+/// ran at the same time (e.g. the daemon and `watch`) are interleaved.
+fn sessions(entries: &[HistoryEntry]) -> Vec<SessionSummary> {
+    let mut sessions: Vec<SessionSummary> = Vec::new();
+    for entry in entries {
+        let idx = match sessions.iter().position(|x| x.session == entry.session) {
+            Some(idx) => idx,
+            None => {
+                sessions.push(SessionSummary {
+                    session: entry.session,
+                    scans: 0,
+                    total: 0.0,
+                });
+                sessions.len() - 1
+            }
+        };
+        sessions[idx].scans += 1;
+        sessions[idx].total += entry.best_price();
+    }
+
+    sessions
+}
+
+fn write_header(sheet: &mut Worksheet, format: &Format, columns: &[&str]) -> Result<()> {
+    for (col, name) in columns.iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, *name, format)?;
+    }
+    Ok(())
+}
+
+fn write_price(sheet: &mut Worksheet, row: u32, col: u16, price: Option<f32>) -> Result<()> {
+    if let Some(price) = price {
+        sheet.write_number(row, col, price)?;
+    }
+    Ok(())
+}
+
+fn spreadsheet_time(unix_timestamp: u64) -> f64 {
+    unix_timestamp as f64 / (24.0 * 60.0 * 60.0) + SPREADSHEET_UNIX_EPOCH_DAYS
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::history::HistoryItem;
+
+    fn entry(session: u64, best_price: Option<f32>) -> HistoryEntry {
+        HistoryEntry {
+            session,
+            timestamp: session + 60,
+            items: vec![HistoryItem {
+                item_name: "Lex Prime Barrel".to_string(),
+                url_name: "lex_prime_barrel".to_string(),
+                slot: 0,
+                avg_price: best_price,
+                min_price: best_price,
+                max_price: best_price,
+                volume: 1.0,
+                watched: false,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_sessions() {
+        let entries = [
+            entry(100, Some(10.0)),
+            entry(200, Some(40.0)),
+            entry(100, Some(20.0)),
+            entry(200, None),
+            entry(300, Some(5.0)),
+        ];
+        let summary = |session, scans, total| SessionSummary {
+            session,
+            scans,
+            total,
+        };
+
+        assert_eq!(
+            sessions(&entries),
+            vec![summary(100, 2, 30.0), summary(200, 2, 40.0), summary(300, 1, 5.0)]
+        );
+        assert!(sessions(&[]).is_empty());
+    }
+}
@@ -0,0 +1,98 @@
+use crate::{
+    config::LogRotation,
+    scan::Scan,
+    util::{history_path, rotated_path},
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+/// A scan as kept in the history file, one JSON object per line
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HistoryEntry {
+    /// When the session (the run of the program) the scan belongs to started
+    pub session: u64,
+    pub timestamp: u64,
+    /// Ranked from best to worst
+    pub items: Vec<HistoryItem>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HistoryItem {
+    pub item_name: String,
+    pub url_name: String,
+    pub slot: usize,
+    pub avg_price: Option<f32>,
+    pub min_price: Option<f32>,
+    pub max_price: Option<f32>,
+    pub volume: f32,
+    pub watched: bool,
+}
+
+impl HistoryEntry {
+    pub fn new(session: u64, scan: &Scan) -> HistoryEntry {
+        // NaN can't be represented in JSON
+        let price = |x: f32| Some(x).filter(|x| !x.is_nan());
+
+        HistoryEntry {
+            session,
+            timestamp: scan.timestamp,
+            items: scan
+                .items
+                .iter()
+                .map(|x| HistoryItem {
+                    item_name: x.item.item_name.clone(),
+                    url_name: x.item.url_name.clone(),
+                    slot: x.slot,
+                    avg_price: price(x.avg_price),
+                    min_price: price(x.min_price),
+                    max_price: price(x.max_price),
+                    volume: x.volume,
+                    watched: x.watched,
+                })
+                .collect(),
+        }
+    }
+
+    /// Platinum of the best pick
+    pub fn best_price(&self) -> f32 {
+        self.items.first().and_then(|x| x.avg_price).unwrap_or(0.0)
+    }
+}
+
+/// Appends the scan to the history file, rotating it first if it's too large
+pub fn record(entry: &HistoryEntry, rotation: &LogRotation) -> Result<()> {
+    let path = history_path()?;
+    rotation.rotate(&path)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+
+    Ok(())
+}
+
+/// Reads every scan in the history file and the rotated ones, oldest first
+pub fn load() -> Result<Vec<HistoryEntry>> {
+    let path = history_path()?;
+
+    // rotated files are numbered from newest to oldest
+    let mut files = vec![path.clone()];
+    loop {
+        let rotated = rotated_path(&path, files.len());
+        if !rotated.exists() {
+            break;
+        }
+        files.push(rotated);
+    }
+
+    let mut entries = Vec::new();
+    for file in files.iter().rev().filter(|x| x.exists()) {
+        for line in fs::read_to_string(file)?.lines() {
+            if !line.trim().is_empty() {
+                entries.push(serde_json::from_str(line)?);
+            }
+        }
+    }
+
+    Ok(entries)
+}
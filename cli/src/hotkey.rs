@@ -0,0 +1,581 @@
+use anyhow::Result;
+use crossbeam_channel::{Receiver, Sender};
+use device_query::{DeviceQuery, DeviceState, Keycode};
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+/// How often the keyboard is checked where the keys can't be grabbed
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Keys that can be held together with a hotkey, either side counting
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Modifier {
+    Ctrl,
+    Shift,
+    Alt,
+}
+
+/// In the order they are written in
+const MODIFIERS: [Modifier; 3] = [Modifier::Ctrl, Modifier::Shift, Modifier::Alt];
+
+/// Every key device_query knows besides the modifiers, which are the keys a
+/// hotkey can be bound to
+const KEYS: [Keycode; 90] = [
+    Keycode::Key0,
+    Keycode::Key1,
+    Keycode::Key2,
+    Keycode::Key3,
+    Keycode::Key4,
+    Keycode::Key5,
+    Keycode::Key6,
+    Keycode::Key7,
+    Keycode::Key8,
+    Keycode::Key9,
+    Keycode::A,
+    Keycode::B,
+    Keycode::C,
+    Keycode::D,
+    Keycode::E,
+    Keycode::F,
+    Keycode::G,
+    Keycode::H,
+    Keycode::I,
+    Keycode::J,
+    Keycode::K,
+    Keycode::L,
+    Keycode::M,
+    Keycode::N,
+    Keycode::O,
+    Keycode::P,
+    Keycode::Q,
+    Keycode::R,
+    Keycode::S,
+    Keycode::T,
+    Keycode::U,
+    Keycode::V,
+    Keycode::W,
+    Keycode::X,
+    Keycode::Y,
+    Keycode::Z,
+    Keycode::F1,
+    Keycode::F2,
+    Keycode::F3,
+    Keycode::F4,
+    Keycode::F5,
+    Keycode::F6,
+    Keycode::F7,
+    Keycode::F8,
+    Keycode::F9,
+    Keycode::F10,
+    Keycode::F11,
+    Keycode::F12,
+    Keycode::Escape,
+    Keycode::Space,
+    Keycode::Meta,
+    Keycode::Enter,
+    Keycode::Up,
+    Keycode::Down,
+    Keycode::Left,
+    Keycode::Right,
+    Keycode::Backspace,
+    Keycode::CapsLock,
+    Keycode::Tab,
+    Keycode::Home,
+    Keycode::End,
+    Keycode::PageUp,
+    Keycode::PageDown,
+    Keycode::Insert,
+    Keycode::Delete,
+    Keycode::Numpad0,
+    Keycode::Numpad1,
+    Keycode::Numpad2,
+    Keycode::Numpad3,
+    Keycode::Numpad4,
+    Keycode::Numpad5,
+    Keycode::Numpad6,
+    Keycode::Numpad7,
+    Keycode::Numpad8,
+    Keycode::Numpad9,
+    Keycode::NumpadSubtract,
+    Keycode::NumpadAdd,
+    Keycode::NumpadDivide,
+    Keycode::NumpadMultiply,
+    Keycode::Grave,
+    Keycode::Minus,
+    Keycode::Equal,
+    Keycode::LeftBracket,
+    Keycode::RightBracket,
+    Keycode::BackSlash,
+    Keycode::Semicolon,
+    Keycode::Apostrophe,
+    Keycode::Comma,
+    Keycode::Dot,
+    Keycode::Slash,
+];
+
+/// The key named like device_query names it, ignoring case
+fn parse_key(name: &str) -> Option<Keycode> {
+    KEYS.iter()
+        .find(|x| format!("{:?}", x).eq_ignore_ascii_case(name))
+        .cloned()
+}
+
+impl Modifier {
+    fn parse(name: &str) -> Option<Modifier> {
+        match name.to_lowercase().as_str() {
+            "ctrl" | "control" => Some(Modifier::Ctrl),
+            "shift" => Some(Modifier::Shift),
+            "alt" => Some(Modifier::Alt),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Modifier::Ctrl => "Ctrl",
+            Modifier::Shift => "Shift",
+            Modifier::Alt => "Alt",
+        }
+    }
+
+    fn keys(self) -> [Keycode; 2] {
+        match self {
+            Modifier::Ctrl => [Keycode::LControl, Keycode::RControl],
+            Modifier::Shift => [Keycode::LShift, Keycode::RShift],
+            Modifier::Alt => [Keycode::LAlt, Keycode::RAlt],
+        }
+    }
+
+    fn is_held(self, keys: &[Keycode]) -> bool {
+        self.keys().iter().any(|x| keys.contains(x))
+    }
+}
+
+/// A key and the modifiers held with it, written like "Ctrl+Shift+F6"
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chord {
+    modifiers: Vec<Modifier>,
+    /// One of `KEYS`, never a modifier
+    key: Keycode,
+}
+
+impl Chord {
+    pub fn parse(s: &str) -> Result<Chord> {
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let key_name = parts.pop().unwrap_or_default();
+        if key_name.is_empty() || Modifier::parse(key_name).is_some() {
+            anyhow::bail!("Hotkey '{}' has no key besides the modifiers", s);
+        }
+        let key = match parse_key(key_name) {
+            Some(x) => x,
+            None => {
+                let names: Vec<String> = KEYS.iter().map(|x| format!("{:?}", x)).collect();
+                anyhow::bail!(
+                    "Unknown key '{}' in hotkey '{}', expected one of {}",
+                    key_name,
+                    s,
+                    names.join(", ")
+                )
+            }
+        };
+
+        let mut modifiers = Vec::new();
+        for part in parts {
+            match Modifier::parse(part) {
+                Some(x) if modifiers.contains(&x) => {
+                    anyhow::bail!("Hotkey '{}' has '{}' more than once", s, part)
+                }
+                Some(x) => modifiers.push(x),
+                None => anyhow::bail!(
+                    "Unknown modifier '{}' in hotkey '{}', expected Ctrl, Shift or Alt",
+                    part,
+                    s
+                ),
+            }
+        }
+
+        Ok(Chord {
+            modifiers: MODIFIERS
+                .iter()
+                .copied()
+                .filter(|x| modifiers.contains(x))
+                .collect(),
+            key,
+        })
+    }
+
+    /// The key pressed along with the modifiers held, unless only modifiers are
+    pub fn from_keys(keys: &[Keycode]) -> Option<Chord> {
+        let key = keys
+            .iter()
+            .find(|key| !MODIFIERS.iter().any(|x| x.keys().contains(key)))?;
+
+        Some(Chord {
+            modifiers: MODIFIERS
+                .iter()
+                .copied()
+                .filter(|x| x.is_held(keys))
+                .collect(),
+            key: key.clone(),
+        })
+    }
+
+    /// Whether the key is held with exactly these modifiers, so that e.g. "F6"
+    /// and "Ctrl+F6" can scan with different layouts
+    pub fn is_held(&self, keys: &[Keycode]) -> bool {
+        keys.contains(&self.key)
+            && MODIFIERS
+                .iter()
+                .all(|x| x.is_held(keys) == self.modifiers.contains(x))
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for modifier in &self.modifiers {
+            write!(f, "{}+", modifier.name())?;
+        }
+        write!(f, "{:?}", self.key)
+    }
+}
+
+/// Sends the index of the chord whenever one is pressed, the `polled` ones
+/// counting on from `grabbed`. The `grabbed` keys are taken from the window
+/// system, so the thread waiting for them sleeps in between. The `polled` keys,
+/// and any of `grabbed` that can't be grabbed (on macOS or Wayland, or when
+/// another program has the key), are noticed by checking the keyboard every
+/// `POLL_INTERVAL` instead, which leaves them to other programs as well.
+pub fn listen(grabbed: Vec<Chord>, polled: Vec<Chord>) -> Receiver<usize> {
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let (not_grabbed_tx, not_grabbed_rx) = crossbeam_channel::bounded(1);
+    let mut to_poll: Vec<(usize, Chord)> = polled
+        .into_iter()
+        .enumerate()
+        .map(|(idx, chord)| (grabbed.len() + idx, chord))
+        .collect();
+
+    let chords = grabbed.clone();
+    let grab_tx = tx.clone();
+    thread::spawn(move || {
+        if let Err(e) = grab(&chords, &grab_tx, &not_grabbed_tx) {
+            // unless grab already told which keys it couldn't grab, and failed
+            // while waiting for the others
+            if not_grabbed_tx.try_send((0..chords.len()).collect()).is_ok() {
+                eprintln!("Polling the keyboard for the hotkeys: {}", e);
+            } else {
+                eprintln!("Stopped waiting for the grabbed hotkeys: {}", e);
+            }
+        }
+    });
+
+    // grab tells which keys it couldn't grab before it waits for any
+    for idx in not_grabbed_rx.recv().unwrap_or_default() {
+        to_poll.push((idx, grabbed[idx].clone()));
+    }
+    if !to_poll.is_empty() {
+        thread::spawn(move || poll(&to_poll, &tx));
+    }
+
+    rx
+}
+
+/// Sends a press only when the chord starts being held, like grabbed keys do
+fn poll(chords: &[(usize, Chord)], tx: &Sender<usize>) {
+    let device = DeviceState::new();
+    let mut held = vec![false; chords.len()];
+    loop {
+        let keys = device.get_keys();
+        for (held, (idx, chord)) in held.iter_mut().zip(chords) {
+            let is_held = chord.is_held(&keys);
+            if is_held && !*held && tx.send(*idx).is_err() {
+                return;
+            }
+            *held = is_held;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// The X11 keysym and Windows virtual key code of the key, for the keys that
+/// are likely to be bound
+#[cfg(any(target_os = "linux", windows))]
+fn key_codes(key: &Keycode) -> Option<(u32, u32)> {
+    let key = format!("{:?}", key).to_uppercase();
+    if let Some(n) = key.strip_prefix('F').and_then(|x| x.parse::<u32>().ok()) {
+        return match n {
+            1..=12 => Some((0xffbe + n - 1, 0x70 + n - 1)),
+            _ => None,
+        };
+    }
+    if let Some(digit) = key.strip_prefix("KEY").and_then(|x| x.parse::<u32>().ok()) {
+        return match digit {
+            0..=9 => Some((0x30 + digit, 0x30 + digit)),
+            _ => None,
+        };
+    }
+    if let [letter @ b'A'..=b'Z'] = key.as_bytes() {
+        // letter keys are mapped to the lowercase keysym
+        let letter = (letter - b'A') as u32;
+        return Some((0x61 + letter, 0x41 + letter));
+    }
+
+    match key.as_str() {
+        "SPACE" => Some((0x20, 0x20)),
+        "ENTER" => Some((0xff0d, 0x0d)),
+        "TAB" => Some((0xff09, 0x09)),
+        "ESCAPE" => Some((0xff1b, 0x1b)),
+        "INSERT" => Some((0xff63, 0x2d)),
+        "DELETE" => Some((0xffff, 0x2e)),
+        "HOME" => Some((0xff50, 0x24)),
+        "END" => Some((0xff57, 0x23)),
+        "PAGEUP" => Some((0xff55, 0x21)),
+        "PAGEDOWN" => Some((0xff56, 0x22)),
+        "GRAVE" => Some((0x60, 0xc0)),
+        _ => None,
+    }
+}
+
+/// Grabs what it can of the chords and sends presses of those, after sending
+/// the indices of the others to `not_grabbed`
+#[cfg(target_os = "linux")]
+fn grab(chords: &[Chord], tx: &Sender<usize>, not_grabbed: &Sender<Vec<usize>>) -> Result<()> {
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{ConnectionExt, GrabMode, ModMask};
+    use x11rb::protocol::Event;
+
+    const ALT_L: u32 = 0xffe9;
+    const ALT_R: u32 = 0xffea;
+    const NUM_LOCK: u32 = 0xff7f;
+
+    // XWayland only sees the keys pressed in its own windows
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        anyhow::bail!("keys can't be grabbed on Wayland");
+    }
+
+    let (conn, screen_num) = x11rb::connect(None)?;
+    let setup = conn.setup();
+    let root = setup.roots[screen_num].root;
+    let mapping = conn
+        .get_keyboard_mapping(setup.min_keycode, setup.max_keycode - setup.min_keycode + 1)?
+        .reply()?;
+    let modifier_mapping = conn.get_modifier_mapping()?.reply()?;
+
+    let keycodes = |keysym: u32| -> Vec<u8> {
+        mapping
+            .keysyms
+            .chunks(mapping.keysyms_per_keycode.max(1) as usize)
+            .enumerate()
+            .filter(|(_, x)| x.contains(&keysym))
+            .map(|(idx, _)| setup.min_keycode + idx as u8)
+            .collect()
+    };
+    // which of the eight modifiers Alt and Num Lock are depends on the keymap
+    let modifier_mask = |keysyms: &[u32]| -> u16 {
+        let keycodes: Vec<u8> = keysyms.iter().flat_map(|x| keycodes(*x)).collect();
+        modifier_mapping
+            .keycodes
+            .chunks(modifier_mapping.keycodes_per_modifier().max(1) as usize)
+            .enumerate()
+            .filter(|(_, x)| x.iter().any(|key| *key != 0 && keycodes.contains(key)))
+            .fold(0, |acc, (idx, _)| acc | 1 << idx)
+    };
+    let alt = modifier_mask(&[ALT_L, ALT_R]);
+    let mask = |modifier: &Modifier| match modifier {
+        Modifier::Ctrl => u16::from(ModMask::CONTROL),
+        Modifier::Shift => u16::from(ModMask::SHIFT),
+        Modifier::Alt => alt,
+    };
+    // Caps Lock and Num Lock don't make it a different key
+    let lock = u16::from(ModMask::LOCK);
+    let num_lock = modifier_mask(&[NUM_LOCK]);
+
+    let lock_variants = [0, lock, num_lock, lock | num_lock];
+
+    let grab_chord = |chord: &Chord| -> Result<(u8, u16)> {
+        let keysym = match key_codes(&chord.key) {
+            Some((keysym, _)) => keysym,
+            None => anyhow::bail!("'{}' can't be grabbed", chord),
+        };
+        let keycode = match keycodes(keysym).first() {
+            Some(x) => *x,
+            None => anyhow::bail!("The keyboard has no key for '{}'", chord),
+        };
+        if chord.modifiers.contains(&Modifier::Alt) && alt == 0 {
+            anyhow::bail!("Alt isn't a modifier in the keymap, so '{}' can't be grabbed", chord);
+        }
+        let modifiers = chord.modifiers.iter().map(mask).fold(0, |acc, x| acc | x);
+
+        for locks in &lock_variants {
+            let grabbed = conn
+                .grab_key(
+                    false,
+                    root,
+                    ModMask::from(modifiers | locks),
+                    keycode,
+                    GrabMode::ASYNC,
+                    GrabMode::ASYNC,
+                )?
+                .check();
+            if let Err(e) = grabbed {
+                // the variants grabbed before would keep the key from other programs
+                for locks in &lock_variants {
+                    conn.ungrab_key(keycode, root, ModMask::from(modifiers | locks))?;
+                }
+                anyhow::bail!("Failed to grab '{}', is another program using it? {:?}", chord, e);
+            }
+        }
+
+        Ok((keycode, modifiers))
+    };
+
+    let mut grabs = Vec::new();
+    let mut failed = Vec::new();
+    for (idx, chord) in chords.iter().enumerate() {
+        match grab_chord(chord) {
+            Ok(grab) => grabs.push((grab, idx)),
+            Err(e) => {
+                eprintln!("Polling the keyboard for '{}': {}", chord, e);
+                failed.push(idx);
+            }
+        }
+    }
+    conn.flush()?;
+    let _ = not_grabbed.send(failed);
+
+    let relevant = MODIFIERS.iter().map(mask).fold(0, |acc, x| acc | x);
+    loop {
+        if let Event::KeyPress(event) = conn.wait_for_event()? {
+            let pressed = (event.detail, event.state & relevant);
+            if let Some((_, idx)) = grabs.iter().find(|(grab, _)| *grab == pressed) {
+                if tx.send(*idx).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+fn grab(chords: &[Chord], tx: &Sender<usize>, not_grabbed: &Sender<Vec<usize>>) -> Result<()> {
+    use std::ptr;
+    use winapi::um::winuser::{
+        GetMessageW, RegisterHotKey, UnregisterHotKey, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT,
+        MOD_SHIFT, MSG, WM_HOTKEY,
+    };
+
+    let mut registered = Vec::new();
+    let mut failed = Vec::new();
+    for (idx, chord) in chords.iter().enumerate() {
+        let virtual_key = match key_codes(&chord.key) {
+            Some((_, x)) => x,
+            None => {
+                eprintln!("Polling the keyboard for '{}': it can't be registered", chord);
+                failed.push(idx);
+                continue;
+            }
+        };
+        let modifiers = chord.modifiers.iter().fold(MOD_NOREPEAT as u32, |acc, x| {
+            acc | match x {
+                Modifier::Ctrl => MOD_CONTROL as u32,
+                Modifier::Shift => MOD_SHIFT as u32,
+                Modifier::Alt => MOD_ALT as u32,
+            }
+        });
+
+        // without a window, the WM_HOTKEY messages go to this thread's queue
+        if unsafe { RegisterHotKey(ptr::null_mut(), idx as i32, modifiers, virtual_key) } == 0 {
+            eprintln!(
+                "Polling the keyboard for '{}': failed to register it, is another program using it?",
+                chord
+            );
+            failed.push(idx);
+        } else {
+            registered.push(idx);
+        }
+    }
+    let _ = not_grabbed.send(failed);
+
+    let mut msg: MSG = unsafe { std::mem::zeroed() };
+    while unsafe { GetMessageW(&mut msg, ptr::null_mut(), 0, 0) } > 0 {
+        if msg.message == WM_HOTKEY && tx.send(msg.wParam as usize).is_err() {
+            break;
+        }
+    }
+    for idx in registered {
+        unsafe { UnregisterHotKey(ptr::null_mut(), idx as i32) };
+    }
+
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn grab(_chords: &[Chord], _tx: &Sender<usize>, _not_grabbed: &Sender<Vec<usize>>) -> Result<()> {
+    anyhow::bail!("keys can't be grabbed on this platform")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_chord() {
+        let chord = Chord::parse("shift + ctrl+F6").unwrap();
+        assert_eq!(chord.to_string(), "Ctrl+Shift+F6");
+        assert_eq!(chord, Chord::parse("Ctrl+Shift+f6").unwrap());
+        assert_ne!(chord, Chord::parse("F6").unwrap());
+        assert_eq!(Chord::parse("F9").unwrap().to_string(), "F9");
+
+        assert!(Chord::parse("Ctrl+").is_err());
+        assert!(Chord::parse("Ctrl+Shift").is_err());
+        assert!(Chord::parse("Ctrl+Ctrl+F6").is_err());
+        assert!(Chord::parse("Hyper+F6").is_err());
+    }
+
+    #[test]
+    fn test_parse_chord_key() {
+        assert_eq!(Chord::parse("ctrl+key0").unwrap().to_string(), "Ctrl+Key0");
+        assert_eq!(Chord::parse("pageup").unwrap().to_string(), "PageUp");
+
+        let err = Chord::parse("Ctrl+F13").unwrap_err().to_string();
+        assert!(err.contains("Unknown key 'F13'"));
+        assert!(err.contains("F12, Escape, Space"));
+        assert!(Chord::parse("LShift").is_err());
+    }
+
+    #[test]
+    fn test_chord_is_held() {
+        let plain = Chord::parse("F6").unwrap();
+        let ctrl = Chord::parse("Ctrl+F6").unwrap();
+
+        assert!(plain.is_held(&[Keycode::F6]));
+        assert!(!plain.is_held(&[Keycode::RControl, Keycode::F6]));
+        assert!(ctrl.is_held(&[Keycode::RControl, Keycode::F6]));
+        assert!(ctrl.is_held(&[Keycode::F6, Keycode::LControl]));
+        assert!(!ctrl.is_held(&[Keycode::LControl]));
+        assert!(!ctrl.is_held(&[Keycode::LControl, Keycode::LShift, Keycode::F6]));
+    }
+
+    #[test]
+    fn test_chord_from_keys() {
+        assert_eq!(
+            Chord::from_keys(&[Keycode::LShift, Keycode::F9])
+                .unwrap()
+                .to_string(),
+            "Shift+F9"
+        );
+        assert!(Chord::from_keys(&[Keycode::LAlt]).is_none());
+        assert!(Chord::from_keys(&[]).is_none());
+    }
+
+    #[cfg(any(target_os = "linux", windows))]
+    #[test]
+    fn test_key_codes() {
+        assert_eq!(key_codes(&Keycode::F6), Some((0xffc3, 0x75)));
+        assert_eq!(key_codes(&Keycode::F12), Some((0xffc9, 0x7b)));
+        assert_eq!(key_codes(&Keycode::F), Some((0x66, 0x46)));
+        assert_eq!(key_codes(&Keycode::Key1), Some((0x31, 0x31)));
+        assert_eq!(key_codes(&Keycode::PageUp), Some((0xff55, 0x21)));
+        assert_eq!(key_codes(&Keycode::Numpad5), None);
+    }
+}
@@ -0,0 +1,53 @@
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use wfm_rs::response::ShortItem;
+
+/// Completes item names by (case-insensitive) prefix
+pub struct ItemCompleter {
+    names: Vec<String>,
+}
+
+impl Completer for ItemCompleter {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let prefix = line[..pos].to_lowercase();
+        let candidates = self
+            .names
+            .iter()
+            .filter(|name| name.to_lowercase().starts_with(&prefix))
+            .cloned()
+            .collect();
+
+        // candidates replace everything typed so far
+        Ok((0, candidates))
+    }
+}
+
+impl Hinter for ItemCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for ItemCompleter {}
+
+impl Validator for ItemCompleter {}
+
+impl Helper for ItemCompleter {}
+
+/// A line editor that completes the names of `items` on tab
+pub fn item_editor(items: &[ShortItem]) -> Editor<ItemCompleter> {
+    let mut names: Vec<String> = items.iter().map(|x| x.item_name.clone()).collect();
+    names.sort();
+
+    let mut editor = Editor::new();
+    editor.set_helper(Some(ItemCompleter { names }));
+    editor
+}
@@ -1,24 +1,60 @@
 use anyhow::Result;
+use args::{Command, OutputFormat};
 use colored::*;
 use device_query::{DeviceQuery, DeviceState, Keycode};
+use image::GenericImageView;
 use ocr::OCREngine;
-use screenshot_rs;
+use scan::{get_item_info, ItemStats, PriceBasis, Scan, Scanner};
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use std::collections::HashSet;
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::mpsc;
 use std::{fs, thread, time::Duration};
 use tokio;
-use util::{clear_terminal, screenshot_path, unix_timestamp};
-use wfm_rs::response::ShortItem;
+use util::{cache_dir, clear_terminal, format_number};
 use wfm_rs::User;
 
+mod args;
+mod calibrate;
+mod capture;
 mod config;
+mod daemon;
+mod ee_log;
+mod export;
+mod history;
+mod hotkey;
+mod interactive;
+mod misses;
 mod ocr;
+mod price_cache;
+mod refine;
+mod scan;
+mod sound;
+mod template;
+mod thumbnail;
+mod token_store;
 mod util;
 
 const DATA_PATH_SUFFIX: &str = ".wfm_cli/";
 const DATA_TESSDATA_DIR: &str = "tessdata/";
 const DATA_SCREENSHOT_DIR: &str = "screenshots/";
-const DATA_CONFIG_FILE: &str = "config.wfm.json";
+const DATA_THUMBNAIL_DIR: &str = "thumbnails/";
+const DATA_CONFIG_FILE: &str = "config.toml";
+const DATA_CACHE_FILE: &str = "cache.json";
+const DATA_LEGACY_CONFIG_FILE: &str = "config.wfm.json";
+const DATA_HISTORY_FILE: &str = "history.jsonl";
+const DATA_MISSES_FILE: &str = "misses.log";
+const DATA_PRICE_CACHE_FILE: &str = "prices.json";
+const DATA_PREVIEW_FILE: &str = "preview.png";
+/// Name the layout found by `calibrate` is saved as
+const CALIBRATED_LAYOUT: &str = "calibrated";
+/// How many of the closest items `match` lists
+const MATCH_CANDIDATES: usize = 5;
+/// How long a new screenshot has to go without being written to before it's
+/// scanned by `watch-dir`
+const SCREENSHOT_WRITE_DELAY: Duration = Duration::from_secs(1);
 const ITEMS_CACHE_EXPIRY_S: u64 = 24 * 60 * 60;
 const RESULT_COLORS: [Color; 4] = [
     Color::TrueColor { r: 0, g: 255, b: 8 },
@@ -44,129 +80,1039 @@ const RESULT_COLORS: [Color; 4] = [
 // - release wfm_rs
 // - release cli
 
-#[cfg(target_os = "windows")]
-std::compile_error!("Windows is not supported!");
+fn main() {
+    // the colors and clearing the terminal are escape codes, which the Windows
+    // console only understands once asked to
+    #[cfg(windows)]
+    let _ = colored::control::set_virtual_terminal(true);
 
-#[tokio::main]
-async fn main() {
-    let config = config::run().await.unwrap();
-    let user = config.user();
-    let device = DeviceState::new();
-    let engine = OCREngine::new(config.items);
-    println!("You may now press 'F6' whenever you get to the relic reward screen");
+    // parsing sets the environment the paths are looked up in, which is only
+    // safe before the runtime starts any threads
+    let args = args::parse().unwrap();
+    if let Err(e) = util::migrate_legacy_dir() {
+        eprintln!("Failed to move the files out of the old data directory: {}", e);
+    }
 
-    {
-        let mut data_path = home::home_dir().unwrap();
-        data_path.push(DATA_PATH_SUFFIX);
-        data_path.push(DATA_TESSDATA_DIR);
+    tokio::runtime::Runtime::new().unwrap().block_on(run(args));
+}
+
+async fn run(args: args::Args) {
+    // commands that run without loading (and possibly refreshing) the config
+    if let Command::SamplePixel { path, x, y } = &args.command {
+        println!("{}", ocr::sample_pixel(path, *x, *y).unwrap());
+        return;
+    }
+    if let Command::ClearCache { all } = &args.command {
+        for cleared in config::clear_cache(*all).unwrap() {
+            println!("Cleared {}", cleared);
+        }
+        return;
+    }
+    if let Command::Preview(path) = &args.command {
+        println!("{}", preview(&args, path.as_deref()).await.unwrap().to_string_lossy());
+        return;
+    }
+    if let Command::Calibrate { path, interactive } = &args.command {
+        let layout = match run_calibration(path.as_deref(), args.layout.as_deref(), *interactive).await.unwrap() {
+            Some(x) => x,
+            None => {
+                println!("Quit without saving the layout");
+                return;
+            }
+        };
+        println!("{}", serde_json::to_string_pretty(&layout).unwrap());
+        println!(
+            "Saved as the '{}' layout, select it with --layout {0} or layout = \"{0}\" in the config",
+            CALIBRATED_LAYOUT
+        );
+        return;
+    }
+    if let Command::TestBeep = &args.command {
+        let config = config::load().unwrap_or_else(|e| {
+            eprintln!("Failed to load the config ({}), testing the default beep", e);
+            config::Config::default()
+        });
+        sound::test(config.scan_sound.as_deref(), config.beep_fade_ms).await.unwrap();
+        return;
+    }
+    if let Command::Monitors = &args.command {
+        for (idx, monitor) in capture::monitors().unwrap().iter().enumerate() {
+            println!("{}: {}", idx, monitor);
+        }
+        return;
+    }
+    if let Command::SwitchProfile(name) = &args.command {
+        config::switch_profile(name).unwrap();
+        println!("Using profile '{}' from now on", name);
+        return;
+    }
+    if let Command::SetHotkey = &args.command {
+        println!("Press the key to scan with, along with Ctrl, Shift or Alt if you like (Escape to cancel)");
+        match capture_hotkey() {
+            Some(chord) => {
+                config::set_hotkey(&chord).unwrap();
+                println!("Scanning on '{}' from now on", chord);
+            }
+            None => println!("Cancelled, the hotkey is unchanged"),
+        }
+        return;
+    }
+    if let Command::Match(text) = &args.command {
+        match_text(&config::load().unwrap(), text).unwrap();
+        return;
+    }
+    if let Command::Export(path) = &args.command {
+        let entries = history::load().unwrap();
+        export::export_xlsx(&entries, path).unwrap();
+        println!("Exported {} scans to {}", entries.len(), path);
+        return;
+    }
+
+    let mut config = config::run(args.no_refresh, args.no_login).await.unwrap();
+    config.run_platform = args.platform.clone();
+    let mut user = config.user();
+
+    match &args.command {
+        Command::Watch => watch(&mut config, &mut user, &args).await,
+        Command::WatchDir(dir) => {
+            let dir = match dir.as_ref().or(config.screenshot_dir.as_ref()) {
+                Some(x) => x.clone(),
+                None => {
+                    eprintln!("Pass the directory to watch-dir, or set screenshot_dir in the config");
+                    return;
+                }
+            };
+            watch_dir(&mut config, &mut user, &args, &dir).await.unwrap()
+        }
+        Command::Scan(paths) => scan_files(&mut config, &mut user, &args, paths).await.unwrap(),
+        Command::Price(name) => price(&mut config, &mut user, name).await.unwrap(),
+        Command::PricePrompt => price_prompt(&mut config, &mut user).await.unwrap(),
+        Command::Login => {
+            config::login(&mut config).await.unwrap();
+            println!("Logged in, prices are looked up for {}", config.stats_platform());
+        }
+        Command::Daemon { listen } => {
+            let scanner = start_scanner(&config, &args).unwrap();
+            daemon::run(config, user, scanner, *listen).await.unwrap()
+        }
+        Command::SamplePixel { .. }
+        | Command::ClearCache { .. }
+        | Command::Preview(_)
+        | Command::Calibrate { .. }
+        | Command::TestBeep
+        | Command::Monitors
+        | Command::SetHotkey
+        | Command::SwitchProfile(_)
+        | Command::Match(_)
+        | Command::Export(_) => unreachable!(),
+    }
+}
+
+async fn price(config: &mut config::Config, user: &mut User, name: &str) -> Result<()> {
+    let (item, _) = ocr::find_closest_match(&config.items, name);
+    let item_stats = get_item_info(&item, user, config).await?;
+    match spread_summary(config, &item_stats) {
+        Some(spread) => println!("{} | {}", item_summary(config, &item_stats), spread),
+        None => println!("{}", item_summary(config, &item_stats)),
+    }
 
-        let user_words = include_str!("../tessdata/eng.user-words");
-        let traineddata = include_bytes!("../tessdata/eng.traineddata");
+    Ok(())
+}
+
+/// Matches the text against the cached items the way OCR results are, and
+/// prints the closest ones
+fn match_text(config: &config::Config, text: &str) -> Result<()> {
+    if config.items.is_empty() {
+        anyhow::bail!("There is no cached item list yet, run once to fetch it");
+    }
+
+    let text = text.trim();
+    let candidates = ocr::closest_matches(&config.items, text, MATCH_CANDIDATES);
+    let (best, distance) = candidates[0];
+    let accepted = if config.match_threshold.accepts(distance, &best.item_name) {
+        "accepted".green()
+    } else {
+        "rejected".red()
+    };
+    println!(
+        "{} (distance {}, confidence {:.0}%, {})",
+        best.item_name.bold(),
+        distance,
+        ocr::match_confidence(distance, &best.item_name) * 100.0,
+        accepted
+    );
+
+    for (item, distance) in &candidates[1..] {
+        println!("  {:>3}  {}", distance, item.item_name);
+    }
+
+    Ok(())
+}
+
+/// Platinum of the best pick of every scan this session
+#[derive(Default)]
+struct SessionTally {
+    total: f32,
+    scans: usize,
+    last: f32,
+}
+
+impl SessionTally {
+    fn add(&mut self, scan: &Scan) {
+        self.last = scan.best().map(|x| x.avg_price).unwrap_or(0.0);
+        self.total += self.last;
+        self.scans += 1;
+    }
 
-        let _ = fs::create_dir_all(data_path.clone());
+    /// Replaces the value of the previous scan, for when it was processed again
+    fn replace_last(&mut self, scan: &Scan) {
+        if self.scans == 0 {
+            return self.add(scan);
+        }
+        self.total -= self.last;
+        self.scans -= 1;
+        self.add(scan);
+    }
+}
 
-        let mut user_words_file = File::create(data_path.join("eng.user-words")).unwrap();
-        write!(user_words_file, "{}", user_words).unwrap();
+/// What the scans of `watch` and `watch-dir` are kept track of with
+struct LiveSession {
+    /// When the session started, which its scans are recorded under
+    started: u64,
+    tally: SessionTally,
+    /// Items of the scans so far, for `--new-only`
+    seen: HashSet<String>,
+}
 
-        let mut traineddata_file = File::create(data_path.join("eng.traineddata")).unwrap();
-        traineddata_file.write(traineddata).unwrap();
+impl LiveSession {
+    fn new() -> Result<LiveSession> {
+        Ok(LiveSession {
+            started: util::unix_timestamp()?,
+            tally: SessionTally::default(),
+            seen: HashSet::new(),
+        })
     }
+}
+
+/// Looks up every item name entered, until the prompt is closed
+async fn price_prompt(config: &mut config::Config, user: &mut User) -> Result<()> {
+    use rustyline::error::ReadlineError;
+
+    let mut editor = interactive::item_editor(&config.items);
+    println!("Enter item names to look up (tab completes), Ctrl-D to quit");
 
     loop {
-        let keys: Vec<Keycode> = device.get_keys();
-        if keys.contains(&Keycode::F6) {
-            println!("Scanning...");
-            let mut screenshot_path = screenshot_path().unwrap();
-            screenshot_path.push(format!("{}.png", unix_timestamp().unwrap()));
-            let screenshot_path_str = screenshot_path.to_string_lossy().to_string();
-            screenshot_rs::screenshot_window(screenshot_path_str.clone());
-            let items = engine.ocr(&screenshot_path_str).unwrap();
-            fs::remove_file(screenshot_path).unwrap();
-
-            let mut all_item_stats = Vec::new();
-
-            let mut best_idx = 0;
-            let mut best_price = 0.0;
-
-            for (i, item) in items {
-                if let Ok(item_stats) = get_item_info(&item, &user).await {
-                    if !item_stats.avg_price.is_nan() {
-                        if item_stats.avg_price > best_price {
-                            best_price = item_stats.avg_price;
-                            best_idx = i;
-                        }
-                        all_item_stats.push(item_stats);
-                    }
-                    all_item_stats.push(item_stats);
+        let line = match editor.readline("item> ") {
+            Ok(x) => x,
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let name = line.trim();
+        if name.is_empty() {
+            continue;
+        }
+
+        editor.add_history_entry(name);
+        if let Err(e) = price(config, user, name).await {
+            println!("Failed to look up '{}': {}", name, e);
+        }
+    }
+}
+
+/// Outlines the crops of the layout on the screenshot at `path`, or a new
+/// capture, returning where the result was saved
+async fn preview(args: &args::Args, path: Option<&str>) -> Result<PathBuf> {
+    let config = config::load()?;
+    let mut settings = config.ocr_settings(args.layout.as_deref(), None)?;
+
+    let img = match path {
+        Some(path) => image::open(path)?,
+        None => capture::capture_image(&config).await?,
+    };
+    if settings.detect_layout {
+        match calibrate::calibrate(&settings.game_image(&img)) {
+            Ok(calibration) => settings.layout = calibration.layout,
+            Err(e) => eprintln!("{}, outlining the configured layout", e),
+        }
+    }
+
+    let mut preview_path = cache_dir()?;
+    preview_path.push(DATA_PREVIEW_FILE);
+    ocr::draw_crops(&img, &settings).save(&preview_path)?;
+
+    Ok(preview_path)
+}
+
+/// Finds the reward row on the screenshot at `path`, or a new capture, and
+/// saves the layout derived from it to the config. With `interactive` the user
+/// adjusts it first, starting from the `layout_name` layout if the row isn't found, and may
+/// quit without saving.
+async fn run_calibration(
+    path: Option<&str>,
+    layout_name: Option<&str>,
+    interactive: bool,
+) -> Result<Option<ocr::Layout>> {
+    let config = config::load()?;
+    let img = match path {
+        Some(path) => image::open(path)?,
+        None => capture::capture_image(&config).await?,
+    };
+    let img = match config.game_region {
+        Some(region) => img.crop_imm(region.x, region.y, region.width, region.height),
+        None => img,
+    };
+
+    let layout = match calibrate::calibrate(&img) {
+        Ok(calibration) => {
+            eprintln!(
+                "Found the reward row at {:.0}% of the 1080p UI size (match score {:.2})",
+                calibration.scale * 100.0,
+                calibration.score
+            );
+            calibration.layout
+        }
+        Err(e) if interactive => {
+            eprintln!("{}, starting from the configured layout", e);
+            config.layout(layout_name)?.scaled_to(img.width(), img.height())
+        }
+        Err(e) => return Err(e),
+    };
+    let layout = if interactive {
+        match calibration_wizard(&config, &img, layout)? {
+            Some(x) => x,
+            None => return Ok(None),
+        }
+    } else {
+        layout
+    };
+    config::save_layout(CALIBRATED_LAYOUT, &layout)?;
+
+    Ok(Some(layout))
+}
+
+/// Outlines the crops on the game image and shows what OCR reads from them,
+/// until the user has nudged them into place and saves, or quits (`None`)
+fn calibration_wizard(
+    config: &config::Config,
+    img: &image::DynamicImage,
+    layout: ocr::Layout,
+) -> Result<Option<ocr::Layout>> {
+    use rustyline::error::ReadlineError;
+
+    if config.items.is_empty() {
+        anyhow::bail!("There is no item list to check the names against yet, run wfm_cli once first");
+    }
+    let mut settings = config.ocr_settings(None, tessdata_dir(config)?)?;
+    // the image is already cropped to the game region, and the crops are
+    // meant to be exactly where the user puts them
+    settings.game_region = None;
+    settings.scale_layout = false;
+    settings.detect_layout = false;
+    settings.count_cards = false;
+    settings.layout = layout;
+    let mut engine = OCREngine::new(config.ocr_items(), settings.clone())?;
+
+    let mut preview_path = cache_dir()?;
+    preview_path.push(DATA_PREVIEW_FILE);
+    let mut editor = rustyline::Editor::<()>::new();
+    println!(
+        "Move the crops with up, down, left and right, resize them with wider, narrower, taller and shorter, \
+         and space them with spread and closer, each followed by an optional number of pixels. \
+         Enter save once every slot reads right, or quit to keep the old layout."
+    );
+
+    loop {
+        ocr::draw_crops(img, &settings).save(&preview_path)?;
+        println!("The crops are outlined in {}", preview_path.to_string_lossy());
+        let mut matches = engine.ocr(img)?;
+        matches.sort_by_key(|x| x.slot);
+        for slot_match in &matches {
+            let read = match (&slot_match.item, &slot_match.closest) {
+                _ if slot_match.empty => "empty".to_string(),
+                (Some(item), _) => format!("{}", item.item_name.green()),
+                (None, Some(closest)) => format!("{} (closest: {})", "unreadable".red(), closest),
+                (None, None) => "unreadable".red().to_string(),
+            };
+            println!(
+                "Slot {}: {}, read '{}' ({} off)",
+                slot_match.slot + 1,
+                read,
+                slot_match.text.trim(),
+                slot_match.distance
+            );
+        }
+
+        let line = match editor.readline("calibrate> ") {
+            Ok(x) => x,
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        match line.trim() {
+            "" => {}
+            "save" => return Ok(Some(settings.layout)),
+            "quit" => return Ok(None),
+            command => match calibrate::Nudge::parse(command) {
+                Some(nudge) => {
+                    editor.add_history_entry(command);
+                    settings.layout = nudge.apply(&settings.layout);
+                    engine.set_layout(settings.layout.clone());
                 }
+                None => println!("Unknown command '{}'", command),
+            },
+        }
+    }
+}
+
+async fn watch(config: &mut config::Config, user: &mut User, args: &args::Args) {
+    let format = args.format;
+    let mut scanner = start_scanner(config, args).unwrap();
+    let mut session = LiveSession::new().unwrap();
+    // validated when the config was read
+    let chords: Vec<hotkey::Chord> = config.hotkeys.iter().map(|x| x.chord().unwrap()).collect();
+    for (hotkey, chord) in config.hotkeys.iter().zip(&chords) {
+        status(
+            format,
+            &format!(
+                "You may now press '{}' whenever you get to the relic reward screen ({} layout)",
+                chord,
+                hotkey
+                    .layout
+                    .as_deref()
+                    .or(args.layout.as_deref())
+                    .or(config.layout.as_deref())
+                    .unwrap_or("default")
+            ),
+        );
+    }
+    status(
+        format,
+        "Press 'F7' to process the last screenshot again after changing the match threshold",
+    );
+    status(format, "Press 'F8' to reset the session total");
+
+    let log_opened = match config.ee_log_path() {
+        Some(path) if config.scan_on_log => {
+            status(
+                format,
+                &format!("Scanning whenever {} says the reward screen opened", path.to_string_lossy()),
+            );
+            ee_log::watch(path)
+        }
+        None if config.scan_on_log => {
+            status(format, "Set ee_log_path in the config to scan when the reward screen opens");
+            crossbeam_channel::never()
+        }
+        _ => crossbeam_channel::never(),
+    };
+
+    let rescan = chords.len();
+    // the log opening the reward screen is handled like a press of this
+    let log_scan = rescan + 2;
+    // F7 and F8 are left to other programs, only the scan keys are grabbed
+    let reserved = vec![hotkey::Chord::parse("F7").unwrap(), hotkey::Chord::parse("F8").unwrap()];
+    let presses = hotkey::listen(chords, reserved);
+
+    loop {
+        // keys pressed (and screens opened) while the last one was handled are ignored
+        while presses.try_recv().is_ok() || log_opened.try_recv().is_ok() {}
+        let pressed = crossbeam_channel::select! {
+            recv(presses) -> x => x,
+            recv(log_opened) -> x => x.map(|_| log_scan),
+        };
+        let pressed = match pressed {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+
+        let scan_layout = match config.hotkeys.get(pressed) {
+            Some(hotkey) => Some(hotkey.layout.clone()),
+            None if pressed == log_scan => Some(None),
+            None => None,
+        };
+        if let Some(layout) = scan_layout {
+            let layout = layout.as_deref().or(args.layout.as_deref());
+            match config.layout(layout) {
+                Ok(x) => scanner.set_layout(x),
+                Err(e) => {
+                    status(format, &e.to_string());
+                    continue;
+                }
+            }
+            if pressed == log_scan {
+                // the rewards take a moment to show up after the screen opened
+                tokio::time::sleep(Duration::from_millis(config.log_scan_delay_ms)).await;
+            } else {
+                countdown(format, config.capture_countdown_s).await;
+            }
+            status(format, "Scanning...");
+            match scanner.scan(config, user).await {
+                Ok(scan) => show_live_scan(config, user, &scanner, args, &mut session, &scan).await,
+                Err(e) => scan_failed(config, format, &e).await,
+            }
+        } else if pressed == rescan {
+            match config::load() {
+                Ok(file_config) => config.match_threshold = file_config.match_threshold,
+                Err(e) => status(format, &format!("Failed to reload the config: {}", e)),
             }
 
-            all_item_stats.sort_by(|a, b| a.avg_price.partial_cmp(&b.avg_price).unwrap());
-            let all_item_stats: Vec<&ItemStats> = all_item_stats.iter().rev().collect();
+            match scanner.rescan_last(config, user).await {
+                Ok(Some(scan)) => {
+                    session.tally.replace_last(&scan);
+                    report(config, user, &scanner, &session.tally, args, &scan, true).await
+                }
+                Ok(None) => status(format, "Nothing has been scanned yet"),
+                Err(e) => scan_failed(config, format, &e).await,
+            }
+        } else if session.tally.scans > 0 {
+            session.tally = SessionTally::default();
+            status(format, "Session total reset");
+        }
+    }
+}
 
-            clear_terminal();
+/// Scans every screenshot saved to `dir` from now on, as soon as it's written
+async fn watch_dir(config: &mut config::Config, user: &mut User, args: &args::Args, dir: &str) -> Result<()> {
+    let format = args.format;
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::watcher(tx, SCREENSHOT_WRITE_DELAY)?;
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    let mut scanner = start_scanner(config, args)?;
+    let mut session = LiveSession::new()?;
+    status(
+        format,
+        &format!("You may now take a screenshot whenever you get to the relic reward screen, watching {}", dir),
+    );
 
-            for (idx, item) in all_item_stats.iter().enumerate() {
-                let msg = format!(
-                    "{} | {:.1} platinum average | {:.0} sold in the last 48 hours",
-                    item.item.item_name, item.avg_price, item.volume
-                );
-                println!("{}", msg.color(RESULT_COLORS[idx]));
+    loop {
+        let path = match rx.recv()? {
+            DebouncedEvent::Create(path) | DebouncedEvent::Rename(_, path) => path,
+            DebouncedEvent::Error(e, _) => {
+                status(format, &format!("Failed to watch {}: {}", dir, e));
+                continue;
             }
-            let _ = beep(best_idx + 1).await;
+            _ => continue,
+        };
+        if !util::is_image_path(&path) {
+            continue;
+        }
+
+        status(format, &format!("Scanning {}...", path.to_string_lossy()));
+        match scanner.scan_file(config, user, &path.to_string_lossy()).await {
+            Ok(scan) => show_live_scan(config, user, &scanner, args, &mut session, &scan).await,
+            Err(e) => scan_failed(config, format, &e).await,
+        }
+    }
+}
+
+/// Records a scan of the game in the session tally and history, and shows it
+/// unless `--new-only` skips it
+async fn show_live_scan(
+    config: &config::Config,
+    user: &User,
+    scanner: &Scanner,
+    args: &args::Args,
+    session: &mut LiveSession,
+    scan: &Scan,
+) {
+    session.tally.add(scan);
+    if config.record_history {
+        let entry = history::HistoryEntry::new(session.started, scan);
+        if let Err(e) = history::record(&entry, &config.log_rotation) {
+            status(args.format, &format!("Failed to record the scan: {}", e));
+        }
+    }
+    if !mark_seen(&mut session.seen, scan) && args.new_only {
+        status(
+            args.format,
+            &format!("Nothing new ({} items seen this session)", session.seen.len()),
+        );
+        return;
+    }
+    report(config, user, scanner, &session.tally, args, scan, true).await;
+}
+
+/// Waits for a key to be pressed, returning it with the modifiers held, or
+/// `None` on Escape
+fn capture_hotkey() -> Option<hotkey::Chord> {
+    let device = DeviceState::new();
+    // the Enter that started the command may still be held
+    while !device.get_keys().is_empty() {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    loop {
+        let keys = device.get_keys();
+        if keys.contains(&Keycode::Escape) {
+            return None;
+        }
+        if let Some(chord) = hotkey::Chord::from_keys(&keys) {
+            return Some(chord);
         }
         thread::sleep(Duration::from_millis(10));
     }
 }
 
-#[derive(Clone)]
-struct ItemStats {
-    volume: f32,
-    avg_price: f32,
-    item: ShortItem,
+/// Scans the screenshots at `paths` (`-` being stdin) and shows the results,
+/// carrying on with the rest when one of several can't be scanned
+async fn scan_files(
+    config: &mut config::Config,
+    user: &mut User,
+    args: &args::Args,
+    paths: &[String],
+) -> Result<()> {
+    let mut scanner = start_scanner(config, args)?;
+    let mut tally = SessionTally::default();
+    let mut failed = 0;
+
+    for path in paths {
+        if paths.len() > 1 {
+            status(args.format, &format!("{}:", path));
+        }
+        let scan = match scanner.scan_file(config, user, path).await {
+            Ok(x) => x,
+            Err(e) if paths.len() == 1 => return Err(e),
+            Err(e) => {
+                status(args.format, &config.redact(&format!("Failed to scan {}: {}", path, e)));
+                failed += 1;
+                continue;
+            }
+        };
+        tally.add(&scan);
+        report(config, user, &scanner, &tally, args, &scan, false).await;
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{} of {} screenshots couldn't be scanned", failed, paths.len());
+    }
+
+    Ok(())
+}
+
+/// Remembers the items of the scan, returning whether any of them are new
+fn mark_seen(seen: &mut HashSet<String>, scan: &Scan) -> bool {
+    let mut any_new = false;
+    for item in &scan.items {
+        any_new |= seen.insert(item.item.url_name.clone());
+    }
+    any_new
 }
 
-async fn get_item_info(item: &ShortItem, user: &User) -> Result<ItemStats> {
-    let statistics = user.get_item_market_statistics(item).await?;
+/// Announces every second before the capture, so the reward screen can be
+/// brought to the front in time
+async fn countdown(format: OutputFormat, seconds: u64) {
+    for remaining in (1..=seconds).rev() {
+        status(format, &format!("Capturing in {}...", remaining));
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
 
-    let last_stats = &statistics.statistics_closed._48_hours;
-    let avg_price: f32 =
-        last_stats.iter().map(|x| x.avg_price).sum::<f32>() / last_stats.len() as f32;
-    let volume: f32 = last_stats.iter().map(|x| x.volume).sum();
+async fn scan_failed(config: &config::Config, format: OutputFormat, err: &anyhow::Error) {
+    status(format, &config.redact(&format!("Scan failed: {}", err)));
+    if config.error_sound.is_some() {
+        sound::alert(config.error_sound.as_deref(), 1, config.beep_fade_ms).await;
+    }
+}
 
-    Ok(ItemStats {
-        volume,
-        avg_price,
-        item: item.clone(),
-    })
+/// Shows the result of a scan in the requested format. Results of `live` scans
+/// of the game replace the previous ones on the terminal and beep.
+async fn report(
+    config: &config::Config,
+    user: &User,
+    scanner: &Scanner,
+    tally: &SessionTally,
+    args: &args::Args,
+    scan: &Scan,
+    live: bool,
+) {
+    match args.format {
+        OutputFormat::Text => {
+            let thumbnails = fetch_thumbnails(config, user, scan).await;
+            let mut lines = print_results(config, scan, &thumbnails, args.verbose, live);
+            println!(
+                "{}",
+                format!(
+                    "Session total: {}p across {} scans",
+                    format_number(tally.total, 0, config.number_locale()),
+                    tally.scans
+                )
+                .dimmed()
+            );
+            lines += 1;
+            if args.verbose {
+                lines += print_worker_errors(scanner);
+            }
+            if live {
+                // keep what's printed after the results (and the next scan) in place
+                if let Some(output_lines) = config.output_lines {
+                    for _ in lines..output_lines {
+                        println!();
+                    }
+                }
+                let best_idx = scan.best().map(|x| x.slot).unwrap_or(0);
+                let best_price = scan.best().map(|x| x.avg_price).unwrap_or(0.0);
+                if best_price >= config.beep_min_value {
+                    sound::alert(config.scan_sound.as_deref(), best_idx + 1, config.beep_fade_ms).await;
+                }
+            }
+        }
+        OutputFormat::Json | OutputFormat::JsonLines => {
+            print_results_json(args.format, scan).unwrap();
+        }
+    }
+
+    if let Some(path) = &config.fifo_path {
+        if let Err(e) = write_fifo(path, scan) {
+            status(args.format, &format!("Failed to write to {}: {}", path, e));
+        }
+    }
+}
+
+/// Starts the OCR workers, extracting the bundled tessdata unless the user
+/// provides their own
+fn start_scanner(config: &config::Config, args: &args::Args) -> Result<Scanner> {
+    let settings = config.ocr_settings(args.layout.as_deref(), tessdata_dir(config)?)?;
+    let engine = OCREngine::new(config.ocr_items(), settings)?;
+
+    Ok(Scanner::new(engine))
+}
+
+/// The tessdata directory tesseract is pointed to, if any
+fn tessdata_dir(config: &config::Config) -> Result<Option<String>> {
+    if let Some(dir) = &config.tessdata_dir {
+        Ok(Some(dir.clone()))
+    } else if std::env::var_os("TESSDATA_PREFIX").is_some() {
+        // tesseract picks up TESSDATA_PREFIX by itself
+        Ok(None)
+    } else {
+        Ok(Some(extract_tessdata()?.to_string_lossy().to_string()))
+    }
+}
+
+/// Writes the bundled tessdata to the cache directory, returning its location
+fn extract_tessdata() -> Result<PathBuf> {
+    let mut tessdata_path = cache_dir()?;
+    tessdata_path.push(DATA_TESSDATA_DIR);
+
+    let user_words = include_str!("../tessdata/eng.user-words");
+    let traineddata = include_bytes!("../tessdata/eng.traineddata");
+
+    fs::create_dir_all(&tessdata_path)?;
+
+    let mut user_words_file = File::create(tessdata_path.join("eng.user-words"))?;
+    write!(user_words_file, "{}", user_words)?;
+
+    let mut traineddata_file = File::create(tessdata_path.join("eng.traineddata"))?;
+    traineddata_file.write_all(traineddata)?;
+
+    Ok(tessdata_path)
+}
+
+/// Prints a status line, keeping stdout clean for machine-readable formats
+fn status(format: OutputFormat, msg: &str) {
+    if format.is_machine_readable() {
+        eprintln!("{}", msg);
+    } else {
+        println!("{}", msg);
+    }
 }
 
-async fn beep(times: usize) -> Result<()> {
-    use rodio::{
-        source::{SineWave, Source},
-        OutputStream, Sink,
+/// The escape sequences showing the thumbnail of every item, if enabled and
+/// supported by the terminal
+async fn fetch_thumbnails(config: &config::Config, user: &User, scan: &Scan) -> Vec<Option<String>> {
+    let protocol = match thumbnail::detect() {
+        Some(x) if config.show_thumbnails => x,
+        _ => return vec![None; scan.items.len()],
     };
 
-    static BEEP_FREQUENCY: u32 = 587;
-    static BEEP_DURATION: f32 = 0.10;
-    static BEEP_SEPARATION: f32 = 0.05;
-    static LENGTH_PER_BEEP: f32 = BEEP_DURATION + BEEP_SEPARATION;
+    let mut thumbnails = Vec::new();
+    for item in &scan.items {
+        let png = thumbnail::fetch(user, &item.item).await.ok();
+        thumbnails.push(png.map(|x| thumbnail::escape(protocol, &x)));
+    }
 
-    let total_length = LENGTH_PER_BEEP * times as f32;
+    thumbnails
+}
 
-    let (_stream, stream_handle) = OutputStream::try_default()?;
-    let sink = Sink::try_new(&stream_handle)?;
+fn print_results(
+    config: &config::Config,
+    scan: &Scan,
+    thumbnails: &[Option<String>],
+    verbose: bool,
+    clear: bool,
+) -> usize {
+    let mut lines = 0;
+    let has_watchlist = !config.watchlist.is_empty();
 
-    let sine = SineWave::new(BEEP_FREQUENCY)
-        .take_duration(Duration::from_secs_f32(BEEP_DURATION))
-        .amplify(0.1);
-    let mixed = sine.mix(SineWave::new(0).take_duration(Duration::from_secs_f32(LENGTH_PER_BEEP)));
-    let repeated = mixed
-        .repeat_infinite()
-        .take_duration(Duration::from_secs_f32(total_length));
+    if clear {
+        clear_terminal();
+    }
 
-    sink.append(repeated);
-    tokio::time::sleep(Duration::from_secs_f32(total_length + BEEP_DURATION * 2.0)).await;
+    for (idx, item) in scan.items.iter().enumerate() {
+        if config.group_by_set {
+            // the parts of a set are next to each other, so the first one gets the header
+            if let Some(set) = &item.set {
+                if idx == 0 || scan.items[idx - 1].set.as_ref() != Some(set) {
+                    println!("{}", set_header(config, scan, set).bold());
+                    lines += 1;
+                }
+            }
+        }
+        let mut msg = format!(
+            "{}{}",
+            if item.watched { "* " } else { "" },
+            item_summary(config, item)
+        );
+        if verbose {
+            msg.push_str(&format!(" | {:.0}% confidence", item.confidence * 100.0));
+            if let Some(score) = item.score {
+                msg.push_str(&format!(" | score {:.2}", score));
+            }
+        }
+        // everything below the last color is as bad as it gets
+        let msg = msg.color(RESULT_COLORS[idx.min(RESULT_COLORS.len() - 1)]);
+        if let Some(Some(thumbnail)) = thumbnails.get(idx) {
+            print!("{} ", thumbnail);
+        }
+        if config.show_rarity {
+            if let Some(rarity) = item.rarity {
+                print!("{} ", rarity_tag(rarity));
+            }
+        }
+        let msg = if has_watchlist && !item.watched {
+            msg.dimmed()
+        } else {
+            msg
+        };
+        match spread_summary(config, item) {
+            Some(spread) => println!("{} | {}", msg, spread),
+            None => println!("{}", msg),
+        }
+        lines += 1;
+    }
+
+    for slot in scan.unreadable() {
+        let msg = if slot.text.is_empty() {
+            format!("Reward {} could not be read", slot.slot_index + 1)
+        } else {
+            format!(
+                "Reward {} could not be read: \"{}\" ({:.0}% text confidence)",
+                slot.slot_index + 1,
+                slot.text,
+                slot.text_confidence * 100.0
+            )
+        };
+        println!("{}", msg.dimmed());
+        lines += 1;
+    }
+
+    for failed in scan.failed() {
+        let item_name = failed.item.as_ref().map_or("", |x| x.item_name.as_str());
+        println!(
+            "{}",
+            format!(
+                "Reward {} ({}) could not be priced: {}",
+                failed.slot_index + 1,
+                item_name,
+                failed.error.as_deref().unwrap_or_default()
+            )
+            .red()
+        );
+        lines += 1;
+    }
+
+    if config.refinement_advice {
+        if let Some(recommendation) = refine::recommend(&scan.items) {
+            print_recommendation(&recommendation);
+            lines += 1;
+        }
+    }
+
+    lines
+}
+
+/// The best orders on both sides of the market, with the spread between them
+/// highlighted. It's colored on its own, so it isn't part of `item_summary`.
+fn spread_summary(config: &config::Config, item: &ItemStats) -> Option<ColoredString> {
+    if !config.show_spread {
+        return None;
+    }
+
+    let locale = config.number_locale();
+    let price = |value: Option<f32>| match value {
+        Some(x) => format!("{}p", format_number(x, config.price_decimals, locale)),
+        None => "-".to_string(),
+    };
+    let summary = format!(
+        "lowest sell {} | highest buy {} | spread {}",
+        price(item.best_sell),
+        price(item.best_buy),
+        price(item.spread())
+    );
+
+    Some(match item.spread() {
+        Some(spread) if spread > 0.0 => summary.bold().cyan(),
+        _ => summary.bold(),
+    })
+}
+
+/// How many parts of the set were scanned, and what they're worth together
+fn set_header(config: &config::Config, scan: &Scan, set: &scan::ItemSet) -> String {
+    let parts: Vec<&ItemStats> = scan
+        .items
+        .iter()
+        .filter(|x| x.set.as_ref() == Some(set))
+        .collect();
+    let total: f32 = parts.iter().map(|x| x.avg_price).filter(|x| !x.is_nan()).sum();
+    format!(
+        "{}: {} of {} parts scanned, {}p together",
+        set.name,
+        parts.len(),
+        set.parts,
+        format_number(total, config.price_decimals, config.number_locale())
+    )
+}
+
+fn print_recommendation(recommendation: &refine::Recommendation) {
+    if recommendation.refinement == refine::Refinement::Intact {
+        println!("No need to refine this relic");
+        return;
+    }
+
+    let mut msg = format!(
+        "Refine to {}: {:.1}p expected per run instead of {:.1}p",
+        recommendation.refinement.name(),
+        recommendation.expected_value,
+        recommendation.intact_value
+    );
+    if let Some((name, price)) = &recommendation.rare {
+        msg.push_str(&format!(" (rare reward {} worth {:.0}p)", name, price));
+    }
+    println!("{}", msg);
+}
+
+fn print_worker_errors(scanner: &Scanner) -> usize {
+    let errors = scanner.worker_errors();
+    for (idx, error) in errors.iter().enumerate() {
+        if let Some(error) = error {
+            println!("{}", format!("OCR worker {} last failed: {}", idx, error).dimmed());
+        }
+    }
+    errors.iter().flatten().count()
+}
+
+fn print_results_json(format: OutputFormat, scan: &Scan) -> Result<()> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    if format == OutputFormat::JsonLines {
+        writeln!(stdout, "{}", serde_json::to_string(scan)?)?;
+    } else {
+        writeln!(stdout, "{}", serde_json::to_string_pretty(scan)?)?;
+    }
+    stdout.flush()?;
 
     Ok(())
 }
+
+/// Writes the scan to a named pipe without waiting for a reader, so the scan
+/// loop keeps going when nobody is listening
+#[cfg(unix)]
+fn write_fifo(path: &str, scan: &Scan) -> Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut fifo = match fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+    {
+        Ok(x) => x,
+        // no reader attached
+        Err(e) if e.raw_os_error() == Some(libc::ENXIO) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    match writeln!(fifo, "{}", serde_json::to_string(scan)?) {
+        // the reader isn't keeping up, drop this scan rather than block
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+        x => Ok(x?),
+    }
+}
+
+#[cfg(not(unix))]
+fn write_fifo(_path: &str, _scan: &Scan) -> Result<()> {
+    anyhow::bail!("Named pipes are only supported on Unix")
+}
+
+/// The rarity in the colors the game uses for relic rewards
+fn rarity_tag(rarity: refine::Rarity) -> ColoredString {
+    let color = match rarity {
+        refine::Rarity::Common => Color::TrueColor { r: 205, g: 127, b: 50 },
+        refine::Rarity::Uncommon => Color::TrueColor { r: 192, g: 192, b: 192 },
+        refine::Rarity::Rare => Color::TrueColor { r: 255, g: 215, b: 0 },
+    };
+    format!("[{}]", rarity.name()).color(color)
+}
+
+fn item_summary(config: &config::Config, item: &ItemStats) -> String {
+    let locale = config.number_locale();
+    let price = |value| format_number(value, config.price_decimals, locale);
+    let basis = match config.price_basis {
+        PriceBasis::Live => "listed",
+        PriceBasis::Closed | PriceBasis::Both => config.price_metric.label(),
+    };
+    let value = |name: &str| match name {
+        "name" => Some(item.item.item_name.clone()),
+        "price" => Some(price(item.avg_price)),
+        "basis" => Some(basis.to_string()),
+        "min_price" => Some(price(item.min_price)),
+        "max_price" => Some(price(item.max_price)),
+        "volume" => Some(format_number(item.volume, config.volume_decimals, locale)),
+        // a slow seller would show up as ~0/day otherwise
+        "sales_per_day" => {
+            let decimals = if item.sales_per_day < 1.0 { 1 } else { 0 };
+            Some(format_number(item.sales_per_day, decimals, locale))
+        }
+        "slot" => Some((item.slot + 1).to_string()),
+        "confidence" => Some(format!("{:.0}%", item.confidence * 100.0)),
+        "score" => item.score.map(|x| format!("{:.2}", x)),
+        "rarity" => item.rarity.map(|x| x.name().to_string()),
+        "live_price" => item.live_price.map(price),
+        "ducats" => item.ducats.map(|x| x.to_string()),
+        "plat_per_ducat" => item.plat_per_ducat().map(|x| format_number(x, 2, locale)),
+        "lowest_sells" => item
+            .order_book
+            .as_ref()
+            .filter(|x| !x.lowest_sells.is_empty())
+            .map(|x| x.lowest_sells.iter().map(|x| price(*x)).collect::<Vec<_>>().join(", ")),
+        "highest_buy" => item.order_book.as_ref().and_then(|x| x.highest_buy).map(price),
+        _ => None,
+    };
+
+    // a custom template decides for itself what to show
+    if let Some(output_template) = &config.output_template {
+        return template::render(output_template, value);
+    }
+
+    let mut summary = template::render(template::DEFAULT_TEMPLATE, value);
+    if let Some(live_price) = item.live_price {
+        summary.push_str(&format!(" | {} platinum listed", price(live_price)));
+    }
+    if let Some(sells) = value("lowest_sells") {
+        summary.push_str(&format!(" | selling at {}", sells));
+    }
+    if let Some(buy) = value("highest_buy") {
+        summary.push_str(&format!(" | buying at {}", buy));
+    }
+    if let Some(my_listing) = item.my_listing {
+        summary.push_str(&format!(" | you list this at {}p", price(my_listing)));
+        if !item.avg_price.is_nan() {
+            summary.push_str(&format!(" (market {}p)", price(item.avg_price)));
+        }
+    }
+    if config.needs_ducats() {
+        if let Some(ratio) = item.plat_per_ducat() {
+            summary.push_str(&format!(" | {}p/ducat", format_number(ratio, 2, locale)));
+        }
+    }
+
+    summary
+}
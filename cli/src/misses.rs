@@ -0,0 +1,51 @@
+use crate::{
+    config::LogRotation,
+    ocr::SlotMatch,
+    util::{misses_path, unix_timestamp},
+};
+use anyhow::Result;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+/// Matches below this confidence are worth a look, even if they were accepted
+pub const LOW_CONFIDENCE: f32 = 0.8;
+
+/// Why a match ended up in the misses log
+#[derive(Clone, Copy, Debug)]
+pub enum Miss {
+    /// Matched, but not closely
+    LowConfidence,
+    /// Too far from every item name to be accepted
+    Rejected,
+    /// Matched an item warframe.market doesn't know
+    NotFound,
+}
+
+impl Miss {
+    fn name(&self) -> &'static str {
+        match self {
+            Miss::LowConfidence => "low-confidence",
+            Miss::Rejected => "rejected",
+            Miss::NotFound => "not-found",
+        }
+    }
+}
+
+/// Appends the match to the misses log as a tab separated line of timestamp,
+/// reason, OCR text, closest item and distance
+pub fn record(slot_match: &SlotMatch, miss: Miss, rotation: &LogRotation) -> Result<()> {
+    let path = misses_path()?;
+    rotation.rotate(&path)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(
+        file,
+        "{}\t{}\t{}\t{}\t{}",
+        unix_timestamp()?,
+        miss.name(),
+        slot_match.text.replace(|c| c == '\t' || c == '\n', " "),
+        slot_match.closest.as_deref().unwrap_or(""),
+        slot_match.distance
+    )?;
+
+    Ok(())
+}
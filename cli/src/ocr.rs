@@ -1,10 +1,11 @@
-use crate::{util::unix_timestamp, DATA_PATH_SUFFIX, DATA_SCREENSHOT_DIR};
+use crate::calibrate;
 use anyhow::Result;
 use crossbeam_channel::{unbounded, Receiver, Sender};
-use home;
-use image::{DynamicImage, GenericImage, GenericImageView, Pixel};
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImage, GenericImageView, Pixel, Rgba};
 use levenshtein::levenshtein;
-use std::sync::{Arc, RwLock};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex, Once, RwLock};
 use std::{fs, thread};
 use tesseract;
 use wfm_rs::response::ShortItem;
@@ -12,10 +13,331 @@ use wfm_rs::response::ShortItem;
 const IMG_MAX_WHITE_DEV: f32 = 45.0;
 const ITEM_CROP_SIZE: [u32; 2] = [250, 50];
 const ITEM_CROP_COORDS: [[u32; 2]; 4] = [[470, 410], [720, 410], [960, 410], [1200, 410]];
+const DEFAULT_RESOLUTION: [u32; 2] = [1920, 1080];
+/// Largest supported supersampling factor, tesseract slows down a lot above it
+pub const MAX_SUPERSAMPLE: u32 = 4;
+/// Thickness of the crop outlines `draw_crops` draws, in pixels
+pub const OUTLINE_WIDTH: u32 = 2;
+/// How far the text of a reward card may be off the center of its crop, as a
+/// share of the crop width, names are centered on their card
+const MAX_TEXT_OFFSET: f32 = 0.15;
+const TEXT_HSV_LOWER: (f64, f64, f64) = (0.075 * 360.0, 0.111, 0.416);
+const TEXT_HSV_UPPER: (f64, f64, f64) = (0.35 * 360.0, 1.0, 1.0);
+/// Themes `UiTheme::Auto` chooses from, the default one first so it wins ties
+const THEMES: [UiTheme; 5] = [
+    UiTheme::Vanilla,
+    UiTheme::Stalker,
+    UiTheme::Equinox,
+    UiTheme::HighContrast,
+    UiTheme::Lotus,
+];
+
+/// The in-game UI theme, which the color of the reward names depends on
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UiTheme {
+    /// Picks the theme whose text color covers the most of the reward crops,
+    /// on every scan
+    Auto,
+    /// The default gold text
+    Vanilla,
+    /// Red text
+    Stalker,
+    /// Pale, nearly white text
+    Equinox,
+    /// Light blue text
+    HighContrast,
+    /// Teal text
+    Lotus,
+}
+
+impl Default for UiTheme {
+    fn default() -> Self {
+        UiTheme::Vanilla
+    }
+}
+
+impl UiTheme {
+    pub fn name(self) -> &'static str {
+        match self {
+            UiTheme::Auto => "auto",
+            UiTheme::Vanilla => "vanilla",
+            UiTheme::Stalker => "stalker",
+            UiTheme::Equinox => "equinox",
+            UiTheme::HighContrast => "highcontrast",
+            UiTheme::Lotus => "lotus",
+        }
+    }
+
+    /// Lowest and highest hue (in degrees), saturation and value of the text.
+    /// The hue range wraps around when the lowest is above the highest.
+    /// `Auto` filters like the default theme until one is picked. The Equinox
+    /// range is centered on the theme's primary color, rgb(158, 159, 167), a
+    /// faintly blue gray, as listed in WFInfo's theme table.
+    fn text_range(self) -> ((f64, f64, f64), (f64, f64, f64)) {
+        match self {
+            UiTheme::Auto | UiTheme::Vanilla => (TEXT_HSV_LOWER, TEXT_HSV_UPPER),
+            UiTheme::Stalker => ((345.0, 0.4, 0.35), (15.0, 1.0, 1.0)),
+            UiTheme::Equinox => ((200.0, 0.02, 0.5), (270.0, 0.15, 0.85)),
+            UiTheme::HighContrast => ((185.0, 0.3, 0.5), (215.0, 1.0, 1.0)),
+            UiTheme::Lotus => ((160.0, 0.3, 0.4), (185.0, 1.0, 1.0)),
+        }
+    }
+}
+
+/// Screens the hotkey is commonly pressed on by mistake, with words that show
+/// up where the reward names would be, but never in a reward name
+const WRONG_SCREENS: &[(&str, &[&str])] = &[
+    (
+        "the void fissure selection",
+        &[
+            "fissure", "exterminate", "survival", "defense", "interception", "capture",
+            "excavation", "disruption", "sabotage", "rescue", "spy", "mobile", "hijack",
+            "requiem", "lith", "meso", "neo", "axi",
+        ],
+    ),
+    (
+        "the relic selection",
+        &["intact", "exceptional", "flawless", "radiant", "refinement"],
+    ),
+];
 
 pub struct OCREngine {
-    tx: [Sender<DynamicImage>; 4],
-    rx: Receiver<(usize, ShortItem)>,
+    /// One channel per worker, and one worker per reward slot. Crops are
+    /// tagged with their slot, since with the shared queue any worker may get them.
+    tx: Vec<Sender<(usize, DynamicImage)>>,
+    /// Queue every worker takes crops from once it's free
+    shared_tx: Sender<(usize, DynamicImage)>,
+    shared_rx: Receiver<(usize, DynamicImage)>,
+    rx: Receiver<SlotMatch>,
+    /// Handed to new workers to send their results to `rx`
+    results_tx: Sender<SlotMatch>,
+    items: Arc<RwLock<Vec<ShortItem>>>,
+    match_threshold: Arc<RwLock<MatchThreshold>>,
+    /// The theme the workers filter the text of, picked anew on every scan
+    /// with `UiTheme::Auto`
+    theme: Arc<RwLock<UiTheme>>,
+    /// The last error every worker ran into, if any
+    last_errors: Arc<Mutex<Vec<Option<String>>>>,
+    settings: OCRSettings,
+    /// Only the first screenshot of the wrong resolution is warned about
+    resolution_warning: Once,
+    /// The layout `detect_layout` found, used for screenshots of its resolution
+    detected_layout: Option<Layout>,
+}
+
+/// The item read from a single reward slot
+#[derive(Clone)]
+pub struct SlotMatch {
+    pub slot: usize,
+    /// `None` if the text couldn't be matched to an item
+    pub item: Option<ShortItem>,
+    /// How closely the text matched the item name, from 0 to 1
+    pub confidence: f32,
+    /// Whether the slot had too little text to hold an item at all
+    pub empty: bool,
+    /// The text as read by tesseract
+    pub text: String,
+    /// How sure tesseract is of the text, from 0 to 1
+    pub text_confidence: f32,
+    /// Name of the closest item, even if the match was rejected
+    pub closest: Option<String>,
+    /// Levenshtein distance between the text and the closest item name
+    pub distance: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OCRSettings {
+    /// The part of the screenshot covered by the game, for capture backends
+    /// that grab the whole (multi-monitor) desktop
+    pub game_region: Option<Region>,
+    pub match_threshold: MatchThreshold,
+    pub layout: Layout,
+    /// Scale the layout to screenshots of another resolution
+    pub scale_layout: bool,
+    /// Find the reward row on the screenshot instead of using the layout
+    pub detect_layout: bool,
+    /// Only read as many slots as there are reward cards on the screenshot
+    pub count_cards: bool,
+    pub ui_theme: UiTheme,
+    /// Directory containing the traineddata, if `None` tesseract looks it up
+    /// using `TESSDATA_PREFIX`
+    pub tessdata_dir: Option<String>,
+    /// Tesseract language to read, the bundled English one if `None`
+    pub tesseract_language: Option<String>,
+    /// Tesseract variables set on every worker, in order
+    pub tesseract_variables: Vec<(String, String)>,
+    /// Slots with a smaller share of text colored pixels are considered empty
+    pub empty_slot_density: f32,
+    /// Factor the crops are upscaled by before filtering and OCR, 1 to disable
+    pub supersample: u32,
+    /// Let whichever worker is free take the next crop, instead of giving
+    /// every worker the same slot
+    pub shared_queue: bool,
+    /// The configured layouts made for a known resolution, by name, to
+    /// suggest one when the screenshot doesn't fit the selected layout
+    pub layout_resolutions: Vec<(String, [u32; 2])>,
+}
+
+impl OCRSettings {
+    /// The part of the screenshot the layout applies to
+    pub fn game_image(&self, img: &DynamicImage) -> DynamicImage {
+        match self.game_region {
+            Some(region) => img.crop_imm(region.x, region.y, region.width, region.height),
+            None => img.clone(),
+        }
+    }
+
+    /// The layout scaled to the game image, with `scale_layout`
+    fn layout_for(&self, img: &DynamicImage) -> Layout {
+        if self.scale_layout {
+            self.layout.scaled_to(img.width(), img.height())
+        } else {
+            self.layout.clone()
+        }
+    }
+}
+
+/// Where the reward names are located on the reward screen
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Layout {
+    /// Size of every reward crop
+    pub crop_size: [u32; 2],
+    /// Top-left corner of every reward crop, one per reward slot
+    pub crop_coords: Vec<[u32; 2]>,
+    /// Size of the screenshots (or game region) the layout is made for, if known
+    #[serde(default)]
+    pub resolution: Option<[u32; 2]>,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            crop_size: ITEM_CROP_SIZE,
+            crop_coords: ITEM_CROP_COORDS.to_vec(),
+            resolution: Some(DEFAULT_RESOLUTION),
+        }
+    }
+}
+
+impl Layout {
+    /// Moves every crop up (negative) or down (positive) by `offset` pixels
+    pub fn shifted_vertically(mut self, offset: i32) -> Layout {
+        for coords in self.crop_coords.iter_mut() {
+            coords[1] = (coords[1] as i64 + offset as i64).max(0) as u32;
+        }
+        self
+    }
+
+    /// The layout for a screenshot of `width` by `height`. The reward screen
+    /// keeps its aspect ratio and stays centered, so on wider (or taller)
+    /// screens the crops are scaled by the height (or width) and moved along
+    /// with the center.
+    pub fn scaled_to(&self, width: u32, height: u32) -> Layout {
+        let [layout_width, layout_height] = match self.resolution {
+            Some(x) if x != [width, height] && x[0] > 0 && x[1] > 0 => x,
+            _ => return self.clone(),
+        };
+        let scale = (width as f64 / layout_width as f64).min(height as f64 / layout_height as f64);
+        let position = |coord: u32, layout_size: u32, size: u32| {
+            let from_center = (coord as f64 - layout_size as f64 / 2.0) * scale;
+            (size as f64 / 2.0 + from_center).round().max(0.0) as u32
+        };
+
+        Layout {
+            crop_size: [
+                ((self.crop_size[0] as f64 * scale).round() as u32).max(1),
+                ((self.crop_size[1] as f64 * scale).round() as u32).max(1),
+            ],
+            crop_coords: self
+                .crop_coords
+                .iter()
+                .map(|coords| {
+                    [
+                        position(coords[0], layout_width, width),
+                        position(coords[1], layout_height, height),
+                    ]
+                })
+                .collect(),
+            resolution: Some([width, height]),
+        }
+    }
+
+    /// The layout with `cards` slots instead, centered on the same point and
+    /// as far apart as these, the way the reward screen lines up the cards of
+    /// smaller squads
+    pub fn with_cards(&self, cards: usize) -> Layout {
+        let slots = self.crop_coords.len();
+        if cards == slots || slots < 2 {
+            return self.clone();
+        }
+        let (first, last) = (self.crop_coords[0], self.crop_coords[slots - 1]);
+        let pitch = (last[0] as f64 - first[0] as f64) / (slots - 1) as f64;
+        let center = (first[0] as f64 + last[0] as f64) / 2.0;
+
+        Layout {
+            crop_size: self.crop_size,
+            crop_coords: (0..cards)
+                .map(|i| {
+                    let x = center + (i as f64 - (cards - 1) as f64 / 2.0) * pitch;
+                    [x.round().max(0.0) as u32, first[1]]
+                })
+                .collect(),
+            resolution: self.resolution,
+        }
+    }
+}
+
+/// How far the OCR output may be from an item name before the slot is
+/// reported as unreadable instead of guessing
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct MatchThreshold {
+    /// Maximum levenshtein distance
+    pub max_distance: Option<usize>,
+    /// Maximum levenshtein distance relative to the length of the item name
+    pub max_ratio: Option<f32>,
+    /// Reads that both tesseract and the match to the item name are less
+    /// confident of than this (from 0 to 1) are reported as unrecognized
+    #[serde(default)]
+    pub min_confidence: Option<f32>,
+}
+
+impl MatchThreshold {
+    /// Whether OCR text with tesseract's `text_confidence` counts as the item
+    pub fn recognizes(&self, distance: usize, item_name: &str, text_confidence: f32) -> bool {
+        if let Some(min_confidence) = self.min_confidence {
+            if text_confidence < min_confidence && match_confidence(distance, item_name) < min_confidence {
+                return false;
+            }
+        }
+
+        self.accepts(distance, item_name)
+    }
+
+    pub fn accepts(&self, distance: usize, item_name: &str) -> bool {
+        if let Some(max_distance) = self.max_distance {
+            if distance > max_distance {
+                return false;
+            }
+        }
+
+        if let Some(max_ratio) = self.max_ratio {
+            if distance as f32 / item_name.len().max(1) as f32 > max_ratio {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// A rectangle within the captured screenshot, in pixels
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -38,87 +360,273 @@ impl From<(f64, f64, f64)> for Hsv {
 }
 
 impl OCREngine {
-    pub fn new(items: Vec<ShortItem>) -> OCREngine {
-        let img_channels: [(Sender<DynamicImage>, Receiver<DynamicImage>); 4] =
-            [unbounded(), unbounded(), unbounded(), unbounded()];
-
-        let (ret_channel_tx, ret_channel_rx) = unbounded::<(usize, ShortItem)>();
-        let items = Arc::new(RwLock::new(items));
-
-        for i in 0..4 {
-            let thread_rx = img_channels[i].1.clone();
-            let thread_tx = ret_channel_tx.clone();
-            let thread_items = items.clone();
-            let _ = thread::spawn(move || {
-                let rx = thread_rx;
-                let tx = thread_tx;
-                let items = thread_items;
-                let idx = i;
-
-                let mut data_path = home::home_dir().unwrap();
-                data_path.push(DATA_PATH_SUFFIX);
-
-                let mut ts = {
-                    let ts = tesseract::Tesseract::new_with_oem(
-                        Some(""),
-                        Some("eng"),
-                        tesseract::OcrEngineMode::TesseractOnly,
-                    )
-                    .unwrap();
-                    ts.set_variable("tessedit_pageseg_mode", "6").unwrap()
+    pub fn new(items: Vec<ShortItem>, settings: OCRSettings) -> Result<OCREngine> {
+        let (results_tx, results_rx) = unbounded::<SlotMatch>();
+        let (shared_tx, shared_rx) = unbounded::<(usize, DynamicImage)>();
+
+        let mut engine = OCREngine {
+            tx: Vec::new(),
+            shared_tx,
+            shared_rx,
+            rx: results_rx,
+            results_tx,
+            items: Arc::new(RwLock::new(items)),
+            match_threshold: Arc::new(RwLock::new(settings.match_threshold)),
+            theme: Arc::new(RwLock::new(settings.ui_theme)),
+            last_errors: Arc::new(Mutex::new(Vec::new())),
+            settings,
+            resolution_warning: Once::new(),
+            detected_layout: None,
+        };
+        engine.spawn_workers();
+
+        Ok(engine)
+    }
+
+    /// Starts workers until there is one for every slot of the layout, and of
+    /// the detected one
+    fn spawn_workers(&mut self) {
+        let detected_slots = self.detected_layout.as_ref().map_or(0, |x| x.crop_coords.len());
+        while self.tx.len() < self.settings.layout.crop_coords.len().max(detected_slots) {
+            self.spawn_worker(self.tx.len());
+        }
+    }
+
+    fn spawn_worker(&mut self, idx: usize) {
+        let (img_tx, rx) = unbounded::<(usize, DynamicImage)>();
+        self.tx.push(img_tx);
+        let shared_rx = self.shared_rx.clone();
+        self.last_errors.lock().unwrap().push(None);
+
+        let tx = self.results_tx.clone();
+        let items = self.items.clone();
+        let errors = self.last_errors.clone();
+        let match_threshold = self.match_threshold.clone();
+        let theme = self.theme.clone();
+        let tessdata_dir = self.settings.tessdata_dir.clone();
+        let language = self.settings.tesseract_language.clone();
+        let mut tesseract_variables = self.settings.tesseract_variables.clone();
+        let empty_slot_density = self.settings.empty_slot_density;
+        let supersample = self.settings.supersample;
+
+        let _ = thread::spawn(move || {
+            // created on first use, and again whenever a failure consumed it
+            let mut ts = None;
+
+            loop {
+                let received = crossbeam_channel::select! {
+                    recv(rx) -> x => x,
+                    recv(shared_rx) -> x => x,
                 };
-                let screenshot_path = data_path.join(DATA_SCREENSHOT_DIR);
-
-                loop {
-                    let mut img = match rx.recv() {
-                        Ok(x) => x,
-                        Err(e) => {
-                            eprintln!("Error in ocr worker: {}", e);
-                            continue;
-                        }
+                let (slot, img) = match received {
+                    Ok(x) => x,
+                    // the engine has been dropped
+                    Err(_) => return,
+                };
+
+                let current_theme = *theme.read().unwrap();
+                if text_density(&img, current_theme) < empty_slot_density {
+                    let slot_match = SlotMatch {
+                        slot,
+                        item: None,
+                        confidence: 0.0,
+                        empty: true,
+                        text: String::new(),
+                        text_confidence: 0.0,
+                        closest: None,
+                        distance: 0,
                     };
+                    if tx.send(slot_match).is_err() {
+                        return;
+                    }
+                    continue;
+                }
+
+                let slot_match = match read_text(
+                    &mut ts,
+                    tessdata_dir.as_deref(),
+                    language.as_deref(),
+                    &mut tesseract_variables,
+                    supersample,
+                    current_theme,
+                    img,
+                ) {
+                    Ok((raw_ocr, text_confidence)) => {
+                        let (closest, distance) =
+                            find_closest_match(&items.read().unwrap(), raw_ocr.trim());
+                        let confidence = match_confidence(distance, &closest.item_name);
+                        let threshold = *match_threshold.read().unwrap();
+                        let closest_name = closest.item_name.clone();
+                        let item = if threshold.recognizes(distance, &closest.item_name, text_confidence) {
+                            Some(closest)
+                        } else {
+                            None
+                        };
+                        SlotMatch {
+                            slot,
+                            item,
+                            confidence,
+                            empty: false,
+                            text: raw_ocr.trim().to_string(),
+                            text_confidence,
+                            closest: Some(closest_name),
+                            distance,
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Error in ocr worker {}: {}", idx, e);
+                        errors.lock().unwrap()[idx] = Some(e.to_string());
+                        SlotMatch {
+                            slot,
+                            item: None,
+                            confidence: 0.0,
+                            empty: false,
+                            text: String::new(),
+                            text_confidence: 0.0,
+                            closest: None,
+                            distance: 0,
+                        }
+                    }
+                };
 
-                    img = remove_not_text(&img, IMG_MAX_WHITE_DEV);
-                    let mut img_path = screenshot_path.clone();
-                    img_path.push(format!("{}_{}.png", unix_timestamp().unwrap(), idx));
-                    img.save(&img_path).unwrap();
-                    let img_path_str = format!("{:?}", img_path).replace(r#"""#, "");
-                    ts = ts.set_image(&img_path_str).unwrap().recognize().unwrap();
-                    let raw_ocr = ts.get_text().unwrap();
-                    fs::remove_file(img_path).unwrap();
-                    let closest = find_closest_levenshtein_match(&items.read().unwrap(), &raw_ocr);
-                    tx.send((i, closest)).unwrap();
+                if tx.send(slot_match).is_err() {
+                    return;
                 }
-            });
+            }
+        });
+    }
+
+    /// The last error every worker ran into, indexed by worker
+    pub fn last_errors(&self) -> Vec<Option<String>> {
+        self.last_errors.lock().unwrap().clone()
+    }
+
+    /// Replaces the items the workers match OCR output against
+    pub fn set_items(&self, items: Vec<ShortItem>) {
+        *self.items.write().unwrap() = items;
+    }
+
+    /// Replaces the threshold used for rejecting reads in all further scans
+    pub fn set_match_threshold(&self, match_threshold: MatchThreshold) {
+        *self.match_threshold.write().unwrap() = match_threshold;
+    }
+
+    /// Uses a different layout for all further scans, starting more workers
+    /// if it has more slots
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.settings.layout = layout;
+        self.spawn_workers();
+    }
+
+    /// With `detect_layout`, finds the reward row on the screenshot unless it
+    /// was already found at this resolution, and scans screenshots of that
+    /// resolution with the layout derived from it
+    pub fn detect_layout(&mut self, img: &DynamicImage) {
+        if !self.settings.detect_layout {
+            return;
+        }
+        let img = self.game_image(img);
+        let resolution = Some([img.width(), img.height()]);
+        if self.detected_layout.as_ref().map_or(false, |x| x.resolution == resolution) {
+            return;
         }
 
-        OCREngine {
-            tx: [
-                img_channels[0].0.clone(),
-                img_channels[1].0.clone(),
-                img_channels[2].0.clone(),
-                img_channels[3].0.clone(),
-            ],
-            rx: ret_channel_rx,
+        match calibrate::calibrate(&img) {
+            Ok(calibration) => {
+                self.detected_layout = Some(calibration.layout);
+                self.spawn_workers();
+            }
+            Err(e) => eprintln!("{}, using the configured layout", e),
         }
     }
 
-    pub fn ocr(&self, path: &str) -> Result<Vec<(usize, ShortItem)>> {
-        let img = image::open(path)?;
+    /// The detected layout if it was found at the resolution of the game
+    /// image, the configured one otherwise
+    fn layout_for(&self, img: &DynamicImage) -> Layout {
+        match &self.detected_layout {
+            Some(layout) if layout.resolution == Some([img.width(), img.height()]) => layout.clone(),
+            _ => self.settings.layout_for(img),
+        }
+    }
 
-        for i in 0..4 {
-            let cropped = img.crop_imm(
-                ITEM_CROP_COORDS[i][0],
-                ITEM_CROP_COORDS[i][1],
-                ITEM_CROP_SIZE[0],
-                ITEM_CROP_SIZE[1],
-            );
-            self.tx[i].send(cropped)?;
+    /// The screenshot's reward crops of the current layout, as the workers get them
+    pub fn crops(&self, img: &DynamicImage) -> Vec<DynamicImage> {
+        let img = self.game_image(img);
+        let layout = self.layout_for(&img);
+        layout
+            .crop_coords
+            .iter()
+            .map(|coords| crop_slot(&img, &layout, coords))
+            .collect()
+    }
+
+    fn game_image(&self, img: &DynamicImage) -> DynamicImage {
+        self.settings.game_image(img)
+    }
+
+    pub fn ocr(&self, img: &DynamicImage) -> Result<Vec<SlotMatch>> {
+        self.ocr_slots(img, None)
+    }
+
+    /// Reads only the given slots, or all of them with `None`
+    pub fn ocr_slots(&self, img: &DynamicImage, slots: Option<&[usize]>) -> Result<Vec<SlotMatch>> {
+        let img = self.game_image(img);
+
+        let mut layout = self.layout_for(&img);
+        if let Some([width, height]) = layout.resolution {
+            if img.dimensions() != (width, height) {
+                self.resolution_warning.call_once(|| {
+                    let matching = self
+                        .settings
+                        .layout_resolutions
+                        .iter()
+                        .find(|(_, resolution)| *resolution == [img.width(), img.height()]);
+                    let hint = match matching {
+                        Some((name, _)) => format!("select the \"{}\" layout", name),
+                        None => "select a matching layout or recalibrate".to_string(),
+                    };
+                    eprintln!(
+                        "The screenshot is {}x{}, but the layout is made for {}x{}, {}",
+                        img.width(),
+                        img.height(),
+                        width,
+                        height,
+                        hint
+                    );
+                });
+            }
+        }
+
+        let theme = match self.settings.ui_theme {
+            UiTheme::Auto => detect_theme(&img, &layout),
+            x => x,
+        };
+        *self.theme.write().unwrap() = theme;
+
+        if self.settings.count_cards {
+            let cards = count_cards(&img, &layout, self.settings.empty_slot_density, theme);
+            layout = layout.with_cards(cards);
+        }
+
+        // hand every crop to its worker as soon as it is cut, so the first
+        // workers are already running OCR while the later slots are cropped
+        let mut sent = 0;
+        for (slot, coords) in layout.crop_coords.iter().enumerate() {
+            if slots.map_or(false, |x| !x.contains(&slot)) {
+                continue;
+            }
+            let tx = if self.settings.shared_queue {
+                &self.shared_tx
+            } else {
+                &self.tx[slot]
+            };
+            tx.send((slot, crop_slot(&img, &layout, coords)))?;
+            sent += 1;
         }
 
         let mut results = Vec::new();
 
-        for _ in 0..4 {
+        for _ in 0..sent {
             results.push(self.rx.recv()?);
         }
 
@@ -126,23 +634,301 @@ impl OCREngine {
     }
 }
 
-// https://github.com/WFCD/WFinfo/blob/a7d4b8311564807cf384495441a18c56f63f7eb1/WFInfo/Data.cs#L830
-fn find_closest_levenshtein_match(items: &Vec<ShortItem>, target: &str) -> ShortItem {
-    let mut lowest_levenshtein = usize::MAX;
+fn crop_slot(img: &DynamicImage, layout: &Layout, coords: &[u32; 2]) -> DynamicImage {
+    img.crop_imm(coords[0], coords[1], layout.crop_size[0], layout.crop_size[1])
+}
+
+/// How many reward cards are on the game image: the most cards whose crops
+/// all have text in their middle. With fewer cards, the crops of a layout
+/// with more either miss them or cut them in half. If no count fits, e.g.
+/// when the text of a card is filtered out, every slot is read.
+fn count_cards(img: &DynamicImage, layout: &Layout, empty_slot_density: f32, theme: UiTheme) -> usize {
+    for cards in (1..=layout.crop_coords.len()).rev() {
+        let candidate = layout.with_cards(cards);
+        let fits = candidate.crop_coords.iter().all(|coords| {
+            let crop = crop_slot(img, &candidate, coords);
+            text_density(&crop, theme) >= empty_slot_density
+                && text_offset(&crop, theme).map_or(false, |x| x.abs() <= MAX_TEXT_OFFSET)
+        });
+        if fits {
+            return cards;
+        }
+    }
+
+    layout.crop_coords.len()
+}
+
+/// How far right of the center the text colored pixels are on average, as a
+/// share of the width, `None` without any
+fn text_offset(img: &DynamicImage, theme: UiTheme) -> Option<f32> {
+    let (sum, count) = img
+        .pixels()
+        .filter(|(_, _, color)| is_text_color(to_hsv(color[0], color[1], color[2]), theme))
+        .fold((0u64, 0u64), |(sum, count), (x, _, _)| (sum + x as u64, count + 1));
+    if count == 0 {
+        return None;
+    }
+
+    let mean = sum as f32 / count as f32 + 0.5;
+    Some((mean - img.width() as f32 / 2.0) / img.width() as f32)
+}
+
+/// The theme whose text color covers the largest share of the crops of the
+/// layout, the default one if none covers any
+fn detect_theme(img: &DynamicImage, layout: &Layout) -> UiTheme {
+    let crops: Vec<DynamicImage> = layout
+        .crop_coords
+        .iter()
+        .map(|coords| crop_slot(img, layout, coords))
+        .collect();
+
+    let mut best = (UiTheme::default(), 0.0);
+    for theme in THEMES.iter() {
+        let density: f32 = crops.iter().map(|crop| text_density(crop, *theme)).sum();
+        if density > best.1 {
+            best = (*theme, density);
+        }
+    }
+
+    best.0
+}
+
+/// Draws the outline of every reward crop onto the screenshot, for checking
+/// whether the layout lines up with the reward names
+pub fn draw_crops(img: &DynamicImage, settings: &OCRSettings) -> DynamicImage {
+    const OUTLINE_COLOR: Rgba<u8> = Rgba([255, 0, 0, 255]);
+
+    let layout = settings.layout_for(&settings.game_image(img));
+    let mut img = img.to_rgba8();
+    let (offset_x, offset_y) = match settings.game_region {
+        Some(region) => (region.x, region.y),
+        None => (0, 0),
+    };
+    let [width, height] = layout.crop_size;
+
+    for coords in &layout.crop_coords {
+        let (left, top) = (offset_x + coords[0], offset_y + coords[1]);
+        for x in left..left + width {
+            for y in top..top + height {
+                let on_outline = x < left + OUTLINE_WIDTH
+                    || x >= (left + width).saturating_sub(OUTLINE_WIDTH)
+                    || y < top + OUTLINE_WIDTH
+                    || y >= (top + height).saturating_sub(OUTLINE_WIDTH);
+                if on_outline && x < img.width() && y < img.height() {
+                    img.put_pixel(x, y, OUTLINE_COLOR);
+                }
+            }
+        }
+    }
+
+    DynamicImage::ImageRgba8(img)
+}
+
+/// Creates a tesseract instance with the user's variables applied. Variables
+/// tesseract rejects are warned about and removed, so they are only tried once.
+fn new_tesseract(
+    tessdata_dir: Option<&str>,
+    language: Option<&str>,
+    variables: &mut Vec<(String, String)>,
+) -> Result<tesseract::Tesseract> {
+    // the bundled English data is made for the legacy engine, downloaded
+    // languages may only come with the LSTM one
+    let legacy = language.is_none();
+    let language = language.unwrap_or("eng");
+
+    'retry: loop {
+        let oem = if legacy {
+            tesseract::OcrEngineMode::TesseractOnly
+        } else {
+            tesseract::OcrEngineMode::Default
+        };
+        let mut ts = tesseract::Tesseract::new_with_oem(tessdata_dir, Some(language), oem)
+        .map_err(|e| anyhow::anyhow!("Failed to initialize tesseract: {:?}", e))?
+        .set_variable("tessedit_pageseg_mode", "6")
+        .map_err(|e| anyhow::anyhow!("Failed to configure tesseract: {:?}", e))?;
+
+        // a failed set_variable consumes the instance, so start over without the variable
+        for idx in 0..variables.len() {
+            let (name, value) = &variables[idx];
+            ts = match ts.set_variable(name, value) {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("Ignoring tesseract variable {} = {}: {:?}", name, value, e);
+                    variables.remove(idx);
+                    continue 'retry;
+                }
+            };
+        }
+
+        return Ok(ts);
+    }
+}
+
+/// Reads tesseract variables from a config file in tesseract's own format,
+/// with one `name value` pair per line and `#` comments
+pub fn read_tesseract_config(path: &str) -> Result<Vec<(String, String)>> {
+    let mut variables = Vec::new();
+
+    for line in fs::read_to_string(path)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match line.split_once(char::is_whitespace) {
+            Some((name, value)) => variables.push((name.to_string(), value.trim().to_string())),
+            None => anyhow::bail!("Missing value for tesseract variable '{}' in {}", line, path),
+        }
+    }
+
+    Ok(variables)
+}
+
+fn recognize(
+    ts: &mut Option<tesseract::Tesseract>,
+    tessdata_dir: Option<&str>,
+    language: Option<&str>,
+    variables: &mut Vec<(String, String)>,
+    img: &DynamicImage,
+) -> Result<(String, f32)> {
+    let tesseract = match ts.take() {
+        Some(x) => x,
+        None => new_tesseract(tessdata_dir, language, variables)?,
+    };
+    // passed as raw pixels, so the crop never has to be encoded and written to disk
+    let rgb = img.to_rgb8();
+    let (width, height) = (rgb.width() as i32, rgb.height() as i32);
+    let mut tesseract = tesseract
+        .set_frame(rgb.as_raw(), width, height, 3, width * 3)
+        .map_err(|e| anyhow::anyhow!("Failed to load image: {:?}", e))?
+        .recognize()
+        .map_err(|e| anyhow::anyhow!("Failed to recognize text: {:?}", e))?;
+    let text = tesseract
+        .get_text()
+        .map_err(|e| anyhow::anyhow!("Failed to get text: {:?}", e));
+    let confidence = tesseract.mean_text_conf() as f32 / 100.0;
+    *ts = Some(tesseract);
+
+    Ok((text?, confidence))
+}
+
+/// Runs OCR on a reward crop, returning the text and how sure tesseract is of it
+fn read_text(
+    ts: &mut Option<tesseract::Tesseract>,
+    tessdata_dir: Option<&str>,
+    language: Option<&str>,
+    variables: &mut Vec<(String, String)>,
+    supersample: u32,
+    theme: UiTheme,
+    img: DynamicImage,
+) -> Result<(String, f32)> {
+    // filtering the upscaled crop keeps the smoothed edges of the glyphs, which
+    // are lost when upscaling the already filtered one
+    let img = remove_not_text(&upscale(&img, supersample), IMG_MAX_WHITE_DEV, theme);
+    recognize(ts, tessdata_dir, language, variables, &img)
+}
+
+/// How many characters of `text` have to change for it to read as the item
+/// name, ignoring case. Words are compared one by one, so a dropped, extra or
+/// swapped word only costs its own letters instead of putting the rest of the
+/// name out of line. Words OCR ran together are still caught by comparing the
+/// whole strings.
+///
+/// Based on how [WFInfo](https://github.com/WFCD/WFinfo/blob/a7d4b8311564807cf384495441a18c56f63f7eb1/WFInfo/Data.cs#L830)
+/// matches names.
+pub fn name_distance(text: &str, item_name: &str) -> usize {
+    let text = text.to_lowercase();
+    let name = item_name.to_lowercase();
+
+    let mut words: Vec<Option<&str>> = text.split_whitespace().map(Some).collect();
+    let mut distance = 0;
+    for name_word in name.split_whitespace() {
+        let len = name_word.chars().count();
+        let closest = words
+            .iter()
+            .enumerate()
+            .filter_map(|(i, word)| word.map(|word| (i, levenshtein(word, name_word))))
+            .min_by_key(|(_, diff)| *diff);
+        match closest {
+            // a word that has to be replaced entirely is missing, not misread
+            Some((i, diff)) if diff < len => {
+                distance += diff;
+                words[i] = None;
+            }
+            _ => distance += len,
+        }
+    }
+    distance += words.iter().flatten().map(|word| word.chars().count()).sum::<usize>();
+
+    distance.min(levenshtein(&text, &name))
+}
+
+/// Returns the item with the name closest to `target`, along with its distance
+pub fn find_closest_match(items: &Vec<ShortItem>, target: &str) -> (ShortItem, usize) {
+    let mut lowest_distance = usize::MAX;
     let mut lowest_item = None;
 
     for item in items {
-        let diff = levenshtein(target, &item.item_name);
-        if diff < lowest_levenshtein {
-            lowest_levenshtein = diff;
+        let diff = name_distance(target, &item.item_name);
+        if diff < lowest_distance {
+            lowest_distance = diff;
             lowest_item = Some(item);
         }
     }
 
-    lowest_item.unwrap().clone()
+    (lowest_item.unwrap().clone(), lowest_distance)
 }
 
-fn remove_not_text(img: &DynamicImage, max_dev: f32) -> DynamicImage {
+/// The `n` items with the names closest to `target`, closest first
+pub fn closest_matches<'a>(
+    items: &'a [ShortItem],
+    target: &str,
+    n: usize,
+) -> Vec<(&'a ShortItem, usize)> {
+    let mut matches: Vec<_> = items
+        .iter()
+        .map(|item| (item, name_distance(target, &item.item_name)))
+        .collect();
+    // stable, so ties keep the order of the item list like the single best match
+    matches.sort_by_key(|(_, distance)| *distance);
+    matches.truncate(n);
+    matches
+}
+
+pub fn match_confidence(distance: usize, item_name: &str) -> f32 {
+    (1.0 - distance as f32 / item_name.chars().count().max(1) as f32).max(0.0)
+}
+
+/// Recognizes screens that aren't the reward screen by the text read from the
+/// slots, returning a description of the screen. At least half of the slots
+/// with text have to contain one of its words.
+pub fn detect_wrong_screen(matches: &[SlotMatch]) -> Option<&'static str> {
+    let texts: Vec<String> = matches
+        .iter()
+        .filter(|x| !x.text.is_empty())
+        .map(|x| x.text.to_lowercase())
+        .collect();
+    if texts.is_empty() {
+        return None;
+    }
+
+    WRONG_SCREENS.iter().find_map(|(screen, words)| {
+        let hits = texts
+            .iter()
+            .filter(|text| {
+                text.split(|c: char| !c.is_alphanumeric())
+                    .any(|word| words.contains(&word))
+            })
+            .count();
+        if hits * 2 >= texts.len() {
+            Some(*screen)
+        } else {
+            None
+        }
+    })
+}
+
+fn remove_not_text(img: &DynamicImage, max_dev: f32, theme: UiTheme) -> DynamicImage {
     let mut result = img.clone();
     for pix in img.pixels() {
         let x = pix.0;
@@ -151,7 +937,7 @@ fn remove_not_text(img: &DynamicImage, max_dev: f32) -> DynamicImage {
         let hsv = to_hsv(color[0], color[1], color[2]);
 
         // if !in_range(hsv, (0.095 * 360.0, 0.111, 0.416), (0.15 * 360.0, 1.0, 1.0)) {
-        if !in_range(hsv, (0.075 * 360.0, 0.111, 0.416), (0.35 * 360.0, 1.0, 1.0)) {
+        if !is_text_color(hsv, theme) {
             result.put_pixel(x, y, Pixel::from_channels(0, 0, 0, 255));
         } else {
             result.put_pixel(
@@ -166,13 +952,77 @@ fn remove_not_text(img: &DynamicImage, max_dev: f32) -> DynamicImage {
     result
 }
 
+/// Resizes the image to `factor` times its size, or leaves it as is for 1
+fn upscale(img: &DynamicImage, factor: u32) -> DynamicImage {
+    if factor <= 1 {
+        return img.clone();
+    }
+    img.resize_exact(img.width() * factor, img.height() * factor, FilterType::CatmullRom)
+}
+
+/// Share of the pixels that have the color of reward text, from 0 to 1
+fn text_density(img: &DynamicImage, theme: UiTheme) -> f32 {
+    let total = img.width() * img.height();
+    if total == 0 {
+        return 0.0;
+    }
+
+    let text = img
+        .pixels()
+        .filter(|(_, _, color)| is_text_color(to_hsv(color[0], color[1], color[2]), theme))
+        .count();
+    text as f32 / total as f32
+}
+
+fn is_text_color(color: Hsv, theme: UiTheme) -> bool {
+    let (lower, upper) = theme.text_range();
+    in_range(color, lower, upper)
+}
+
+/// Describes a single pixel of an image, to help with tuning the text color filter
+pub fn sample_pixel(path: &str, x: u32, y: u32) -> Result<String> {
+    let img = image::open(path)?;
+    if !img.in_bounds(x, y) {
+        let (width, height) = img.dimensions();
+        anyhow::bail!("({}, {}) is outside of the {}x{} image", x, y, width, height);
+    }
+
+    let color = img.get_pixel(x, y);
+    let hsv = to_hsv(color[0], color[1], color[2]);
+    let themes: Vec<&str> = THEMES
+        .iter()
+        .filter(|theme| is_text_color(hsv, **theme))
+        .map(|theme| theme.name())
+        .collect();
+
+    Ok(format!(
+        "rgb({}, {}, {}) | hsv({:.1}, {:.3}, {:.3}) | {}",
+        color[0],
+        color[1],
+        color[2],
+        hsv.h,
+        hsv.s,
+        hsv.v,
+        if themes.is_empty() {
+            "not text".to_string()
+        } else {
+            format!("text in the {} theme(s)", themes.join(", "))
+        }
+    ))
+}
+
 fn pixel_dev(pixel: image::Rgba<u8>) -> f32 {
     (255.0 - pixel[0] as f32) + (255.0 - pixel[1] as f32) + (255.0 - pixel[2] as f32)
 }
 
 fn in_range(color: Hsv, lower: (f64, f64, f64), upper: (f64, f64, f64)) -> bool {
     // println!("{:?}", color);
-    if color.h < lower.0 || color.h > upper.0 {
+    let hue_in_range = if lower.0 <= upper.0 {
+        color.h >= lower.0 && color.h <= upper.0
+    } else {
+        color.h >= lower.0 || color.h <= upper.0
+    };
+    if !hue_in_range {
         return false;
     } else if color.s < lower.1 || color.s > upper.1 {
         return false;
@@ -191,7 +1041,7 @@ fn to_hsv(r: u8, g: u8, b: u8) -> Hsv {
     let cmin = r_.min(g_.min(b_));
     let delta = cmax - cmin;
 
-    let hue = 60.0
+    let hue = (60.0
         * if delta == 0.0 {
             0.0
         } else if cmax == r_ {
@@ -200,8 +1050,8 @@ fn to_hsv(r: u8, g: u8, b: u8) -> Hsv {
             (b_ - r_) / delta + 2.0
         } else {
             (r_ - g_) / delta + 4.0
-        }
-        .rem_euclid(360.0);
+        })
+    .rem_euclid(360.0);
 
     let sat = if cmax == 0.0 { 0.0 } else { delta / cmax };
 
@@ -243,6 +1093,12 @@ mod test {
         // assert_eq!((265.0, 0.349, 0.741), to_hsv(150, 123, 189));
     }
 
+    #[test]
+    fn test_to_hsv_magenta_hues() {
+        assert_eq!(Hsv::new(300.0, 1.0, 1.0), to_hsv(255, 0, 255));
+        assert!((to_hsv(255, 0, 128).h - 330.0).abs() < 0.5);
+    }
+
     #[test]
     fn test_to_rgb() {
         assert_eq!((0, 0, 0), to_rgb(0.0, 0.0, 0.0));
@@ -251,4 +1107,268 @@ mod test {
         assert_eq!((0, 255, 0), to_rgb(120.0, 1.0, 1.0));
         assert_eq!((0, 0, 255), to_rgb(240.0, 1.0, 1.0));
     }
+
+    #[test]
+    fn test_text_density() {
+        let mut img = DynamicImage::new_rgba8(10, 10);
+        assert_eq!(text_density(&img, UiTheme::Vanilla), 0.0);
+
+        for x in 0..10 {
+            img.put_pixel(x, 0, Rgba([255, 200, 60, 255]));
+        }
+        assert!((text_density(&img, UiTheme::Vanilla) - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_upscale() {
+        let img = DynamicImage::new_rgba8(25, 5);
+        assert_eq!(upscale(&img, 1).dimensions(), (25, 5));
+        assert_eq!(upscale(&img, 3).dimensions(), (75, 15));
+    }
+
+    /// Rough cost of supersampling a reward crop, run with
+    /// `cargo test --release -- --ignored --nocapture bench_supersample`.
+    /// Tesseract itself also gets slower with the size of the crop.
+    #[test]
+    #[ignore]
+    fn bench_supersample() {
+        const RUNS: u32 = 20;
+
+        let mut img = DynamicImage::new_rgba8(ITEM_CROP_SIZE[0], ITEM_CROP_SIZE[1]);
+        for x in (0..img.width()).step_by(3) {
+            for y in 10..40 {
+                img.put_pixel(x, y, Rgba([255, 200, 60, 255]));
+            }
+        }
+
+        for factor in 1..=MAX_SUPERSAMPLE {
+            let start = std::time::Instant::now();
+            for _ in 0..RUNS {
+                remove_not_text(&upscale(&img, factor), IMG_MAX_WHITE_DEV, UiTheme::Vanilla);
+            }
+            println!("{}x: {:?} per crop", factor, start.elapsed() / RUNS);
+        }
+    }
+
+    #[test]
+    fn test_is_text_color() {
+        let vanilla = UiTheme::Vanilla;
+        assert!(is_text_color(to_hsv(255, 200, 60), vanilla));
+        assert!(!is_text_color(to_hsv(0, 0, 0), vanilla));
+        assert!(!is_text_color(to_hsv(255, 255, 255), vanilla));
+        assert!(!is_text_color(to_hsv(40, 60, 255), vanilla));
+
+        // the red hue range wraps around 0
+        assert!(is_text_color(to_hsv(230, 30, 40), UiTheme::Stalker));
+        assert!(is_text_color(to_hsv(230, 40, 30), UiTheme::Stalker));
+        assert!(!is_text_color(to_hsv(255, 200, 60), UiTheme::Stalker));
+        assert!(is_text_color(to_hsv(158, 159, 167), UiTheme::Equinox));
+        assert!(!is_text_color(to_hsv(255, 255, 255), UiTheme::Equinox));
+        assert!(!is_text_color(to_hsv(160, 160, 160), UiTheme::Equinox));
+    }
+
+    #[test]
+    fn test_detect_theme() {
+        let layout = Layout::default();
+        let with_text = |color| {
+            let mut img = DynamicImage::new_rgba8(1920, 1080);
+            for coords in &layout.crop_coords {
+                for x in coords[0] + 50..coords[0] + 200 {
+                    for y in coords[1] + 15..coords[1] + 35 {
+                        img.put_pixel(x, y, color);
+                    }
+                }
+            }
+            img
+        };
+
+        assert_eq!(detect_theme(&with_text(Rgba([255, 200, 60, 255])), &layout), UiTheme::Vanilla);
+        assert_eq!(detect_theme(&with_text(Rgba([230, 30, 40, 255])), &layout), UiTheme::Stalker);
+        assert_eq!(detect_theme(&with_text(Rgba([90, 200, 255, 255])), &layout), UiTheme::HighContrast);
+        assert_eq!(detect_theme(&with_text(Rgba([158, 159, 167, 255])), &layout), UiTheme::Equinox);
+        assert_eq!(detect_theme(&DynamicImage::new_rgba8(1920, 1080), &layout), UiTheme::Vanilla);
+    }
+
+    fn slot_match(text: &str) -> SlotMatch {
+        SlotMatch {
+            slot: 0,
+            item: None,
+            confidence: 0.0,
+            empty: text.is_empty(),
+            text: text.to_string(),
+            text_confidence: 1.0,
+            closest: None,
+            distance: 0,
+        }
+    }
+
+    #[test]
+    fn test_detect_wrong_screen() {
+        let fissures = [
+            slot_match("Lith Fissure"),
+            slot_match("Exterminate - Earth"),
+            slot_match("Mot"),
+            slot_match(""),
+        ];
+        assert_eq!(detect_wrong_screen(&fissures), Some("the void fissure selection"));
+
+        let relics = [slot_match("Radiant"), slot_match("Intact")];
+        assert_eq!(detect_wrong_screen(&relics), Some("the relic selection"));
+
+        let rewards = [
+            slot_match("Lex Prime Barrel"),
+            slot_match("Forma Blueprint"),
+            slot_match("Nova Prime Systems Blueprint"),
+            slot_match(""),
+        ];
+        assert_eq!(detect_wrong_screen(&rewards), None);
+        assert_eq!(detect_wrong_screen(&[slot_match("")]), None);
+    }
+
+    #[test]
+    fn test_match_threshold() {
+        assert!(MatchThreshold::default().accepts(100, "Forma Blueprint"));
+
+        let absolute = MatchThreshold {
+            max_distance: Some(3),
+            ..Default::default()
+        };
+        assert!(absolute.accepts(3, "Forma Blueprint"));
+        assert!(!absolute.accepts(4, "Forma Blueprint"));
+
+        let ratio = MatchThreshold {
+            max_ratio: Some(0.25),
+            ..Default::default()
+        };
+        assert!(ratio.accepts(2, "Lex Prime"));
+        assert!(!ratio.accepts(3, "Lex Prime"));
+
+        let confidence = MatchThreshold {
+            min_confidence: Some(0.6),
+            ..Default::default()
+        };
+        // "Lex Prime" 6 off is only a third of a match
+        assert!(!confidence.recognizes(6, "Lex Prime", 0.3));
+        assert!(confidence.recognizes(6, "Lex Prime", 0.9));
+        assert!(confidence.recognizes(0, "Lex Prime", 0.3));
+        assert!(MatchThreshold::default().recognizes(6, "Lex Prime", 0.0));
+    }
+
+    #[test]
+    fn test_count_cards() {
+        let layout = Layout::default();
+        assert_eq!(layout.with_cards(4), layout);
+        assert_eq!(layout.with_cards(2).crop_coords, vec![[713, 410], [957, 410]]);
+        assert_eq!(layout.with_cards(1).crop_coords, vec![[835, 410]]);
+
+        for cards in 1..=4 {
+            // a line of text in the middle of every card
+            let mut img = DynamicImage::new_rgba8(1920, 1080);
+            for coords in &layout.with_cards(cards).crop_coords {
+                let center = coords[0] + layout.crop_size[0] / 2;
+                for x in center - 75..center + 75 {
+                    for y in coords[1] + 15..coords[1] + 35 {
+                        img.put_pixel(x, y, Rgba([255, 200, 60, 255]));
+                    }
+                }
+            }
+            assert_eq!(count_cards(&img, &layout, 0.005, UiTheme::Vanilla), cards);
+        }
+
+        let empty = DynamicImage::new_rgba8(1920, 1080);
+        assert_eq!(count_cards(&empty, &layout, 0.005, UiTheme::Vanilla), 4);
+    }
+
+    #[test]
+    fn test_layout_scaled_to() {
+        let layout = Layout::default();
+        assert_eq!(layout.scaled_to(1920, 1080), layout);
+
+        let scaled = layout.scaled_to(2560, 1440);
+        assert_eq!(scaled.crop_size, [333, 67]);
+        assert_eq!(scaled.crop_coords[0], [627, 547]);
+        assert_eq!(scaled.resolution, Some([2560, 1440]));
+
+        // ultrawide: scaled by the height, with the reward row kept centered
+        let scaled = layout.scaled_to(3440, 1440);
+        assert_eq!(scaled.crop_size, [333, 67]);
+        assert_eq!(scaled.crop_coords[0], [1067, 547]);
+
+        let unknown = Layout {
+            resolution: None,
+            ..Layout::default()
+        };
+        assert_eq!(unknown.scaled_to(2560, 1440), unknown);
+    }
+
+    fn short_items(names: &[&str]) -> Vec<ShortItem> {
+        names
+            .iter()
+            .map(|name| ShortItem {
+                url_name: String::new(),
+                thumb: String::new(),
+                id: String::new(),
+                item_name: name.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_closest_matches() {
+        let items = short_items(&["Lex Prime Barrel", "Lex Prime Receiver", "Forma Blueprint"]);
+
+        let matches = closest_matches(&items, "Lex Prlme Barrel", 2);
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].0.item_name, "Lex Prime Barrel");
+        assert_eq!(matches[0].1, 1);
+        assert_eq!(matches[1].0.item_name, "Lex Prime Receiver");
+
+        let (best, distance) = find_closest_match(&items, "Lex Prlme Barrel");
+        assert_eq!((best.item_name.as_str(), distance), ("Lex Prime Barrel", 1));
+    }
+
+    #[test]
+    fn test_name_distance() {
+        assert_eq!(name_distance("Lex Prime Barrel", "Lex Prime Barrel"), 0);
+        assert_eq!(name_distance("LEX PRIME BARREL", "Lex Prime Barrel"), 0);
+        // run together
+        assert_eq!(name_distance("LexPrime Barrel", "Lex Prime Barrel"), 1);
+        // extra and dropped words cost their letters
+        assert_eq!(name_distance("Lex Prime Barrel 1", "Lex Prime Barrel"), 1);
+        assert_eq!(name_distance("Prime Barrel", "Lex Prime Barrel"), 3);
+    }
+
+    /// The rewards on test-data/relic1.jpg, as they wrap on the cards, among
+    /// the other parts of the same items
+    #[test]
+    fn test_noisy_ocr_matches() {
+        let items = short_items(&[
+            "Zhuge Prime Blueprint",
+            "Zhuge Prime Barrel",
+            "Zhuge Prime Receiver",
+            "Tekko Prime Blueprint",
+            "Tekko Prime Gauntlet",
+            "Tekko Prime Plating",
+            "Inaros Prime Blueprint",
+            "Inaros Prime Chassis Blueprint",
+            "Inaros Prime Systems Blueprint",
+            "Octavia Prime Blueprint",
+            "Octavia Prime Neuroptics Blueprint",
+            "Octavia Prime Systems Blueprint",
+        ]);
+        let closest = |text| find_closest_match(&items, text).0.item_name;
+
+        assert_eq!(closest("Zhuge Prime Blueprint"), "Zhuge Prime Blueprint");
+        assert_eq!(closest("Tekko Prime Gauntlet"), "Tekko Prime Gauntlet");
+        assert_eq!(closest("Inaros Prime Systems\nBlueprint"), "Inaros Prime Systems Blueprint");
+        assert_eq!(
+            closest("Octavia Prime Neuroptics\nBlueprint"),
+            "Octavia Prime Neuroptics Blueprint"
+        );
+        // the edge of the highlighted card and the "+5" below it
+        assert_eq!(closest("| Zhuge Prime Blueprint +5"), "Zhuge Prime Blueprint");
+        // a crop too narrow for the whole name
+        assert_eq!(closest("Prime Gauntlet"), "Tekko Prime Gauntlet");
+        assert_eq!(closest("aros Prime Systems\nBlueprint"), "Inaros Prime Systems Blueprint");
+    }
 }
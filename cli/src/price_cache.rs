@@ -0,0 +1,137 @@
+use crate::util::price_cache_path;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use wfm_rs::response::MarketStatisticsWrapper;
+
+/// Statistics fetched in earlier sessions, kept in the cache directory so they
+/// don't have to be fetched again after a restart
+#[derive(Serialize, Deserialize, Default)]
+pub struct PriceCache {
+    /// Keyed by platform and item id, see `key`
+    entries: HashMap<String, CacheEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    /// When the statistics were fetched
+    timestamp: u64,
+    statistics: MarketStatisticsWrapper,
+}
+
+fn key(platform: &str, item_id: &str) -> String {
+    format!("{}/{}", platform, item_id)
+}
+
+impl PriceCache {
+    /// Reads the cache file, starting over with an empty cache if there is none
+    /// or it can't be read
+    pub fn load() -> PriceCache {
+        let path = match price_cache_path() {
+            Ok(x) => x,
+            Err(_) => return PriceCache::default(),
+        };
+        let contents = match fs::read_to_string(&path) {
+            Ok(x) => x,
+            Err(_) => return PriceCache::default(),
+        };
+
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            eprintln!("Ignoring the unreadable price cache {}: {}", path.to_string_lossy(), e);
+            PriceCache::default()
+        })
+    }
+
+    /// Writes the cache file, replacing it only once the new one is complete.
+    /// Entries older than `max_age_s` (as of `now`) are dropped first, they
+    /// would never be used again.
+    pub fn save(&mut self, max_age_s: u64, now: u64) -> Result<()> {
+        self.remove_outdated(max_age_s, now);
+
+        let path = price_cache_path()?;
+        let mut tmp_path = path.clone();
+        tmp_path.set_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string(self)?)?;
+        fs::rename(tmp_path, path)?;
+
+        Ok(())
+    }
+
+    /// The statistics of the item, unless there are none fetched within the
+    /// last `max_age_s` seconds (as of `now`)
+    pub fn get(
+        &self,
+        platform: &str,
+        item_id: &str,
+        max_age_s: u64,
+        now: u64,
+    ) -> Option<&MarketStatisticsWrapper> {
+        self.entries
+            .get(&key(platform, item_id))
+            .filter(|x| now.saturating_sub(x.timestamp) <= max_age_s)
+            .map(|x| &x.statistics)
+    }
+
+    fn remove_outdated(&mut self, max_age_s: u64, now: u64) {
+        self.entries
+            .retain(|_, x| now.saturating_sub(x.timestamp) <= max_age_s);
+    }
+
+    pub fn insert(
+        &mut self,
+        platform: &str,
+        item_id: &str,
+        statistics: MarketStatisticsWrapper,
+        now: u64,
+    ) {
+        self.entries.insert(
+            key(platform, item_id),
+            CacheEntry {
+                timestamp: now,
+                statistics,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wfm_rs::response::MarketStatistics;
+
+    fn statistics() -> MarketStatisticsWrapper {
+        MarketStatisticsWrapper {
+            statistics_closed: MarketStatistics {
+                _48_hours: Vec::new(),
+                _90_days: Vec::new(),
+            },
+            statistics_live: MarketStatistics {
+                _48_hours: Vec::new(),
+                _90_days: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_price_cache_freshness() {
+        let mut cache = PriceCache::default();
+        cache.insert("pc", "abc", statistics(), 1000);
+
+        assert!(cache.get("pc", "abc", 60, 1060).is_some());
+        assert!(cache.get("pc", "abc", 60, 1061).is_none());
+        assert!(cache.get("ps4", "abc", 60, 1000).is_none());
+        assert!(cache.get("pc", "def", 60, 1000).is_none());
+    }
+
+    #[test]
+    fn test_price_cache_remove_outdated() {
+        let mut cache = PriceCache::default();
+        cache.insert("pc", "abc", statistics(), 1000);
+        cache.insert("pc", "def", statistics(), 1050);
+
+        cache.remove_outdated(60, 1100);
+        assert!(cache.get("pc", "abc", u64::MAX, 1100).is_none());
+        assert!(cache.get("pc", "def", u64::MAX, 1100).is_some());
+    }
+}
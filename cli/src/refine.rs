@@ -0,0 +1,180 @@
+use crate::scan::ItemStats;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rarity {
+    Common,
+    Uncommon,
+    Rare,
+}
+
+impl Rarity {
+    /// Parses the rarity as returned by warframe.market
+    pub fn from_api(rarity: &str) -> Option<Rarity> {
+        match rarity {
+            "common" => Some(Rarity::Common),
+            "uncommon" => Some(Rarity::Uncommon),
+            "rare" => Some(Rarity::Rare),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rarity::Common => "common",
+            Rarity::Uncommon => "uncommon",
+            Rarity::Rare => "rare",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Refinement {
+    Intact,
+    Exceptional,
+    Flawless,
+    Radiant,
+}
+
+impl Refinement {
+    const ALL: [Refinement; 4] = [
+        Refinement::Intact,
+        Refinement::Exceptional,
+        Refinement::Flawless,
+        Refinement::Radiant,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Refinement::Intact => "Intact",
+            Refinement::Exceptional => "Exceptional",
+            Refinement::Flawless => "Flawless",
+            Refinement::Radiant => "Radiant",
+        }
+    }
+
+    /// Chance of getting one specific common, uncommon and rare reward.
+    /// Every relic has 3 common, 2 uncommon and 1 rare reward.
+    fn drop_chances(&self) -> (f32, f32, f32) {
+        match self {
+            Refinement::Intact => (0.2533, 0.11, 0.02),
+            Refinement::Exceptional => (0.2333, 0.13, 0.04),
+            Refinement::Flawless => (0.20, 0.17, 0.06),
+            Refinement::Radiant => (0.1667, 0.20, 0.10),
+        }
+    }
+}
+
+pub struct Recommendation {
+    pub refinement: Refinement,
+    /// Expected platinum per run at the recommended refinement
+    pub expected_value: f32,
+    /// Expected platinum per run of an intact relic
+    pub intact_value: f32,
+    /// The most valuable rare reward, if any
+    pub rare: Option<(String, f32)>,
+}
+
+/// Recommends a refinement for the relic the rewards came from, based on the
+/// expected value of a run at every refinement. Rarities missing from the
+/// rewards are assumed to be worth the average of the known ones.
+pub fn recommend(items: &[ItemStats]) -> Option<Recommendation> {
+    let priced: Vec<(Rarity, f32)> = items
+        .iter()
+        .filter(|x| !x.avg_price.is_nan())
+        .filter_map(|x| Some((x.rarity?, x.avg_price)))
+        .collect();
+
+    if priced.is_empty() {
+        return None;
+    }
+
+    let overall = priced.iter().map(|x| x.1).sum::<f32>() / priced.len() as f32;
+    let average = |rarity: Rarity| {
+        let prices: Vec<f32> = priced
+            .iter()
+            .filter(|x| x.0 == rarity)
+            .map(|x| x.1)
+            .collect();
+        if prices.is_empty() {
+            overall
+        } else {
+            prices.iter().sum::<f32>() / prices.len() as f32
+        }
+    };
+    let (common, uncommon, rare) = (
+        average(Rarity::Common),
+        average(Rarity::Uncommon),
+        average(Rarity::Rare),
+    );
+    let expected_value = |refinement: Refinement| {
+        let (c, u, r) = refinement.drop_chances();
+        3.0 * c * common + 2.0 * u * uncommon + r * rare
+    };
+
+    // refining costs void traces, so only recommend it when it actually gains something
+    let mut best = Refinement::Intact;
+    for refinement in Refinement::ALL.iter().copied() {
+        if expected_value(refinement) > expected_value(best) + 0.01 {
+            best = refinement;
+        }
+    }
+
+    let rare = items
+        .iter()
+        .filter(|x| x.rarity == Some(Rarity::Rare) && !x.avg_price.is_nan())
+        .max_by(|a, b| a.avg_price.total_cmp(&b.avg_price))
+        .map(|x| (x.item.item_name.clone(), x.avg_price));
+
+    Some(Recommendation {
+        refinement: best,
+        expected_value: expected_value(best),
+        intact_value: expected_value(Refinement::Intact),
+        rare,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn item_stats(name: &str, avg_price: f32, rarity: Rarity) -> ItemStats {
+        ItemStats {
+            rarity: Some(rarity),
+            ..ItemStats::test_default(name, avg_price)
+        }
+    }
+
+    #[test]
+    fn test_drop_chances_sum_to_one() {
+        for refinement in Refinement::ALL.iter() {
+            let (c, u, r) = refinement.drop_chances();
+            assert!((3.0 * c + 2.0 * u + r - 1.0).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn test_recommend_valuable_rare() {
+        let items = vec![
+            item_stats("a", 2.0, Rarity::Common),
+            item_stats("b", 3.0, Rarity::Common),
+            item_stats("c", 5.0, Rarity::Uncommon),
+            item_stats("d", 40.0, Rarity::Rare),
+        ];
+        let recommendation = recommend(&items).unwrap();
+        assert_eq!(recommendation.refinement, Refinement::Radiant);
+        assert_eq!(recommendation.rare, Some(("d".to_string(), 40.0)));
+        assert!(recommendation.expected_value > recommendation.intact_value);
+    }
+
+    #[test]
+    fn test_recommend_flat_prices() {
+        let items = vec![
+            item_stats("a", 10.0, Rarity::Common),
+            item_stats("b", 10.0, Rarity::Uncommon),
+            item_stats("c", 10.0, Rarity::Rare),
+        ];
+        assert_eq!(recommend(&items).unwrap().refinement, Refinement::Intact);
+    }
+}
@@ -0,0 +1,1365 @@
+use crate::{
+    capture::capture_image,
+    config::{self, Config},
+    misses::{self, Miss, LOW_CONFIDENCE},
+    ocr::{self, Layout, OCREngine, SlotMatch},
+    price_cache::PriceCache,
+    refine::Rarity,
+    util::unix_timestamp,
+};
+use anyhow::Result;
+use image::{DynamicImage, GenericImageView};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+use wfm_rs::response::{MarketStatisticsWrapper, Order, ShortItem};
+use wfm_rs::User;
+
+/// Length of the statistics window the prices and volumes are taken from
+const STATS_WINDOW_HOURS: f32 = 48.0;
+/// How long to wait before capturing the slots that looked empty again, the
+/// frame right after a bad one is likely just as bad
+const RECAPTURE_DELAY: Duration = Duration::from_millis(100);
+/// How many of the cheapest sell orders the order book shows
+const ORDER_BOOK_SELLS: usize = 5;
+
+#[derive(Clone, Serialize)]
+pub struct ItemStats {
+    pub volume: f32,
+    /// Rough estimate of how many sell each day, derived from the volume
+    pub sales_per_day: f32,
+    pub avg_price: f32,
+    pub min_price: f32,
+    pub max_price: f32,
+    /// Average price of the live sell orders, when shown next to the closed trades
+    pub live_price: Option<f32>,
+    /// Lowest live sell order, what buying the item costs, only with `show_spread`
+    pub best_sell: Option<f32>,
+    /// Highest live buy order, what selling the item right away gets
+    pub best_buy: Option<f32>,
+    /// Current orders of online users, only with `show_order_book`
+    pub order_book: Option<OrderBook>,
+    pub item: ShortItem,
+    pub slot: usize,
+    pub watched: bool,
+    /// How confidently the item was read, from 0 to 1
+    pub confidence: f32,
+    /// Combined price, volume and confidence rating, if enabled
+    pub score: Option<f32>,
+    /// Relic rarity of the item, only looked up for refinement advice
+    pub rarity: Option<Rarity>,
+    /// Ducats the item is worth, only looked up for the ducat ratio
+    pub ducats: Option<u16>,
+    /// Price of the user's own sell order for the item, if they have one
+    pub my_listing: Option<f32>,
+    /// Prime set the item is a part of, only looked up for grouping by set
+    pub set: Option<ItemSet>,
+}
+
+/// What the item can be traded for right now, by the orders of users that
+/// are online or in game
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct OrderBook {
+    /// The cheapest sell orders, lowest first
+    pub lowest_sells: Vec<f32>,
+    pub highest_buy: Option<f32>,
+}
+
+#[derive(Clone, Serialize, Debug, PartialEq)]
+pub struct ItemSet {
+    pub name: String,
+    /// How many parts make up the set
+    pub parts: usize,
+}
+
+impl ItemStats {
+    /// How much more the cheapest seller asks than the best buyer offers
+    pub fn spread(&self) -> Option<f32> {
+        Some(self.best_sell? - self.best_buy?)
+    }
+
+    /// Platinum given up for every ducat when trading the item in for ducats
+    pub fn plat_per_ducat(&self) -> Option<f32> {
+        match self.ducats {
+            Some(ducats) if ducats > 0 && !self.avg_price.is_nan() => {
+                Some(self.avg_price / ducats as f32)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+impl ItemStats {
+    /// An item read with full confidence, with nothing but its name and price
+    pub fn test_default(name: &str, avg_price: f32) -> ItemStats {
+        ItemStats {
+            volume: 0.0,
+            sales_per_day: 0.0,
+            avg_price,
+            min_price: f32::NAN,
+            max_price: f32::NAN,
+            live_price: None,
+            best_sell: None,
+            best_buy: None,
+            order_book: None,
+            item: ShortItem {
+                url_name: String::new(),
+                thumb: String::new(),
+                id: String::new(),
+                item_name: name.to_string(),
+            },
+            slot: 0,
+            watched: false,
+            confidence: 1.0,
+            score: None,
+            rarity: None,
+            ducats: None,
+            my_listing: None,
+            set: None,
+        }
+    }
+}
+
+/// Which statistics the displayed prices are based on
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PriceBasis {
+    /// Prices of closed trades, lagging behind the market a bit
+    Closed,
+    /// Prices of the current sell orders, which can include bait listings
+    Live,
+    /// Closed trade prices, with the live sell price next to them
+    Both,
+}
+
+impl Default for PriceBasis {
+    fn default() -> Self {
+        PriceBasis::Closed
+    }
+}
+
+/// How the closed trade prices of the window are combined into one price
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PriceMetric {
+    Mean,
+    Median,
+    /// A low percentile (`quick_sell_percentile`), a price the item can be
+    /// expected to sell at quickly
+    QuickSell,
+}
+
+impl Default for PriceMetric {
+    fn default() -> Self {
+        PriceMetric::Mean
+    }
+}
+
+impl PriceMetric {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PriceMetric::Mean => "average",
+            PriceMetric::Median => "median",
+            PriceMetric::QuickSell => "quick-sell",
+        }
+    }
+}
+
+/// What happens to the scan when fetching the statistics of one item fails
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SlotErrorBehavior {
+    /// Leave the item out of the results
+    Skip,
+    /// List the item as failed, along with the error
+    Mark,
+    /// Fail the whole scan
+    Abort,
+}
+
+impl Default for SlotErrorBehavior {
+    fn default() -> Self {
+        SlotErrorBehavior::Skip
+    }
+}
+
+/// Weights of the "deal score" that items are ranked by instead of just their price
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct DealScoreWeights {
+    #[serde(default = "default_price_weight")]
+    pub price: f32,
+    #[serde(default = "default_volume_weight")]
+    pub volume: f32,
+    #[serde(default = "default_confidence_weight")]
+    pub confidence: f32,
+}
+
+fn default_price_weight() -> f32 {
+    1.0
+}
+
+fn default_volume_weight() -> f32 {
+    0.5
+}
+
+fn default_confidence_weight() -> f32 {
+    0.5
+}
+
+impl DealScoreWeights {
+    /// Scores every item from 0 to 1, with price and volume relative to the best in the scan
+    fn apply(&self, items: &mut [ItemStats]) {
+        let max_price = items.iter().map(|x| x.avg_price).fold(0.0, f32::max);
+        let max_volume = items.iter().map(|x| x.volume).fold(0.0, f32::max);
+        let total_weight = self.price + self.volume + self.confidence;
+
+        for item in items.iter_mut() {
+            let price = if max_price > 0.0 { item.avg_price / max_price } else { 0.0 };
+            let volume = if max_volume > 0.0 { item.volume / max_volume } else { 0.0 };
+            let score = self.price * price + self.volume * volume + self.confidence * item.confidence;
+
+            item.score = if total_weight > 0.0 {
+                Some(score / total_weight)
+            } else {
+                Some(0.0)
+            };
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Scan {
+    pub timestamp: u64,
+    /// Ranked from best to worst
+    pub items: Vec<ItemStats>,
+    /// Every slot of the layout in order, whatever became of it
+    pub slots: Vec<ScanResult>,
+    /// How many matched items the API didn't know about
+    #[serde(skip)]
+    pub not_found: usize,
+}
+
+/// Everything known about one reward slot, in a single typed value
+#[derive(Clone, Serialize)]
+pub struct ScanResult {
+    pub slot_index: usize,
+    /// `None` if the slot is empty or its text couldn't be matched to an item
+    pub item: Option<ShortItem>,
+    pub ocr_confidence: f32,
+    /// The text as read by tesseract
+    pub text: String,
+    /// How sure tesseract is of the text, from 0 to 1
+    pub text_confidence: f32,
+    /// NaN if there are no statistics for the item
+    pub avg_price: f32,
+    pub volume: f32,
+    pub is_empty: bool,
+    /// Why the statistics of the item couldn't be fetched, with `on_slot_error`
+    /// set to mark
+    pub error: Option<String>,
+}
+
+impl ScanResult {
+    fn new(slot_match: &SlotMatch) -> ScanResult {
+        ScanResult {
+            slot_index: slot_match.slot,
+            item: slot_match.item.clone(),
+            ocr_confidence: slot_match.confidence,
+            text: slot_match.text.clone(),
+            text_confidence: slot_match.text_confidence,
+            avg_price: f32::NAN,
+            volume: 0.0,
+            is_empty: slot_match.empty,
+            error: None,
+        }
+    }
+}
+
+impl Scan {
+    pub fn best(&self) -> Option<&ItemStats> {
+        self.items.first()
+    }
+
+    /// Slots whose text couldn't be matched to an item
+    pub fn unreadable(&self) -> impl Iterator<Item = &ScanResult> {
+        self.slots
+            .iter()
+            .filter(|x| x.item.is_none() && !x.is_empty)
+    }
+
+    /// Items that were read, but whose statistics couldn't be fetched
+    pub fn failed(&self) -> impl Iterator<Item = &ScanResult> {
+        self.slots.iter().filter(|x| x.error.is_some())
+    }
+}
+
+/// Turns screenshots into prices, keeping track of what's needed across scans
+pub struct Scanner {
+    engine: OCREngine,
+    consecutive_not_found: usize,
+    last_auto_refresh: u64,
+    /// The screenshot of the previous scan, so it can be processed again
+    last_image: Option<DynamicImage>,
+}
+
+impl Scanner {
+    pub fn new(engine: OCREngine) -> Scanner {
+        Scanner {
+            engine,
+            consecutive_not_found: 0,
+            last_auto_refresh: 0,
+            last_image: None,
+        }
+    }
+
+    /// The last error every OCR worker ran into, indexed by worker
+    pub fn worker_errors(&self) -> Vec<Option<String>> {
+        self.engine.last_errors()
+    }
+
+    /// Scans with a different layout from now on
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.engine.set_layout(layout);
+    }
+
+    /// Captures the screen, then reads and prices the rewards on it
+    pub async fn scan(&mut self, config: &mut Config, user: &mut User) -> Result<Scan> {
+        let img = self.capture_settled(config).await?;
+        check_frame_quality(config, &img)?;
+        let mut matches = self.engine.ocr(&img)?;
+
+        // a real reward's text can be partially filtered out on a bad frame,
+        // so slots only count as empty if they look empty in several captures
+        for _ in 1..config.empty_slot_reads {
+            let failed: Vec<usize> = matches.iter().filter(|x| x.empty).map(|x| x.slot).collect();
+            if failed.is_empty() {
+                break;
+            }
+
+            tokio::time::sleep(RECAPTURE_DELAY).await;
+            let img = capture_image(config).await?;
+            for retry in self.engine.ocr_slots(&img, Some(&failed))? {
+                let slot_match = matches.iter_mut().find(|x| x.slot == retry.slot);
+                if let Some(slot_match) = slot_match {
+                    *slot_match = retry;
+                }
+            }
+        }
+
+        let scan = self.scan_matches(config, user, &img, matches).await;
+        self.last_image = Some(img);
+
+        scan
+    }
+
+    /// Captures the screen, with `wait_for_settle` only once the reward crops
+    /// look the same in two captures in a row, so the reveal animation of the
+    /// rewards is over
+    async fn capture_settled(&mut self, config: &Config) -> Result<DynamicImage> {
+        let mut img = capture_image(config).await?;
+        self.engine.detect_layout(&img);
+        if !config.wait_for_settle {
+            return Ok(img);
+        }
+
+        let start = Instant::now();
+        loop {
+            tokio::time::sleep(Duration::from_millis(config.settle_interval_ms)).await;
+            let next = capture_image(config).await?;
+            let difference = self
+                .engine
+                .crops(&img)
+                .iter()
+                .zip(self.engine.crops(&next).iter())
+                .map(|(a, b)| frame_difference(a, b))
+                .fold(0.0, f32::max);
+            img = next;
+
+            if difference <= config.settle_threshold {
+                return Ok(img);
+            }
+            if start.elapsed() >= Duration::from_millis(config.settle_timeout_ms) {
+                eprintln!(
+                    "The rewards were still changing after {} ms, scanning anyway",
+                    config.settle_timeout_ms
+                );
+                return Ok(img);
+            }
+        }
+    }
+
+    /// Reads and prices the rewards on an existing screenshot, read from stdin
+    /// if `path` is `-`
+    pub async fn scan_file(&mut self, config: &mut Config, user: &mut User, path: &str) -> Result<Scan> {
+        let img = open_image(path)?;
+        self.scan_image(config, user, img).await
+    }
+
+    /// Reads and prices the rewards on an already decoded screenshot
+    pub async fn scan_image(&mut self, config: &mut Config, user: &mut User, img: DynamicImage) -> Result<Scan> {
+        self.engine.detect_layout(&img);
+        let matches = self.engine.ocr(&img)?;
+        let scan = self.scan_matches(config, user, &img, matches).await;
+        self.last_image = Some(img);
+
+        scan
+    }
+
+    /// Processes the screenshot of the previous scan again, using the current
+    /// match threshold of the config
+    pub async fn rescan_last(&mut self, config: &mut Config, user: &mut User) -> Result<Option<Scan>> {
+        self.engine.set_match_threshold(config.match_threshold);
+
+        let img = match self.last_image.take() {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        let matches = self.engine.ocr(&img)?;
+        let scan = self.scan_matches(config, user, &img, matches).await;
+        self.last_image = Some(img);
+
+        scan.map(Some)
+    }
+
+    /// Prices the items read from `img`
+    async fn scan_matches(
+        &mut self,
+        config: &mut Config,
+        user: &mut User,
+        img: &DynamicImage,
+        mut matches: Vec<SlotMatch>,
+    ) -> Result<Scan> {
+        if config.detect_wrong_screen {
+            if let Some(screen) = ocr::detect_wrong_screen(&matches) {
+                anyhow::bail!(
+                    "This looks like {} rather than the reward screen, press the hotkey once the rewards are shown",
+                    screen
+                );
+            }
+        }
+
+        // names read in the game language are shown and priced as the English item
+        for slot_match in matches.iter_mut() {
+            slot_match.item = slot_match.item.take().map(|x| config.canonical_item(x));
+        }
+        let mut scan = fetch_item_stats(config, user, matches).await?;
+
+        // a run of 404s means the cached item list is probably older than the game
+        self.consecutive_not_found = if scan.not_found > 0 {
+            self.consecutive_not_found + scan.not_found
+        } else {
+            0
+        };
+        let now = unix_timestamp()?;
+        if !config.no_refresh
+            && self.consecutive_not_found >= config.not_found_refresh_threshold
+            && now - self.last_auto_refresh >= config.auto_refresh_cooldown_s
+        {
+            eprintln!(
+                "{} items not found in a row, refreshing the item list...",
+                self.consecutive_not_found
+            );
+            self.consecutive_not_found = 0;
+            self.last_auto_refresh = now;
+
+            match config::refresh_items(config).await {
+                Ok(()) => {
+                    self.engine.set_items(config.ocr_items());
+                    let items = self.engine.ocr(img)?;
+                    scan = fetch_item_stats(config, user, items).await?;
+                }
+                Err(e) => eprintln!("Failed to refresh the item list: {}", e),
+            }
+        }
+
+        if let Some(weights) = &config.deal_score {
+            weights.apply(&mut scan.items);
+        }
+        rank_items(&mut scan.items, config.rank_by_ducat_ratio);
+        if config.group_by_set {
+            group_by_set(&mut scan.items);
+        }
+        Ok(scan)
+    }
+}
+
+/// Opens the image at `path`, or decodes the image piped to stdin if it is `-`
+pub fn open_image(path: &str) -> Result<DynamicImage> {
+    if path != "-" {
+        return Ok(image::open(path)?);
+    }
+
+    let mut bytes = Vec::new();
+    io::stdin().read_to_end(&mut bytes)?;
+    if bytes.is_empty() {
+        anyhow::bail!("Nothing was piped to stdin, expected an image");
+    }
+    Ok(image::load_from_memory(&bytes)?)
+}
+
+/// Rejects captures too dark or too flat to be read, so they fail with a clear
+/// message instead of a scan full of misreads
+fn check_frame_quality(config: &Config, img: &DynamicImage) -> Result<()> {
+    let (brightness, contrast) = frame_quality(img);
+    if brightness < config.min_brightness {
+        anyhow::bail!(
+            "The capture is too dark (brightness {:.3}, min_brightness is {}), is the game minimized or loading?",
+            brightness,
+            config.min_brightness
+        );
+    }
+    if contrast < config.min_contrast {
+        anyhow::bail!(
+            "The capture has too little contrast (contrast {:.3}, min_contrast is {}), try again once the screen has faded in",
+            contrast,
+            config.min_contrast
+        );
+    }
+
+    Ok(())
+}
+
+/// Mean and standard deviation of the brightness of the pixels, both from 0 to 1
+fn frame_quality(img: &DynamicImage) -> (f32, f32) {
+    let luma = img.to_luma8();
+    if luma.is_empty() {
+        return (0.0, 0.0);
+    }
+
+    let n = luma.len() as f64;
+    let (sum, sum_squares) = luma.iter().fold((0.0, 0.0), |(sum, squares), x| {
+        let x = *x as f64 / 255.0;
+        (sum + x, squares + x * x)
+    });
+    let mean = sum / n;
+    let variance = (sum_squares / n - mean * mean).max(0.0);
+    (mean as f32, variance.sqrt() as f32)
+}
+
+/// Mean difference of the pixels of two images of the same size, from 0 for
+/// identical images to 1. Images of different sizes are as different as it gets.
+fn frame_difference(a: &DynamicImage, b: &DynamicImage) -> f32 {
+    if a.dimensions() != b.dimensions() {
+        return 1.0;
+    }
+    let (a, b) = (a.to_rgb8(), b.to_rgb8());
+    if a.is_empty() {
+        return 0.0;
+    }
+
+    let total: u64 = a
+        .iter()
+        .zip(b.iter())
+        .map(|(a, b)| (*a as i32 - *b as i32).abs() as u64)
+        .sum();
+    total as f32 / (a.len() as f32 * 255.0)
+}
+
+/// Fetches the statistics of every scanned item
+async fn fetch_item_stats(
+    config: &mut Config,
+    user: &mut User,
+    items: Vec<SlotMatch>,
+) -> Result<Scan> {
+    let mut scan = Scan {
+        timestamp: unix_timestamp()?,
+        items: Vec::new(),
+        slots: items.iter().map(ScanResult::new).collect(),
+        not_found: 0,
+    };
+
+    let my_listings = if config.compare_listings && config.logged_in() {
+        match get_my_listings(user).await {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("Failed to fetch your listings: {}", e);
+                HashMap::new()
+            }
+        }
+    } else {
+        HashMap::new()
+    };
+
+    let (log_misses, log_rotation) = (config.log_misses, config.log_rotation);
+    let log_miss = |slot_match: &SlotMatch, miss: Miss| {
+        if log_misses {
+            if let Err(e) = misses::record(slot_match, miss, &log_rotation) {
+                eprintln!("Failed to write to the misses log: {}", e);
+            }
+        }
+    };
+
+    let mut tradable = Vec::new();
+    for slot_match in items {
+        let item = match slot_match.item.clone() {
+            Some(x) => x,
+            None if slot_match.empty => continue,
+            None => {
+                if !slot_match.text.is_empty() {
+                    log_miss(&slot_match, Miss::Rejected);
+                }
+                continue;
+            }
+        };
+        if slot_match.confidence < LOW_CONFIDENCE {
+            log_miss(&slot_match, Miss::LowConfidence);
+        }
+
+        // items without a url (e.g. Forma) can't be traded, so there are no statistics
+        if item.url_name.is_empty() || config.is_ignored(&item) {
+            continue;
+        }
+
+        tradable.push((slot_match, item));
+    }
+
+    let items: Vec<&ShortItem> = tradable.iter().map(|(_, item)| item).collect();
+    let statistics = fetch_statistics(config, user, &items).await;
+
+    for ((slot_match, item), statistics) in tradable.into_iter().zip(statistics) {
+        let i = slot_match.slot;
+        let item_info = match statistics {
+            Ok(statistics) => item_info(statistics, &item, user, config).await,
+            Err(e) => Err(e),
+        };
+
+        match item_info {
+            Ok(mut item_stats) => {
+                if !item_stats.avg_price.is_nan() {
+                    item_stats.slot = i;
+                    item_stats.watched = config.is_watched(&item_stats.item);
+                    item_stats.confidence = slot_match.confidence;
+                    item_stats.my_listing = my_listings.get(&item.url_name).copied();
+                    if config.needs_item_details() {
+                        if let Ok(details) = get_item_details(&item, user).await {
+                            item_stats.rarity = details.rarity;
+                            item_stats.ducats = details.ducats;
+                            item_stats.set = details.set;
+                        }
+                    }
+                    scan.items.push(item_stats);
+                }
+            }
+            Err(e) => {
+                if wfm_rs::error_status(&e) == Some(404) {
+                    scan.not_found += 1;
+                    log_miss(&slot_match, Miss::NotFound);
+                }
+
+                match config.on_slot_error {
+                    SlotErrorBehavior::Skip => {}
+                    SlotErrorBehavior::Mark => {
+                        if let Some(result) = scan.slots.iter_mut().find(|x| x.slot_index == i) {
+                            result.error = Some(e.to_string());
+                        }
+                    }
+                    SlotErrorBehavior::Abort => return Err(e),
+                }
+            }
+        }
+    }
+
+    for item_stats in &scan.items {
+        if let Some(result) = scan.slots.iter_mut().find(|x| x.slot_index == item_stats.slot) {
+            result.avg_price = item_stats.avg_price;
+            result.volume = item_stats.volume;
+        }
+    }
+
+    scan.slots.sort_by_key(|x| x.slot_index);
+    Ok(scan)
+}
+
+/// Looks up a single item
+pub async fn get_item_info(
+    item: &ShortItem,
+    user: &mut User,
+    config: &mut Config,
+) -> Result<ItemStats> {
+    let statistics = fetch_statistics(config, user, &[item]).await.remove(0)?;
+    item_info(statistics, item, user, config).await
+}
+
+/// Statistics of every item, in the same order. If the login expired, this
+/// asks to log in again and retries, replacing `user` with one that has the
+/// new token.
+async fn fetch_statistics(
+    config: &mut Config,
+    user: &mut User,
+    items: &[&ShortItem],
+) -> Vec<Result<MarketStatisticsWrapper>> {
+    let statistics = cached_statistics(config, user, items).await;
+    let expired = statistics
+        .iter()
+        .any(|x| matches!(x, Err(e) if config::is_expired_login(e)));
+    if !expired || !config.logged_in() {
+        return statistics;
+    }
+
+    if let Err(e) = config::relogin(config).await {
+        eprintln!("Failed to log in again: {}", e);
+        return statistics;
+    }
+    *user = config.user();
+    cached_statistics(config, user, items).await
+}
+
+/// Statistics of every item, in the same order. With `price_cache`, fresh
+/// enough statistics come from the cache file and only the rest are fetched,
+/// which are then written to it.
+async fn cached_statistics(
+    config: &Config,
+    user: &User,
+    items: &[&ShortItem],
+) -> Vec<Result<MarketStatisticsWrapper>> {
+    let platform = config.stats_platform();
+    let now = unix_timestamp().unwrap_or(0);
+    let mut cache = if config.price_cache {
+        Some(PriceCache::load())
+    } else {
+        None
+    };
+
+    let cached: Vec<Option<MarketStatisticsWrapper>> = items
+        .iter()
+        .map(|item| {
+            cache
+                .as_ref()
+                .and_then(|x| x.get(platform, &item.id, config.price_cache_max_age_s, now))
+                .cloned()
+        })
+        .collect();
+
+    // all statistics requests are made at once, rather than one item after the other
+    let urls: Vec<&str> = items
+        .iter()
+        .zip(&cached)
+        .filter(|(_, cached)| cached.is_none())
+        .map(|(item, _)| item.url_name.as_str())
+        .collect();
+    let mut fetched = user.get_items_market_statistics(&urls).await.into_iter();
+
+    let statistics = items
+        .iter()
+        .zip(cached)
+        .map(|(item, cached)| match cached {
+            Some(x) => Ok(x),
+            None => {
+                let statistics = fetched.next().unwrap();
+                if let (Some(cache), Ok(statistics)) = (&mut cache, &statistics) {
+                    cache.insert(platform, &item.id, statistics.clone(), now);
+                }
+                statistics
+            }
+        })
+        .collect();
+
+    if let Some(mut cache) = cache {
+        if let Err(e) = cache.save(config.price_cache_max_age_s, now) {
+            eprintln!("Failed to write the price cache: {}", e);
+        }
+    }
+
+    statistics
+}
+
+/// Prices and volume of the item from its statistics, plus its live orders if
+/// the price basis needs them
+async fn item_info(
+    statistics: MarketStatisticsWrapper,
+    item: &ShortItem,
+    user: &User,
+    config: &Config,
+) -> Result<ItemStats> {
+    let last_stats = &statistics.statistics_closed._48_hours;
+    let prices: Vec<f32> = last_stats.iter().map(|x| x.avg_price).collect();
+    let avg_price = match config.price_metric {
+        PriceMetric::Mean => prices.iter().sum::<f32>() / prices.len() as f32,
+        PriceMetric::Median => percentile(&prices, 50.0),
+        PriceMetric::QuickSell => percentile(&prices, config.quick_sell_percentile),
+    };
+    let volume: f32 = last_stats.iter().map(|x| x.volume).sum();
+    let min_price = last_stats.iter().map(|x| x.min_price).fold(f32::NAN, f32::min);
+    let max_price = last_stats.iter().map(|x| x.max_price).fold(f32::NAN, f32::max);
+
+    let needs_live = config.price_basis != PriceBasis::Closed || config.show_spread;
+    // the statistics only summarize the orders, the order book needs them one by one
+    let orders = if (needs_live && config.online_only) || config.show_order_book {
+        Some(user.get_item_orders(item).await?)
+    } else {
+        None
+    };
+    // they may have only been fetched for the order book
+    let online_orders = orders.as_deref().filter(|_| config.online_only);
+    let live = match (config.price_basis, online_orders) {
+        (PriceBasis::Closed, _) => None,
+        (_, Some(orders)) => online_sell_prices(orders),
+        (_, None) => Some(live_sell_prices(&statistics)),
+    };
+
+    let mut item_stats = ItemStats {
+        volume,
+        sales_per_day: volume / (STATS_WINDOW_HOURS / 24.0),
+        avg_price,
+        min_price,
+        max_price,
+        live_price: None,
+        best_sell: None,
+        best_buy: None,
+        order_book: orders.as_deref().filter(|_| config.show_order_book).map(order_book),
+        item: item.clone(),
+        slot: 0,
+        watched: false,
+        confidence: 1.0,
+        score: None,
+        rarity: None,
+        ducats: None,
+        my_listing: None,
+        set: None,
+    };
+
+    match (config.price_basis, live) {
+        (PriceBasis::Live, Some((avg, min, max))) => {
+            item_stats.avg_price = avg;
+            item_stats.min_price = min;
+            item_stats.max_price = max;
+        }
+        // there may be no sell orders in the live statistics
+        (PriceBasis::Both, Some((avg, _, _))) => item_stats.live_price = Some(avg).filter(|x| !x.is_nan()),
+        _ => {}
+    }
+
+    if config.show_spread {
+        let (best_sell, best_buy) = match online_orders {
+            Some(orders) => online_best_prices(orders),
+            None => live_best_prices(&statistics),
+        };
+        item_stats.best_sell = Some(best_sell).filter(|x| !x.is_nan());
+        item_stats.best_buy = Some(best_buy).filter(|x| !x.is_nan());
+    }
+
+    Ok(item_stats)
+}
+
+/// The `p`th percentile (0 to 100) of the values, interpolating between the
+/// two closest ones. NaN if there are none.
+pub fn percentile(values: &[f32], p: f32) -> f32 {
+    let mut sorted: Vec<f32> = values.iter().copied().filter(|x| !x.is_nan()).collect();
+    if sorted.is_empty() {
+        return f32::NAN;
+    }
+    sorted.sort_by(|a, b| a.total_cmp(b));
+
+    let rank = (p.clamp(0.0, 100.0) / 100.0) * (sorted.len() - 1) as f32;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f32)
+}
+
+/// Average, lowest and highest price of the sell orders of the last 48 hours
+fn live_sell_prices(statistics: &MarketStatisticsWrapper) -> (f32, f32, f32) {
+    let live_stats: Vec<_> = statistics
+        .statistics_live
+        ._48_hours
+        .iter()
+        .filter(|x| x.order_type == "sell")
+        .collect();
+
+    (
+        live_stats.iter().map(|x| x.avg_price).sum::<f32>() / live_stats.len() as f32,
+        live_stats.iter().map(|x| x.min_price).fold(f32::NAN, f32::min),
+        live_stats.iter().map(|x| x.max_price).fold(f32::NAN, f32::max),
+    )
+}
+
+/// Lowest sell and highest buy order of the most recent live statistics
+fn live_best_prices(statistics: &MarketStatisticsWrapper) -> (f32, f32) {
+    let latest = |order_type: &str| {
+        statistics
+            .statistics_live
+            ._48_hours
+            .iter()
+            .filter(|x| x.order_type == order_type)
+            .max_by(|a, b| a.datetime.cmp(&b.datetime))
+    };
+
+    (
+        latest("sell").map_or(f32::NAN, |x| x.min_price),
+        latest("buy").map_or(f32::NAN, |x| x.max_price),
+    )
+}
+
+/// Lowest sell and highest buy order of users that are online
+fn online_best_prices(orders: &[Order]) -> (f32, f32) {
+    let mut best_sell = f32::NAN;
+    let mut best_buy = f32::NAN;
+    for order in orders.iter().filter(|x| x.visible && x.user.status != "offline") {
+        let price = order.platinum as f32;
+        match order.order_type.as_str() {
+            "sell" => best_sell = best_sell.min(price),
+            "buy" => best_buy = best_buy.max(price),
+            _ => {}
+        }
+    }
+
+    (best_sell, best_buy)
+}
+
+/// Average, lowest and highest price of the current sell orders of sellers
+/// that are online, since offline listings often undercut unrealistically.
+/// `None` if no seller is online.
+fn online_sell_prices(orders: &[Order]) -> Option<(f32, f32, f32)> {
+    let prices: Vec<f32> = orders
+        .iter()
+        .filter(|x| x.order_type == "sell" && x.visible && x.user.status != "offline")
+        .map(|x| x.platinum as f32)
+        .collect();
+    if prices.is_empty() {
+        return None;
+    }
+
+    Some((
+        prices.iter().sum::<f32>() / prices.len() as f32,
+        prices.iter().copied().fold(f32::NAN, f32::min),
+        prices.iter().copied().fold(f32::NAN, f32::max),
+    ))
+}
+
+fn order_book(orders: &[Order]) -> OrderBook {
+    let online = || {
+        orders
+            .iter()
+            .filter(|x| x.visible && x.user.status != "offline")
+    };
+    let mut lowest_sells: Vec<f32> = online()
+        .filter(|x| x.order_type == "sell")
+        .map(|x| x.platinum as f32)
+        .collect();
+    lowest_sells.sort_by(|a, b| a.total_cmp(b));
+    lowest_sells.truncate(ORDER_BOOK_SELLS);
+
+    OrderBook {
+        lowest_sells,
+        highest_buy: online()
+            .filter(|x| x.order_type == "buy")
+            .map(|x| x.platinum as f32)
+            .reduce(f32::max),
+    }
+}
+
+/// The prices of the logged in user's visible sell orders, by item url name
+async fn get_my_listings(user: &User) -> Result<HashMap<String, f32>> {
+    let ingame_name = match user.get_profile().await?.ingame_name {
+        Some(x) => x,
+        None => anyhow::bail!("Not logged in"),
+    };
+    let orders = user.get_user_orders(&ingame_name).await?;
+
+    Ok(orders
+        .sell_orders
+        .into_iter()
+        .filter(|x| x.visible)
+        .map(|x| (x.item.url_name, x.platinum as f32))
+        .collect())
+}
+
+#[derive(Default)]
+struct ItemDetails {
+    rarity: Option<Rarity>,
+    ducats: Option<u16>,
+    set: Option<ItemSet>,
+}
+
+/// Looks up the relic rarity, ducat value and set of the item
+async fn get_item_details(item: &ShortItem, user: &User) -> Result<ItemDetails> {
+    let long_item = user.get_item(item).await?;
+
+    let mut details = long_item
+        .items_in_set
+        .iter()
+        .find(|x| x.url_name == item.url_name)
+        .map(|x| ItemDetails {
+            rarity: Rarity::from_api(&x.rarity),
+            ducats: Some(x.ducats),
+            set: None,
+        })
+        .unwrap_or_default();
+    // items that aren't part of a set have no set root
+    details.set = long_item
+        .items_in_set
+        .iter()
+        .find(|x| x.set_root)
+        .map(|root| ItemSet {
+            name: root.en.item_name.clone(),
+            parts: long_item.items_in_set.iter().filter(|x| !x.set_root).count(),
+        });
+
+    Ok(details)
+}
+
+/// Sorts the items from best to worst. Watchlisted items always rank above the
+/// rest, then items are ranked by their deal score if there is one, or their
+/// price otherwise. Items without a price always rank last.
+///
+/// With `by_ducat_ratio`, the items giving up the least platinum per ducat rank
+/// first instead, and items without a ducat value rank last.
+fn rank_items(items: &mut [ItemStats], by_ducat_ratio: bool) {
+    let rating = |item: &ItemStats| {
+        let rating = if by_ducat_ratio {
+            item.plat_per_ducat().map(|x| -x).unwrap_or(f32::NAN)
+        } else {
+            item.score.unwrap_or(item.avg_price)
+        };
+        if rating.is_nan() {
+            f32::NEG_INFINITY
+        } else {
+            rating
+        }
+    };
+
+    items.sort_by(|a, b| {
+        b.watched
+            .cmp(&a.watched)
+            .then_with(|| rating(b).total_cmp(&rating(a)))
+    });
+}
+
+/// Moves the parts of the same set next to each other, ranking the sets by
+/// the total price of their scanned parts. Sets with a watchlisted part still
+/// rank first, and the parts keep their ranking within the set. Items without
+/// a set count as a set of their own.
+fn group_by_set(items: &mut Vec<ItemStats>) {
+    // in the order of their best part
+    let mut groups: Vec<Vec<ItemStats>> = Vec::new();
+    for item in items.drain(..) {
+        let group = item.set.as_ref().and_then(|set| {
+            groups
+                .iter()
+                .position(|group| group[0].set.as_ref() == Some(set))
+        });
+        match group {
+            Some(idx) => groups[idx].push(item),
+            None => groups.push(vec![item]),
+        }
+    }
+
+    let rating = |group: &[ItemStats]| {
+        let total: f32 = group.iter().map(|x| x.avg_price).filter(|x| !x.is_nan()).sum();
+        (group.iter().any(|x| x.watched), total)
+    };
+    // stable, so sets of the same value keep the order of their best part
+    groups.sort_by(|a, b| {
+        let (a, b) = (rating(a), rating(b));
+        b.0.cmp(&a.0).then_with(|| b.1.total_cmp(&a.1))
+    });
+    items.extend(groups.into_iter().flatten());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::GenericImage;
+
+    fn order(order_type: &str, platinum: f64, status: &str) -> Order {
+        Order {
+            quantity: 1,
+            user: wfm_rs::response::OrderUser {
+                reputation: 0,
+                region: "en".to_string(),
+                ingame_name: String::new(),
+                status: status.to_string(),
+                id: String::new(),
+            },
+            visible: true,
+            last_update: String::new(),
+            creation_date: String::new(),
+            platinum,
+            platform: "pc".to_string(),
+            order_type: order_type.to_string(),
+            region: "en".to_string(),
+            id: String::new(),
+        }
+    }
+
+    fn item_stats(name: &str, avg_price: f32, watched: bool) -> ItemStats {
+        ItemStats {
+            watched,
+            ..ItemStats::test_default(name, avg_price)
+        }
+    }
+
+    fn names(items: &[ItemStats]) -> Vec<&str> {
+        items.iter().map(|x| x.item.item_name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_rank_items_nan() {
+        let mut items = vec![
+            item_stats("a", f32::NAN, false),
+            item_stats("b", 10.0, false),
+            item_stats("c", f32::NAN, false),
+            item_stats("d", 25.0, false),
+        ];
+        rank_items(&mut items, false);
+        assert_eq!(names(&items)[..2], ["d", "b"]);
+    }
+
+    #[test]
+    fn test_rank_items_watched() {
+        let mut items = vec![
+            item_stats("a", 50.0, false),
+            item_stats("b", f32::NAN, true),
+            item_stats("c", 5.0, true),
+        ];
+        rank_items(&mut items, false);
+        assert_eq!(names(&items), ["c", "b", "a"]);
+    }
+
+    #[test]
+    fn test_deal_score() {
+        let mut items = vec![
+            item_stats("expensive", 100.0, false),
+            item_stats("liquid", 80.0, false),
+        ];
+        items[0].volume = 2.0;
+        items[1].volume = 40.0;
+
+        let weights = DealScoreWeights {
+            price: 1.0,
+            volume: 1.0,
+            confidence: 0.0,
+        };
+        weights.apply(&mut items);
+        rank_items(&mut items, false);
+
+        assert_eq!(names(&items), ["liquid", "expensive"]);
+        assert!((items[0].score.unwrap() - 0.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_rank_items_ducat_ratio() {
+        let mut items = vec![
+            item_stats("a", 30.0, false),
+            item_stats("b", 10.0, false),
+            item_stats("c", 5.0, false),
+        ];
+        items[0].ducats = Some(100);
+        items[1].ducats = Some(15);
+        rank_items(&mut items, true);
+        assert_eq!(names(&items), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_live_best_prices() {
+        use wfm_rs::response::{MarketStatistics, MarketSubStatisticsLive};
+
+        let live = |datetime: &str, order_type: &str, min_price: f32, max_price: f32| {
+            MarketSubStatisticsLive {
+                datetime: datetime.to_string(),
+                volume: 1.0,
+                min_price,
+                max_price,
+                avg_price: (min_price + max_price) / 2.0,
+                wa_price: 0.0,
+                median: 0.0,
+                order_type: order_type.to_string(),
+                moving_avg: None,
+                id: String::new(),
+            }
+        };
+        let mut statistics = MarketStatisticsWrapper {
+            statistics_closed: MarketStatistics {
+                _48_hours: Vec::new(),
+                _90_days: Vec::new(),
+            },
+            statistics_live: MarketStatistics {
+                _48_hours: vec![
+                    live("2021-05-01T10:00:00", "sell", 20.0, 40.0),
+                    live("2021-05-01T11:00:00", "sell", 25.0, 50.0),
+                    live("2021-05-01T11:00:00", "buy", 10.0, 18.0),
+                    live("2021-05-01T10:00:00", "buy", 12.0, 22.0),
+                ],
+                _90_days: Vec::new(),
+            },
+        };
+        assert_eq!(live_best_prices(&statistics), (25.0, 18.0));
+
+        statistics.statistics_live._48_hours.retain(|x| x.order_type == "sell");
+        let (best_sell, best_buy) = live_best_prices(&statistics);
+        assert_eq!(best_sell, 25.0);
+        assert!(best_buy.is_nan());
+    }
+
+    #[test]
+    fn test_online_sell_prices() {
+        let mut orders = vec![
+            order("sell", 40.0, "ingame"),
+            order("sell", 50.0, "online"),
+            order("sell", 5.0, "offline"),
+            order("buy", 30.0, "ingame"),
+        ];
+        assert_eq!(online_sell_prices(&orders), Some((45.0, 40.0, 50.0)));
+
+        // nobody who is around to trade
+        orders.retain(|x| x.user.status == "offline" || x.order_type == "buy");
+        let mut hidden = order("sell", 42.0, "ingame");
+        hidden.visible = false;
+        orders.push(hidden);
+        assert_eq!(online_sell_prices(&orders), None);
+    }
+
+    #[test]
+    fn test_order_book() {
+        use wfm_rs::response::OrderUser;
+
+        let order = |order_type: &str, platinum: f64, status: &str| Order {
+            quantity: 1,
+            user: OrderUser {
+                reputation: 0,
+                region: "en".to_string(),
+                ingame_name: String::new(),
+                status: status.to_string(),
+                id: String::new(),
+            },
+            visible: true,
+            last_update: String::new(),
+            creation_date: String::new(),
+            platinum,
+            platform: "pc".to_string(),
+            order_type: order_type.to_string(),
+            region: "en".to_string(),
+            id: String::new(),
+        };
+        let mut orders: Vec<Order> = [48.0, 45.0, 60.0, 46.0, 52.0, 50.0]
+            .iter()
+            .map(|x| order("sell", *x, "ingame"))
+            .collect();
+        // a bait listing of someone who isn't around
+        orders.push(order("sell", 10.0, "offline"));
+        orders.push(order("buy", 38.0, "online"));
+        orders.push(order("buy", 40.0, "ingame"));
+
+        let book = order_book(&orders);
+        assert_eq!(book.lowest_sells, vec![45.0, 46.0, 48.0, 50.0, 52.0]);
+        assert_eq!(book.highest_buy, Some(40.0));
+
+        orders.retain(|x| x.order_type == "sell");
+        assert_eq!(order_book(&orders).highest_buy, None);
+    }
+
+    #[test]
+    fn test_group_by_set() {
+        let set = |name: &str| {
+            Some(ItemSet {
+                name: name.to_string(),
+                parts: 4,
+            })
+        };
+        let mut items = vec![
+            item_stats("a", 50.0, false),
+            item_stats("b", 40.0, false),
+            item_stats("c", 25.0, false),
+            item_stats("d", 20.0, false),
+            item_stats("e", f32::NAN, false),
+            item_stats("f", 10.0, false),
+        ];
+        items[0].set = set("X");
+        items[2].set = set("Y");
+        items[3].set = set("Y");
+        items[4].set = set("Y");
+        items[5].set = set("X");
+        group_by_set(&mut items);
+        assert_eq!(names(&items), ["a", "f", "c", "d", "e", "b"]);
+
+        items.iter_mut().find(|x| x.item.item_name == "f").unwrap().watched = true;
+        rank_items(&mut items, false);
+        group_by_set(&mut items);
+        assert_eq!(names(&items), ["f", "a", "c", "d", "e", "b"]);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let values = [9.0, 1.0, 5.0, 3.0, 7.0, 2.0, 8.0, 4.0, 6.0];
+        assert_eq!(percentile(&values, 0.0), 1.0);
+        assert_eq!(percentile(&values, 25.0), 3.0);
+        assert_eq!(percentile(&values, 50.0), 5.0);
+        assert_eq!(percentile(&values, 100.0), 9.0);
+
+        // between two values
+        assert!((percentile(&[10.0, 20.0, 30.0, 40.0], 25.0) - 17.5).abs() < 1e-6);
+        assert_eq!(percentile(&[42.0], 25.0), 42.0);
+        assert!(percentile(&[], 25.0).is_nan());
+    }
+
+    #[test]
+    fn test_frame_difference() {
+        let black = DynamicImage::new_rgb8(4, 4);
+        let mut half = DynamicImage::new_rgb8(4, 4);
+        for x in 0..2 {
+            for y in 0..4 {
+                half.put_pixel(x, y, image::Rgba([255, 255, 255, 255]));
+            }
+        }
+
+        assert_eq!(frame_difference(&black, &black), 0.0);
+        assert!((frame_difference(&black, &half) - 0.5).abs() < 1e-6);
+        assert_eq!(frame_difference(&black, &DynamicImage::new_rgb8(4, 5)), 1.0);
+    }
+    #[test]
+    fn test_frame_quality() {
+        let black = DynamicImage::new_rgb8(4, 4);
+        assert_eq!(frame_quality(&black), (0.0, 0.0));
+
+        let mut half = DynamicImage::new_rgb8(4, 4);
+        for x in 0..2 {
+            for y in 0..4 {
+                half.put_pixel(x, y, image::Rgba([255, 255, 255, 255]));
+            }
+        }
+        let (brightness, contrast) = frame_quality(&half);
+        assert!((brightness - 0.5).abs() < 1e-6);
+        assert!((contrast - 0.5).abs() < 1e-6);
+
+        let mut config = Config::default();
+        assert!(check_frame_quality(&config, &black).is_err());
+        assert!(check_frame_quality(&config, &half).is_ok());
+        config.min_brightness = 0.0;
+        config.min_contrast = 0.0;
+        assert!(check_frame_quality(&config, &black).is_ok());
+    }
+
+    #[test]
+    fn test_scan_slot_views() {
+        let slot = |slot, item: Option<&str>, empty| {
+            ScanResult::new(&SlotMatch {
+                slot,
+                item: item.map(|name| ItemStats::test_default(name, 0.0).item),
+                confidence: 1.0,
+                empty,
+                text: String::new(),
+                text_confidence: 1.0,
+                closest: None,
+                distance: 0,
+            })
+        };
+        let mut failed = slot(1, Some("b"), false);
+        failed.error = Some("500".to_string());
+        let scan = Scan {
+            timestamp: 0,
+            items: Vec::new(),
+            slots: vec![
+                slot(0, Some("a"), false),
+                failed,
+                slot(2, None, false),
+                slot(3, None, true),
+            ],
+            not_found: 0,
+        };
+
+        let indices =
+            |slots: Vec<&ScanResult>| slots.iter().map(|x| x.slot_index).collect::<Vec<_>>();
+        assert_eq!(indices(scan.unreadable().collect()), vec![2]);
+        assert_eq!(indices(scan.failed().collect()), vec![1]);
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::Result;
+use rodio::{
+    source::{SineWave, Source},
+    Decoder, OutputStream, Sink,
+};
+use std::fs::File;
+use std::io::{self, Write};
+use std::time::Duration;
+
+const BEEP_FREQUENCY: u32 = 587;
+const BEEP_DURATION: f32 = 0.10;
+const BEEP_SEPARATION: f32 = 0.05;
+const LENGTH_PER_BEEP: f32 = BEEP_DURATION + BEEP_SEPARATION;
+
+/// Plays the sound file, or beeps `times` times if there is none or it can't be played
+pub async fn alert(sound: Option<&str>, times: usize, fade_ms: u64) {
+    if let Some(path) = sound {
+        match play_sound(path).await {
+            Ok(()) => return,
+            Err(e) => eprintln!("Failed to play {}: {}", path, e),
+        }
+    }
+
+    let _ = beep(times, fade_ms).await;
+}
+
+/// Opens the audio output, then plays the scan sound (if any) and a beep,
+/// reporting how every step went
+pub async fn test(sound: Option<&str>, fade_ms: u64) -> Result<()> {
+    print!("Opening the default audio output...   ");
+    io::stdout().flush()?;
+    if let Err(e) = OutputStream::try_default() {
+        println!("failed!");
+        anyhow::bail!("No audio output available: {}", e);
+    }
+    println!("success!");
+
+    if let Some(path) = sound {
+        print!("Playing {}...   ", path);
+        io::stdout().flush()?;
+        match play_sound(path).await {
+            Ok(()) => println!("success!"),
+            Err(e) => println!("failed: {}", e),
+        }
+    }
+
+    print!("Beeping...   ");
+    io::stdout().flush()?;
+    beep(1, fade_ms).await?;
+    println!("success!");
+
+    Ok(())
+}
+
+async fn play_sound(path: &str) -> Result<()> {
+    let file = File::open(path)?;
+
+    // the output stream can't be moved between threads, so it lives on the blocking one
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        let (_stream, stream_handle) = OutputStream::try_default()?;
+        let sink = Sink::try_new(&stream_handle)?;
+        sink.append(Decoder::new(io::BufReader::new(file))?);
+        sink.sleep_until_end();
+
+        Ok(())
+    })
+    .await?
+}
+
+/// Beeps `times` times, fading every beep in and out over `fade_ms` so it
+/// doesn't click. The fades are part of the beep, so the timing stays the same.
+pub async fn beep(times: usize, fade_ms: u64) -> Result<()> {
+    let total_length = LENGTH_PER_BEEP * times as f32;
+
+    let (_stream, stream_handle) = OutputStream::try_default()?;
+    let sink = Sink::try_new(&stream_handle)?;
+
+    let sine = Envelope::new(
+        SineWave::new(BEEP_FREQUENCY).take_duration(Duration::from_secs_f32(BEEP_DURATION)),
+        Duration::from_secs_f32(BEEP_DURATION),
+        Duration::from_millis(fade_ms),
+    )
+    .amplify(0.1);
+    let mixed = sine.mix(SineWave::new(0).take_duration(Duration::from_secs_f32(LENGTH_PER_BEEP)));
+    let repeated = mixed
+        .repeat_infinite()
+        .take_duration(Duration::from_secs_f32(total_length));
+
+    sink.append(repeated);
+    tokio::time::sleep(Duration::from_secs_f32(total_length + BEEP_DURATION * 2.0)).await;
+
+    Ok(())
+}
+
+/// Linear fade-in over the first and fade-out over the last `fade` of a source
+/// that is `length` long
+#[derive(Clone)]
+struct Envelope<S> {
+    inner: S,
+    position: usize,
+    total: usize,
+    fade: usize,
+}
+
+impl<S: Source<Item = f32>> Envelope<S> {
+    fn new(inner: S, length: Duration, fade: Duration) -> Envelope<S> {
+        let samples_per_s = inner.sample_rate() as f32 * inner.channels() as f32;
+        let total = (length.as_secs_f32() * samples_per_s) as usize;
+        Envelope {
+            inner,
+            position: 0,
+            total,
+            // fading in and out can take up the whole source, but not more
+            fade: ((fade.as_secs_f32() * samples_per_s) as usize).min(total / 2),
+        }
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for Envelope<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.inner.next()?;
+        let gain = fade_gain(self.position, self.total, self.fade);
+        self.position += 1;
+        Some(sample * gain)
+    }
+}
+
+impl<S: Source<Item = f32>> Source for Envelope<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+/// Gain of the sample at `position` out of `total`, ramping up over the first
+/// `fade` samples and down over the last `fade` samples
+fn fade_gain(position: usize, total: usize, fade: usize) -> f32 {
+    if fade == 0 {
+        return 1.0;
+    }
+    let from_edge = position.min(total.saturating_sub(position + 1));
+    (from_edge as f32 / fade as f32).min(1.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fade_gain() {
+        assert_eq!(fade_gain(0, 100, 10), 0.0);
+        assert_eq!(fade_gain(5, 100, 10), 0.5);
+        assert_eq!(fade_gain(50, 100, 10), 1.0);
+        assert_eq!(fade_gain(94, 100, 10), 0.5);
+        assert_eq!(fade_gain(99, 100, 10), 0.0);
+        assert_eq!(fade_gain(0, 100, 0), 1.0);
+    }
+}
@@ -0,0 +1,120 @@
+/// Everything an output template can refer to as `{placeholder}`
+pub const PLACEHOLDERS: [&str; 16] = [
+    "name",
+    "price",
+    "basis",
+    "min_price",
+    "max_price",
+    "volume",
+    "sales_per_day",
+    "slot",
+    "confidence",
+    "score",
+    "rarity",
+    "live_price",
+    "ducats",
+    "plat_per_ducat",
+    "lowest_sells",
+    "highest_buy",
+];
+
+/// How a result line looks without `output_template`, apart from the parts
+/// that are only shown when there is something to show
+pub const DEFAULT_TEMPLATE: &str = "{name} | {price} platinum {basis} ({min_price}-{max_price}) | {volume} sold in the last 48 hours (~{sales_per_day}/day)";
+
+enum Part<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Splits the template into text and placeholders. `{{` and `}}` stand for
+/// literal braces, and a `{` without a closing brace is kept as it is.
+fn parse(template: &str) -> Vec<Part<'_>> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+
+    while !rest.is_empty() {
+        let brace = match rest.find(|c: char| c == '{' || c == '}') {
+            Some(x) => x,
+            None => {
+                parts.push(Part::Text(rest));
+                break;
+            }
+        };
+        parts.push(Part::Text(&rest[..brace]));
+        rest = &rest[brace..];
+
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            parts.push(Part::Text(&rest[..1]));
+            rest = &rest[2..];
+        } else if rest.starts_with('}') {
+            parts.push(Part::Text("}"));
+            rest = &rest[1..];
+        } else {
+            match rest.find('}') {
+                Some(end) => {
+                    parts.push(Part::Placeholder(&rest[1..end]));
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    parts.push(Part::Text(rest));
+                    break;
+                }
+            }
+        }
+    }
+
+    parts
+}
+
+/// The placeholders of the template that aren't in `PLACEHOLDERS`
+pub fn unknown_placeholders(template: &str) -> Vec<&str> {
+    parse(template)
+        .into_iter()
+        .filter_map(|part| match part {
+            Part::Placeholder(name) if !PLACEHOLDERS.contains(&name) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Fills in the placeholders of the template with `value`. Placeholders it has
+/// no value for are left empty.
+pub fn render(template: &str, value: impl Fn(&str) -> Option<String>) -> String {
+    parse(template)
+        .into_iter()
+        .map(|part| match part {
+            Part::Text(text) => text.to_string(),
+            Part::Placeholder(name) => value(name).unwrap_or_default(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let value = |name: &str| match name {
+            "name" => Some("Lex Prime Barrel".to_string()),
+            "price" => Some("12".to_string()),
+            _ => None,
+        };
+
+        assert_eq!(render("{name}: {price}p", value), "Lex Prime Barrel: 12p");
+        assert_eq!(render("{name} {ducats}!", value), "Lex Prime Barrel !");
+        assert_eq!(render("{{name}} {price}", value), "{name} 12");
+        assert_eq!(render("{price} {unclosed", value), "12 {unclosed");
+        assert_eq!(render("no placeholders", value), "no placeholders");
+    }
+
+    #[test]
+    fn test_unknown_placeholders() {
+        assert!(unknown_placeholders(DEFAULT_TEMPLATE).is_empty());
+        assert_eq!(
+            unknown_placeholders("{name} {prcie} {{volume}} {slot} {}"),
+            vec!["prcie", ""]
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use crate::util::thumbnail_path;
+use anyhow::Result;
+use std::fs;
+use wfm_rs::response::ShortItem;
+use wfm_rs::User;
+
+/// Largest payload kitty accepts in a single escape sequence
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+/// Ways of showing images inline that a terminal may support
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    Kitty,
+    Iterm,
+}
+
+/// Guesses the image protocol of the current terminal from its environment
+pub fn detect() -> Option<Protocol> {
+    let term = std::env::var("TERM").unwrap_or_default();
+    let term_program = std::env::var("TERM_PROGRAM").unwrap_or_default();
+
+    if term == "xterm-kitty" || std::env::var_os("KITTY_WINDOW_ID").is_some() {
+        Some(Protocol::Kitty)
+    } else if term_program == "iTerm.app" || term_program == "WezTerm" {
+        Some(Protocol::Iterm)
+    } else {
+        None
+    }
+}
+
+/// The thumbnail of the item, as PNG. Thumbnails don't change, so once
+/// fetched they are kept in the cache directory.
+pub async fn fetch(user: &User, item: &ShortItem) -> Result<Vec<u8>> {
+    let mut path = thumbnail_path()?;
+    path.push(item.thumb.replace('/', "_"));
+    if let Ok(png) = fs::read(&path) {
+        return Ok(png);
+    }
+
+    let png = user.get_asset(&item.thumb).await?;
+    if let Err(e) = fs::create_dir_all(thumbnail_path()?).and_then(|_| fs::write(&path, &png)) {
+        eprintln!("Failed to cache the thumbnail: {}", e);
+    }
+
+    Ok(png)
+}
+
+/// The escape sequence displaying the image two cells wide and one cell high
+pub fn escape(protocol: Protocol, png: &[u8]) -> String {
+    let data = base64::encode(png);
+
+    match protocol {
+        Protocol::Iterm => format!(
+            "\x1b]1337;File=inline=1;width=2;height=1;preserveAspectRatio=1:{}\x07",
+            data
+        ),
+        Protocol::Kitty => {
+            let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+            let mut result = String::new();
+            for (idx, chunk) in chunks.iter().enumerate() {
+                let more = if idx + 1 < chunks.len() { 1 } else { 0 };
+                let control = if idx == 0 {
+                    format!("a=T,f=100,c=2,r=1,m={}", more)
+                } else {
+                    format!("m={}", more)
+                };
+                // base64 is always valid utf-8
+                let chunk = std::str::from_utf8(chunk).unwrap();
+                result.push_str(&format!("\x1b_G{};{}\x1b\\", control, chunk));
+            }
+            result
+        }
+    }
+}
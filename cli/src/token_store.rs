@@ -0,0 +1,39 @@
+use anyhow::Result;
+
+/// Service the tokens are stored under, with the profile name as the user
+#[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+const SERVICE: &str = "wfm_cli";
+
+/// The token stored for the profile in the OS keyring, `None` if there is none
+#[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+pub fn load(profile: &str) -> Result<Option<String>> {
+    match keyring::Entry::new(SERVICE, profile).get_password() {
+        Ok(x) => Ok(Some(x)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Stores the token for the profile in the OS keyring, or removes it if it's empty
+#[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+pub fn store(profile: &str, token: &str) -> Result<()> {
+    let entry = keyring::Entry::new(SERVICE, profile);
+    if !token.is_empty() {
+        return Ok(entry.set_password(token)?);
+    }
+
+    match entry.delete_password() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+pub fn load(_profile: &str) -> Result<Option<String>> {
+    anyhow::bail!("There is no supported keyring on this platform")
+}
+
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+pub fn store(_profile: &str, _token: &str) -> Result<()> {
+    anyhow::bail!("There is no supported keyring on this platform")
+}
@@ -1,38 +1,366 @@
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
 use std::time::SystemTime;
 use anyhow::Result;
 use home;
+use num_format::{Grouping, Locale};
 use crate::{
     DATA_SCREENSHOT_DIR,
+    DATA_THUMBNAIL_DIR,
     DATA_PATH_SUFFIX,
     DATA_CONFIG_FILE,
+    DATA_CACHE_FILE,
+    DATA_LEGACY_CONFIG_FILE,
+    DATA_HISTORY_FILE,
+    DATA_MISSES_FILE,
+    DATA_PRICE_CACHE_FILE,
 };
 
-pub fn data_path() -> Result<PathBuf> {
-    let mut home_dir = match home::home_dir() {
+/// Overrides the data directory, which is in the home directory otherwise.
+/// Everything is kept in it then, like before the XDG directories were used.
+pub const DATA_DIR_ENV: &str = "WFM_CLI_DATA_DIR";
+/// Overrides the path of the settings file
+pub const CONFIG_FILE_ENV: &str = "WFM_CLI_CONFIG";
+/// Name of the data directory in `%APPDATA%`, which isn't hidden by a dot there
+#[cfg(windows)]
+const DATA_DIR_WINDOWS: &str = "wfm_cli/";
+/// Name of the directory in each of the XDG base directories
+#[cfg(all(unix, not(target_os = "macos")))]
+const XDG_DIR_NAME: &str = "wfm_cli";
+
+/// What kind of files a directory holds, in the XDG base directory sense
+#[derive(Clone, Copy)]
+enum Kind {
+    /// The settings
+    Config,
+    /// What can be fetched or extracted again, like prices and tessdata
+    Cache,
+    /// What can't, like the history and the login token
+    Data,
+}
+
+/// Where the files of the kind are kept: `WFM_CLI_DATA_DIR` if set, otherwise
+/// `%APPDATA%` on Windows, the XDG base directories on Linux and the home
+/// directory elsewhere, or the current directory if there is neither
+fn base_dir(kind: Kind) -> Result<PathBuf> {
+    if let Some(dir) = std::env::var_os(DATA_DIR_ENV) {
+        return Ok(PathBuf::from(dir));
+    }
+    if let Some(dir) = app_data_dir() {
+        return Ok(dir);
+    }
+    if let Some(dir) = xdg_dir(kind) {
+        return Ok(dir);
+    }
+
+    let mut base_dir = match home::home_dir() {
         Some(x) => x,
-        None => anyhow::bail!("Failed to find home directory!"),
+        None => match std::env::current_dir() {
+            Ok(x) => {
+                static WARNING: Once = Once::new();
+                WARNING.call_once(|| {
+                    eprintln!(
+                        "Failed to find the home directory, using {} instead (set {} to change this)",
+                        x.to_string_lossy(),
+                        DATA_DIR_ENV
+                    )
+                });
+                x
+            }
+            Err(e) => anyhow::bail!(
+                "Failed to find the home directory or current directory ({}), set {} to choose a data directory",
+                e,
+                DATA_DIR_ENV
+            ),
+        },
     };
 
-    home_dir.push(DATA_PATH_SUFFIX);
+    base_dir.push(DATA_PATH_SUFFIX);
+
+    Ok(base_dir)
+}
+
+/// Where Windows applications keep their data
+#[cfg(windows)]
+fn app_data_dir() -> Option<PathBuf> {
+    let mut dir = PathBuf::from(std::env::var_os("APPDATA")?);
+    dir.push(DATA_DIR_WINDOWS);
+    Some(dir)
+}
 
-    Ok(home_dir)
+#[cfg(not(windows))]
+fn app_data_dir() -> Option<PathBuf> {
+    None
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn xdg_dir(kind: Kind) -> Option<PathBuf> {
+    let (var, default) = match kind {
+        Kind::Config => ("XDG_CONFIG_HOME", ".config"),
+        Kind::Cache => ("XDG_CACHE_HOME", ".cache"),
+        Kind::Data => ("XDG_DATA_HOME", ".local/share"),
+    };
+    let mut dir = xdg_base_dir(
+        std::env::var_os(var).map(PathBuf::from),
+        home::home_dir(),
+        default,
+    )?;
+    dir.push(XDG_DIR_NAME);
+    Some(dir)
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn xdg_dir(_kind: Kind) -> Option<PathBuf> {
+    None
+}
+
+/// The XDG base directory set by its environment variable, or `default` in the
+/// home directory. Relative paths in the variable are invalid, so they're
+/// ignored like the spec says.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn xdg_base_dir(var: Option<PathBuf>, home: Option<PathBuf>, default: &str) -> Option<PathBuf> {
+    match var {
+        Some(dir) if dir.is_absolute() => Some(dir),
+        _ => home.map(|x| x.join(default)),
+    }
+}
+
+/// Where the history, the item list and the login token are kept
+pub fn data_path() -> Result<PathBuf> {
+    base_dir(Kind::Data)
+}
+
+/// Where files that can be fetched or extracted again are kept
+pub fn cache_dir() -> Result<PathBuf> {
+    base_dir(Kind::Cache)
 }
 
 pub fn screenshot_path() -> Result<PathBuf> {
+    let mut cache_dir = cache_dir()?;
+    cache_dir.push(DATA_SCREENSHOT_DIR);
+
+    Ok(cache_dir)
+}
+
+pub fn thumbnail_path() -> Result<PathBuf> {
+    let mut cache_dir = cache_dir()?;
+    cache_dir.push(DATA_THUMBNAIL_DIR);
+
+    Ok(cache_dir)
+}
+
+/// The settings file, `WFM_CLI_CONFIG` if set
+pub fn config_path() -> Result<PathBuf> {
+    if let Some(path) = std::env::var_os(CONFIG_FILE_ENV) {
+        return Ok(PathBuf::from(path));
+    }
+
+    let mut config_dir = base_dir(Kind::Config)?;
+    config_dir.push(DATA_CONFIG_FILE);
+
+    Ok(config_dir)
+}
+
+/// The token and item cache of the profile, or of the top level account
+pub fn cache_path(profile: Option<&str>) -> Result<PathBuf> {
     let mut data_path = data_path()?;
-    data_path.push(DATA_SCREENSHOT_DIR);
+    data_path.push(DATA_CACHE_FILE);
+    if let Some(name) = profile {
+        // cache.<profile>.json
+        data_path.set_extension(format!("{}.json", name));
+    }
 
     Ok(data_path)
 }
 
-pub fn config_path() -> Result<PathBuf> {
+/// The caches of every account that has one, see `cache_path`
+pub fn cache_paths() -> Result<Vec<PathBuf>> {
+    let data_path = data_path()?;
+    if !data_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    // cache.json itself, or cache.<profile>.json
+    let prefix = DATA_CACHE_FILE.trim_end_matches("json");
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(&data_path)? {
+        let path = entry?.path();
+        let name = match path.file_name() {
+            Some(x) => x.to_string_lossy().to_string(),
+            None => continue,
+        };
+        if name.starts_with(prefix) && name.ends_with(".json") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// The JSON file older versions kept the settings and the cache in, next to
+/// the settings file
+pub fn legacy_config_path() -> Result<PathBuf> {
+    Ok(config_path()?.with_file_name(DATA_LEGACY_CONFIG_FILE))
+}
+
+pub fn history_path() -> Result<PathBuf> {
     let mut data_path = data_path()?;
-    data_path.push(DATA_CONFIG_FILE);
+    data_path.push(DATA_HISTORY_FILE);
 
     Ok(data_path)
 }
 
+pub fn misses_path() -> Result<PathBuf> {
+    let mut data_path = data_path()?;
+    data_path.push(DATA_MISSES_FILE);
+
+    Ok(data_path)
+}
+
+pub fn price_cache_path() -> Result<PathBuf> {
+    let mut cache_dir = cache_dir()?;
+    cache_dir.push(DATA_PRICE_CACHE_FILE);
+
+    Ok(cache_dir)
+}
+
+/// Moves the files of `~/.wfm_cli`, where everything was kept before the XDG
+/// base directories were used, to where they belong now. Whatever can be
+/// extracted or fetched again (tessdata, screenshots) is left behind.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn migrate_legacy_dir() -> Result<()> {
+    if std::env::var_os(DATA_DIR_ENV).is_some() || std::env::var_os(CONFIG_FILE_ENV).is_some() {
+        return Ok(());
+    }
+    let legacy_dir = match home::home_dir() {
+        Some(x) => x.join(DATA_PATH_SUFFIX),
+        None => return Ok(()),
+    };
+    if !legacy_dir.is_dir() || config_path()?.exists() || legacy_config_path()?.exists() {
+        return Ok(());
+    }
+
+    let config_dir = base_dir(Kind::Config)?;
+    let data_dir = data_path()?;
+    let cache_dir = cache_dir()?;
+    let mut moved = 0;
+    for entry in fs::read_dir(&legacy_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let to_dir = if name == DATA_CONFIG_FILE || name == DATA_LEGACY_CONFIG_FILE {
+            &config_dir
+        } else if name == DATA_CACHE_FILE
+            || name.starts_with(DATA_HISTORY_FILE)
+            || name.starts_with(DATA_MISSES_FILE)
+        {
+            &data_dir
+        } else if name == DATA_PRICE_CACHE_FILE {
+            &cache_dir
+        } else {
+            continue;
+        };
+
+        fs::create_dir_all(to_dir)?;
+        let to = to_dir.join(&name);
+        // renaming fails across file systems
+        if fs::rename(entry.path(), &to).is_err() {
+            fs::copy(entry.path(), &to)?;
+            fs::remove_file(entry.path())?;
+        }
+        moved += 1;
+    }
+
+    if moved > 0 {
+        eprintln!(
+            "Moved {} file(s) from {} to {}, {} and {}, the rest of it can be removed",
+            moved,
+            legacy_dir.to_string_lossy(),
+            config_dir.to_string_lossy(),
+            data_dir.to_string_lossy(),
+            cache_dir.to_string_lossy()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub fn migrate_legacy_dir() -> Result<()> {
+    Ok(())
+}
+
+/// `path` with `.<n>` appended, where the n-th most recently rotated log is
+pub fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Moves the log at `path` to `<path>.1` once it is `max_bytes` large, after
+/// moving every older one up a number, and dropping any beyond `max_files`
+pub fn rotate_log(path: &Path, max_bytes: u64, max_files: usize) -> Result<()> {
+    match fs::metadata(path) {
+        Ok(x) if x.len() >= max_bytes => {}
+        _ => return Ok(()),
+    }
+
+    if max_files == 0 {
+        fs::remove_file(path)?;
+        return Ok(());
+    }
+
+    let oldest = rotated_path(path, max_files);
+    if oldest.exists() {
+        fs::remove_file(oldest)?;
+    }
+    for n in (1..max_files).rev() {
+        let rotated = rotated_path(path, n);
+        if rotated.exists() {
+            fs::rename(rotated, rotated_path(path, n + 1))?;
+        }
+    }
+    fs::rename(path, rotated_path(path, 1))?;
+
+    Ok(())
+}
+
+/// The path of a `file://` URI, with percent-encoded characters decoded
+#[cfg(any(target_os = "linux", test))]
+pub fn file_uri_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        let escaped = encoded
+            .get(i + 1..i + 3)
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| u8::from_str_radix(x, 16).ok());
+        match (encoded[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).ok().map(PathBuf::from)
+}
+
+/// Whether the file looks like a screenshot by its extension
+pub fn is_image_path(path: &Path) -> bool {
+    match path.extension().and_then(|x| x.to_str()) {
+        Some(ext) => ["png", "jpg", "jpeg"].iter().any(|x| ext.eq_ignore_ascii_case(x)),
+        None => false,
+    }
+}
+
 pub fn unix_timestamp() -> Result<u64> {
     Ok(SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs())
 }
@@ -40,4 +368,114 @@ pub fn unix_timestamp() -> Result<u64> {
 // https://stackoverflow.com/questions/34837011/how-to-clear-the-terminal-screen-in-rust-after-a-new-line-is-printed
 pub fn clear_terminal() {
     print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
-}
\ No newline at end of file
+}
+
+/// Formats a number with the decimal and thousands separators of `locale`,
+/// or as plain `1234.5` if there is none
+pub fn format_number(value: f32, decimals: usize, locale: Option<Locale>) -> String {
+    let plain = format!("{:.*}", decimals, value);
+    let locale = match locale {
+        Some(x) if value.is_finite() => x,
+        _ => return plain,
+    };
+
+    let (integer, fraction) = match plain.find('.') {
+        Some(idx) => (&plain[..idx], Some(&plain[idx + 1..])),
+        None => (plain.as_str(), None),
+    };
+    let (sign, digits) = match integer.strip_prefix('-') {
+        Some(digits) => (locale.minus_sign(), digits),
+        None => ("", integer),
+    };
+
+    let mut result = sign.to_string();
+    for (idx, digit) in digits.chars().enumerate() {
+        let remaining = digits.len() - idx;
+        if idx > 0 && remaining % 3 == 0 && locale.grouping() != Grouping::Posix {
+            result.push_str(locale.separator());
+        }
+        result.push(digit);
+    }
+
+    if let Some(fraction) = fraction {
+        result.push_str(locale.decimal());
+        result.push_str(fraction);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!("12345.5", format_number(12345.5, 1, None));
+        assert_eq!("12,345.5", format_number(12345.5, 1, Some(Locale::en)));
+        assert_eq!("12.345,5", format_number(12345.5, 1, Some(Locale::de)));
+        assert_eq!("345", format_number(345.0, 0, Some(Locale::de)));
+        assert_eq!("-1,000", format_number(-1000.0, 0, Some(Locale::en)));
+        assert_eq!("NaN", format_number(f32::NAN, 1, Some(Locale::en)));
+    }
+
+    #[test]
+    fn test_rotate_log() {
+        let dir = std::env::temp_dir().join(format!("wfm_cli_rotate_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("test.log");
+
+        fs::write(&log, "a").unwrap();
+        rotate_log(&log, 10, 2).unwrap();
+        assert!(log.exists());
+
+        for content in ["first", "second", "third"].iter() {
+            fs::write(&log, content).unwrap();
+            rotate_log(&log, 1, 2).unwrap();
+            assert!(!log.exists());
+        }
+        assert_eq!(fs::read_to_string(rotated_path(&log, 1)).unwrap(), "third");
+        assert_eq!(fs::read_to_string(rotated_path(&log, 2)).unwrap(), "second");
+        assert!(!rotated_path(&log, 3).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn test_xdg_base_dir() {
+        let home = Some(PathBuf::from("/home/tenno"));
+        assert_eq!(
+            xdg_base_dir(None, home.clone(), ".config"),
+            Some(PathBuf::from("/home/tenno/.config"))
+        );
+        assert_eq!(
+            xdg_base_dir(Some(PathBuf::from("/tmp/config")), home.clone(), ".config"),
+            Some(PathBuf::from("/tmp/config"))
+        );
+        assert_eq!(
+            xdg_base_dir(Some(PathBuf::from("relative")), home, ".cache"),
+            Some(PathBuf::from("/home/tenno/.cache"))
+        );
+        assert_eq!(xdg_base_dir(None, None, ".cache"), None);
+    }
+
+    #[test]
+    fn test_is_image_path() {
+        assert!(is_image_path(Path::new("Warframe0001.png")));
+        assert!(is_image_path(Path::new("/steam/screenshots/20230101_1.JPG")));
+        assert!(is_image_path(Path::new("shot.jpeg")));
+        assert!(!is_image_path(Path::new("Warframe0001.png.tmp")));
+        assert!(!is_image_path(Path::new("screenshots")));
+    }
+
+    #[test]
+    fn test_file_uri_path() {
+        assert_eq!(
+            file_uri_path("file:///home/tenno/Pictures/Screenshot%20from%202021.png"),
+            Some(PathBuf::from("/home/tenno/Pictures/Screenshot from 2021.png"))
+        );
+        assert_eq!(file_uri_path("file:///tmp/100%.png"), Some(PathBuf::from("/tmp/100%.png")));
+        assert_eq!(file_uri_path("https://warframe.market"), None);
+    }
+}
@@ -10,19 +10,60 @@ pub mod traits;
 pub use model::User;
 
 pub(crate) const BASE_URL: &str = "https://api.warframe.market/v1";
+/// Where the images the API refers to, such as item thumbnails, are served from
+pub(crate) const ASSETS_URL: &str = "https://warframe.market/static/assets/";
 
-pub(crate) async fn get_endpoint<T: DeserializeOwned>(client: &reqwest::Client, url: &str, jwt: &str) -> Result<T> {
+/// How many requests a [`User`] has in flight at most, unless changed
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 4;
+
+/// Platforms supported by warframe.market, as passed to the API
+pub const PLATFORMS: [&str; 4] = ["pc", "xbox", "ps4", "switch"];
+
+/// Languages warframe.market translates item names to, as passed to the API
+pub const LANGUAGES: [&str; 11] = ["en", "ru", "ko", "de", "fr", "pt", "zh-hans", "zh-hant", "es", "it", "pl"];
+
+/// Returned (wrapped in an `anyhow::Error`) when the API responds with a non-success status code
+#[derive(Debug)]
+pub struct StatusError {
+    pub status: u16,
+}
+
+impl std::fmt::Display for StatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "API responded with status {}", self.status)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// Returns the status code of the API response that caused this error, if any
+pub fn error_status(err: &anyhow::Error) -> Option<u16> {
+    err.downcast_ref::<StatusError>().map(|e| e.status)
+}
+
+pub(crate) async fn get_endpoint<T: DeserializeOwned>(client: &reqwest::Client, base_url: &str, url: &str, jwt: &str, platform: &str, language: Option<&str>) -> Result<T> {
     let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert("authorization", jwt.parse()?);
+    // the item list and statistics are public, so they're fetched without logging in
+    if !jwt.is_empty() {
+        headers.insert("authorization", jwt.parse()?);
+    }
+    headers.insert("platform", platform.parse()?);
+    if let Some(language) = language {
+        headers.insert("language", language.parse()?);
+    }
     headers.insert("Content-Type", "application/json".parse()?);
 
-    let raw = client.get(format!("{}{}", BASE_URL, url))
+    let response = client.get(format!("{}{}", base_url, url))
         .headers(headers)
         .send()
-        .await?
-        .text()
         .await?;
 
+    if !response.status().is_success() {
+        return Err(StatusError { status: response.status().as_u16() }.into());
+    }
+
+    let raw = response.text().await?;
+
     let base: response::ResponseWrapper<T> = serde_json::from_str(&raw)?;
 
     Ok(base.payload)
@@ -1,10 +1,22 @@
 use reqwest;
 use anyhow::Result;
-use crate::{ request, response, traits, BASE_URL, get_endpoint };
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use crate::{ request, response, traits, ASSETS_URL, BASE_URL, DEFAULT_MAX_CONCURRENT_REQUESTS, StatusError, get_endpoint };
 
+/// Cloning is cheap, and clones share the connection pool and request limit
+#[derive(Clone)]
 pub struct User {
     client: reqwest::Client,
     jwt_token: String,
+    platform: String,
+    /// Language item names are returned in, English if `None`
+    language: Option<String>,
+    /// Limits the number of requests in flight, shared by everything using this user
+    limiter: Arc<Semaphore>,
+    /// Server with the same API that is asked before warframe.market itself
+    mirror: Option<String>,
 }
 
 impl User {
@@ -38,6 +50,10 @@ impl User {
         return Ok(User {
             client: req_client,
             jwt_token: jwt_token.to_str()?.to_string(),
+            platform: platform.to_string(),
+            language: None,
+            limiter: Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENT_REQUESTS)),
+            mirror: None,
         });
     }
 
@@ -45,26 +61,108 @@ impl User {
         User {
             jwt_token: jwt_token.to_string(),
             client: reqwest::Client::new(),
+            platform: "pc".to_string(),
+            language: None,
+            limiter: Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENT_REQUESTS)),
+            mirror: None,
         }
     }
 
+    /// Makes all further requests for the given platform, see [`crate::PLATFORMS`]
+    pub fn with_platform(mut self, platform: &str) -> User {
+        self.platform = platform.to_string();
+        self
+    }
+
+    /// Returns item names in the given language from now on, see [`crate::LANGUAGES`]
+    pub fn with_language(mut self, language: &str) -> User {
+        self.language = Some(language.to_string());
+        self
+    }
+
+    /// Allows at most `max` requests to be in flight at once, so bursts of lookups don't trip the rate limit
+    pub fn with_max_concurrent_requests(mut self, max: usize) -> User {
+        self.limiter = Arc::new(Semaphore::new(max.max(1)));
+        self
+    }
+
+    /// Fetches the item list and statistics from a (e.g. shared, caching) server serving the same API
+    /// under `base_url`, falling back to warframe.market if it fails. Everything else, and anything
+    /// needing the login token, always uses warframe.market.
+    pub fn with_mirror(mut self, base_url: &str) -> User {
+        self.mirror = Some(base_url.trim_end_matches('/').to_string());
+        self
+    }
+
     pub fn _jwt_token(&self) -> String {
         self.jwt_token.clone()
     }
 
     pub async fn get_items(&self) -> Result<Vec<response::ShortItem>> {
-        Ok(get_endpoint::<response::Items>(&self.client, "/items", &self.jwt_token).await?.items)
+        Ok(self.get::<response::Items>("/items").await?.items)
     }
 
     pub async fn get_item<T: traits::ItemUrl>(&self, item: &T) -> Result<response::LongItem> {
-        get_endpoint::<response::LongItem>(&self.client, &format!("/items/{}", item.item_url()), &self.jwt_token).await
+        self.get::<response::LongItem>(&format!("/items/{}", item.item_url())).await
     }
 
     pub async fn get_item_orders<T: traits::ItemUrl>(&self, item: &T) -> Result<Vec<response::Order>> {
-        Ok(get_endpoint::<response::Orders>(&self.client, &format!("/items/{}/orders", item.item_url()), &self.jwt_token).await?.orders)
+        Ok(self.get::<response::Orders>(&format!("/items/{}/orders", item.item_url())).await?.orders)
     }
 
     pub async fn get_item_market_statistics<T: traits::ItemUrl>(&self, item: &T) -> Result<response::MarketStatisticsWrapper> {
-        get_endpoint(&self.client, &format!("/items/{}/statistics", item.item_url()), &self.jwt_token).await
+        self.get(&format!("/items/{}/statistics", item.item_url())).await
+    }
+
+    /// Statistics of every item, in the same order. warframe.market has no
+    /// endpoint for several items at once, so they are requested concurrently,
+    /// as many at a time as the request limit allows
+    pub async fn get_items_market_statistics<T: traits::ItemUrl>(&self, items: &[T]) -> Vec<Result<response::MarketStatisticsWrapper>> {
+        let requests = items
+            .iter()
+            .map(|item| self.get_item_market_statistics(item));
+
+        futures::future::join_all(requests).await
+    }
+
+    /// The profile of the logged in user
+    pub async fn get_profile(&self) -> Result<response::Profile> {
+        Ok(self.get::<response::ProfileWrapper>("/profile").await?.profile)
+    }
+
+    /// The orders a user has listed, by their in-game name
+    pub async fn get_user_orders(&self, ingame_name: &str) -> Result<response::ProfileOrders> {
+        self.get(&format!("/profile/{}/orders", ingame_name)).await
+    }
+
+    /// Downloads one of the images the API refers to, such as the `thumb` of an item
+    pub async fn get_asset(&self, path: &str) -> Result<Vec<u8>> {
+        let _permit = self.limiter.acquire().await?;
+
+        let response = self.client.get(format!("{}{}", ASSETS_URL, path)).send().await?;
+        if !response.status().is_success() {
+            return Err(StatusError { status: response.status().as_u16() }.into());
+        }
+
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let _permit = self.limiter.acquire().await?;
+
+        if let Some(mirror) = self.mirror.as_ref().filter(|_| is_mirrored(url)) {
+            // the mirror is a third party, so it never gets to see the login token
+            match get_endpoint(&self.client, mirror, url, "", &self.platform, self.language.as_deref()).await {
+                Ok(x) => return Ok(x),
+                Err(e) => eprintln!("Mirror {} failed for {}, asking warframe.market instead: {}", mirror, url, e),
+            }
+        }
+
+        get_endpoint(&self.client, BASE_URL, url, &self.jwt_token, &self.platform, self.language.as_deref()).await
     }
-}
\ No newline at end of file
+}
+
+/// Whether a request may go to the mirror, which only serves the public item list and statistics
+fn is_mirrored(url: &str) -> bool {
+    url == "/items" || (url.starts_with("/items/") && url.ends_with("/statistics"))
+}
@@ -155,6 +155,48 @@ pub struct OrderUser {
     // missing: avatar, last_seen
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProfileWrapper {
+    pub profile: Profile,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Profile {
+    pub id: String,
+    /// Missing when the token isn't logged in
+    pub ingame_name: Option<String>,
+    pub anonymous: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProfileOrders {
+    pub sell_orders: Vec<ProfileOrder>,
+    pub buy_orders: Vec<ProfileOrder>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProfileOrder {
+    pub quantity: u16,
+    pub visible: bool,
+    /// Sometimes this is a floating-point number
+    pub platinum: f64,
+    pub order_type: String,
+    pub item: ProfileOrderItem,
+    pub id: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProfileOrderItem {
+    pub id: String,
+    pub url_name: String,
+}
+
+impl ItemUrl for ProfileOrderItem {
+    fn item_url(&self) -> &str {
+        self.url_name.as_str()
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct MarketStatisticsWrapper {
     pub statistics_closed: MarketStatistics<MarketSubStatisticsClosed>,